@@ -1,7 +1,83 @@
 // TODO This is a tiny amount of javascript because I'm not sure how to serve
 // another rust wasm js file...
 self.addEventListener('push', function(event) {
-    const promiseChain = self.registration.showNotification(event.data.text());
+    let payload;
+    try {
+        payload = event.data.json();
+    } catch (error) {
+        // Plain text from before payloads were structured
+        payload = { title: event.data.text() };
+    }
+    const options = { body: payload.body, data: payload };
+    if (payload.game) {
+        // A newer notification about a game replaces the one already showing
+        options.tag = 'game-' + payload.game;
+        options.renotify = true;
+    }
 
-    event.waitUntil(promiseChain);
+    event.waitUntil(self.registration.showNotification(payload.title, options));
+});
+
+self.addEventListener('notificationclick', function(event) {
+    event.notification.close();
+    const game = event.notification.data && event.notification.data.game;
+    const url = new URL(game ? '/ui/game/' + game : '/', self.location.origin).href;
+
+    event.waitUntil(self.clients.matchAll({ type: 'window', includeUncontrolled: true }).then(function(windows) {
+        for (const client of windows) {
+            if (client.url === url && 'focus' in client) {
+                return client.focus();
+            }
+        }
+        return self.clients.openWindow(url);
+    }));
+});
+
+// Everything below keeps the app loadable without a connection. Game data is
+// cached by the app itself, so rpc calls always go straight to the network.
+const CACHE = 'duck-chess-v1';
+// Every page renders the same app shell, so one cached page can stand in for
+// any route.
+const APP_SHELL = '/';
+
+self.addEventListener('install', function(event) {
+    event.waitUntil(self.skipWaiting());
+});
+
+self.addEventListener('activate', function(event) {
+    event.waitUntil(caches.keys().then(function(keys) {
+        return Promise.all(keys
+            .filter(function(key) { return key !== CACHE; })
+            .map(function(key) { return caches.delete(key); }));
+    }).then(function() {
+        return self.clients.claim();
+    }));
+});
+
+function remember(key, response) {
+    if (response.ok) {
+        const copy = response.clone();
+        caches.open(CACHE).then(function(cache) { cache.put(key, copy); });
+    }
+    return response;
+}
+
+self.addEventListener('fetch', function(event) {
+    const request = event.request;
+    const url = new URL(request.url);
+    if (request.method !== 'GET' || url.origin !== self.location.origin || url.pathname.startsWith('/rpc/')) {
+        return;
+    }
+
+    if (request.mode === 'navigate') {
+        event.respondWith(fetch(request)
+            .then(function(response) { return remember(APP_SHELL, response); })
+            .catch(function() { return caches.match(APP_SHELL); }));
+    } else {
+        const fresh = fetch(request).then(function(response) { return remember(request, response); });
+        event.waitUntil(fresh.catch(function() {}));
+        event.respondWith(caches.match(request).then(function(cached) {
+            return cached || fresh;
+        }));
+    }
 });
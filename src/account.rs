@@ -0,0 +1,594 @@
+use web_sys::window;
+
+use account::{
+    MAX_API_TOKENS, MAX_NAME, MAX_PASSWORD, MAX_WEBHOOKS, MIN_PASSWORD, RECOVERY_CODES, TotpSetup,
+};
+
+use crate::{
+    email::Email,
+    mainmenu::{ago, now},
+    notification::Notifications,
+    prelude::*,
+    route::Route,
+};
+
+#[component]
+pub fn Account() -> Element {
+    let player: Player = use_context();
+
+    rsx! {
+        div {
+            class: "profile",
+            div {
+                class: "header",
+                h1 { "Account" }
+                div {
+                    class: "buttonMenu",
+                    Link { to: Route::MainMenu {}, "Back" }
+                }
+            }
+            ChangeName { name: player.name.clone() }
+            ChangePassword {}
+            Email {}
+            Notifications {}
+            Webhooks {}
+            ApiTokens {}
+            TwoFactor {}
+            Passkeys {}
+            Devices {}
+            DeleteAccount {}
+        }
+    }
+}
+
+#[component]
+fn ChangeName(name: String) -> Element {
+    let mut name = use_signal(|| name);
+    let mut password = use_signal(String::new);
+    let mut status = use_signal(|| None::<String>);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Username" }
+            label {
+                "New username: "
+                input {
+                    maxlength: "{MAX_NAME}",
+                    value: "{name}",
+                    oninput: move |evt| name.set(evt.value()),
+                }
+            }
+            label {
+                "Password: "
+                input {
+                    "type": "password",
+                    value: "{password}",
+                    oninput: move |evt| password.set(evt.value()),
+                }
+            }
+            button {
+                onclick: move |_| async move {
+                    match crate::rpc::change_name_rpc(name(), password()).await {
+                        // Everything on the page was drawn with the old name
+                        Ok(_) => window().unwrap().location().reload().unwrap(),
+                        Err(err) => status.set(Some(err.to_string())),
+                    }
+                },
+                "Change username"
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn ChangePassword() -> Element {
+    let mut current = use_signal(String::new);
+    let mut new = use_signal(String::new);
+    let mut confirm = use_signal(String::new);
+    let mut status = use_signal(|| None::<String>);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Password" }
+            label {
+                "Current password: "
+                input {
+                    "type": "password",
+                    value: "{current}",
+                    oninput: move |evt| current.set(evt.value()),
+                }
+            }
+            label {
+                "New password: "
+                input {
+                    "type": "password",
+                    minlength: "{MIN_PASSWORD}",
+                    maxlength: "{MAX_PASSWORD}",
+                    value: "{new}",
+                    oninput: move |evt| new.set(evt.value()),
+                }
+            }
+            label {
+                "Confirm new password: "
+                input {
+                    "type": "password",
+                    value: "{confirm}",
+                    oninput: move |evt| confirm.set(evt.value()),
+                }
+            }
+            div { "Passwords need between {MIN_PASSWORD} and {MAX_PASSWORD} characters." }
+            button {
+                onclick: move |_| async move {
+                    if new() != confirm() {
+                        status.set(Some("The new passwords don't match".into()));
+                        return;
+                    }
+                    match crate::rpc::change_password_rpc(current(), new()).await {
+                        Ok(()) => {
+                            current.set(String::new());
+                            new.set(String::new());
+                            confirm.set(String::new());
+                            status.set(Some("Password changed".into()));
+                        }
+                        Err(err) => status.set(Some(err.to_string())),
+                    }
+                },
+                "Change password"
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn TwoFactor() -> Element {
+    let mut enabled = use_resource(crate::rpc::fetch_two_factor_rpc);
+    let mut password = use_signal(String::new);
+    let mut code = use_signal(String::new);
+    let mut setup = use_signal(|| None::<TotpSetup>);
+    let mut recovery = use_signal(Vec::<String>::new);
+    let mut status = use_signal(|| None::<String>);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Two-factor authentication" }
+            if !recovery().is_empty() {
+                div {
+                    "Two-factor authentication is on. Keep these recovery codes somewhere safe, each one logs in once without your app. They won't be shown again."
+                }
+                ul {
+                    for code in recovery() {
+                        li { code { "{code}" } }
+                    }
+                }
+            } else if let Some(TotpSetup { uri, secret }) = setup() {
+                div {
+                    "Add this account to your authenticator app by opening "
+                    a { href: "{uri}", "this link" }
+                    " on your phone or entering the key "
+                    code { "{secret}" }
+                    ", then type the code it shows. You'll also get {RECOVERY_CODES} recovery codes for if you lose your phone."
+                }
+                label {
+                    "Code: "
+                    input {
+                        autocomplete: "one-time-code",
+                        value: "{code}",
+                        oninput: move |evt| code.set(evt.value()),
+                    }
+                }
+                button {
+                    onclick: move |_| async move {
+                        match crate::rpc::confirm_two_factor_rpc(code()).await {
+                            Ok(codes) => {
+                                setup.set(None);
+                                status.set(None);
+                                recovery.set(codes);
+                                enabled.restart();
+                            }
+                            Err(err) => status.set(Some(err.to_string())),
+                        }
+                    },
+                    "Turn on"
+                }
+            } else {
+                match enabled() {
+                    Some(Ok(on)) => rsx! {
+                        div {
+                            if on {
+                                "Logging in needs a code from your authenticator app."
+                            } else {
+                                "Logging in only needs your password."
+                            }
+                        }
+                        label {
+                            "Password: "
+                            input {
+                                "type": "password",
+                                value: "{password}",
+                                oninput: move |evt| password.set(evt.value()),
+                            }
+                        }
+                        if on {
+                            button {
+                                onclick: move |_| async move {
+                                    match crate::rpc::disable_two_factor_rpc(password()).await {
+                                        Ok(()) => {
+                                            password.set(String::new());
+                                            status.set(Some("Two-factor authentication is off".into()));
+                                            enabled.restart();
+                                        }
+                                        Err(err) => status.set(Some(err.to_string())),
+                                    }
+                                },
+                                "Turn off"
+                            }
+                        } else {
+                            button {
+                                onclick: move |_| async move {
+                                    match crate::rpc::start_two_factor_rpc(password()).await {
+                                        Ok(totp) => {
+                                            password.set(String::new());
+                                            status.set(None);
+                                            setup.set(Some(totp));
+                                        }
+                                        Err(err) => status.set(Some(err.to_string())),
+                                    }
+                                },
+                                "Set up"
+                            }
+                        }
+                    },
+                    Some(Err(err)) => rsx! { div { class: "conflict", "{err}" } },
+                    None => rsx! { "Loading..." },
+                }
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn Passkeys() -> Element {
+    let mut passkeys = use_resource(crate::rpc::fetch_passkeys);
+    let mut name = use_signal(|| "My phone".to_string());
+    let mut password = use_signal(String::new);
+    let mut status = use_signal(|| None::<String>);
+    let now = now();
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Passkeys" }
+            div { "Passkeys log in with your phone's or computer's lock screen instead of a password." }
+            match passkeys() {
+                Some(Ok(list)) => rsx! {
+                    for passkey in list {
+                        div {
+                            class: "device",
+                            div { "{passkey.name}, added {ago(now, passkey.created)}" }
+                            button {
+                                onclick: move |_| {
+                                    let id = passkey.id.clone();
+                                    async move {
+                                        match crate::rpc::remove_passkey_rpc(id).await {
+                                            Ok(()) => passkeys.restart(),
+                                            Err(err) => status.set(Some(err.to_string())),
+                                        }
+                                    }
+                                },
+                                "Remove"
+                            }
+                        }
+                    }
+                },
+                Some(Err(err)) => rsx! { div { class: "conflict", "{err}" } },
+                None => rsx! { "Loading..." },
+            }
+            label {
+                "Name: "
+                input {
+                    value: "{name}",
+                    oninput: move |evt| name.set(evt.value()),
+                }
+            }
+            label {
+                "Password: "
+                input {
+                    "type": "password",
+                    value: "{password}",
+                    oninput: move |evt| password.set(evt.value()),
+                }
+            }
+            button {
+                onclick: move |_| async move {
+                    match crate::passkeys::register(name(), password()).await {
+                        Ok(()) => {
+                            password.set(String::new());
+                            status.set(None);
+                            passkeys.restart();
+                        }
+                        Err(err) => status.set(Some(err)),
+                    }
+                },
+                "Add a passkey"
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn Webhooks() -> Element {
+    let mut hooks = use_resource(crate::rpc::fetch_webhooks);
+    let mut url = use_signal(String::new);
+    let mut status = use_signal(|| None::<String>);
+    let now = now();
+    let full = matches!(&*hooks.read(), Some(Ok(list)) if list.len() >= MAX_WEBHOOKS);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Webhooks" }
+            div {
+                "Game starts, turns, chat messages and results in your games are POSTed as JSON to each webhook. "
+                "Requests are signed with an HMAC-SHA256 of the X-Duck-Chess-Timestamp header, a dot and the body, keyed by the secret, in the X-Duck-Chess-Signature header."
+            }
+            match hooks() {
+                Some(Ok(list)) => rsx! {
+                    for hook in list {
+                        div {
+                            class: "device",
+                            div { "{hook.url}, added {ago(now, hook.created)}" }
+                            div { "Secret: " code { "{hook.secret}" } }
+                            for delivery in hook.deliveries {
+                                div {
+                                    match delivery.error {
+                                        Some(error) => rsx! { "✗ {delivery.event} {ago(now, delivery.time)}, {delivery.attempts} tries: {error}" },
+                                        None => rsx! { "✓ {delivery.event} {ago(now, delivery.time)}" },
+                                    }
+                                }
+                            }
+                            button {
+                                onclick: move |_| {
+                                    let id = hook.id.clone();
+                                    async move {
+                                        match crate::rpc::remove_webhook_rpc(id).await {
+                                            Ok(()) => hooks.restart(),
+                                            Err(err) => status.set(Some(err.to_string())),
+                                        }
+                                    }
+                                },
+                                "Remove"
+                            }
+                        }
+                    }
+                },
+                Some(Err(err)) => rsx! { div { class: "conflict", "{err}" } },
+                None => rsx! { "Loading..." },
+            }
+            if !full {
+                label {
+                    "URL: "
+                    input {
+                        r#type: "url",
+                        value: "{url}",
+                        oninput: move |evt| url.set(evt.value()),
+                    }
+                }
+                button {
+                    onclick: move |_| async move {
+                        match crate::rpc::add_webhook_rpc(url()).await {
+                            Ok(()) => {
+                                status.set(None);
+                                url.set(String::new());
+                                hooks.restart();
+                            }
+                            Err(err) => status.set(Some(err.to_string())),
+                        }
+                    },
+                    "Add a webhook"
+                }
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn ApiTokens() -> Element {
+    let mut tokens = use_resource(crate::rpc::fetch_api_tokens);
+    let mut name = use_signal(|| "My bot".to_string());
+    let mut created = use_signal(|| None::<String>);
+    let mut status = use_signal(|| None::<String>);
+    let now = now();
+    let full = matches!(&*tokens.read(), Some(Ok(list)) if list.len() >= MAX_API_TOKENS);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "API tokens" }
+            div {
+                "Programs can play as you through the bot API by sending a token in an "
+                code { "Authorization: Bearer" }
+                " header. Anyone with a token can play your games, so keep it secret."
+            }
+            match tokens() {
+                Some(Ok(list)) => rsx! {
+                    for token in list {
+                        div {
+                            class: "device",
+                            div { "{token.name}, created {ago(now, token.created)}" }
+                            button {
+                                onclick: move |_| {
+                                    let id = token.id.clone();
+                                    async move {
+                                        match crate::rpc::revoke_api_token(id).await {
+                                            Ok(()) => tokens.restart(),
+                                            Err(err) => status.set(Some(err.to_string())),
+                                        }
+                                    }
+                                },
+                                "Revoke"
+                            }
+                        }
+                    }
+                },
+                Some(Err(err)) => rsx! { div { class: "conflict", "{err}" } },
+                None => rsx! { "Loading..." },
+            }
+            if let Some(token) = created() {
+                div {
+                    "Your new token, which won't be shown again: "
+                    code { "{token}" }
+                }
+            }
+            if !full {
+                label {
+                    "Name: "
+                    input {
+                        value: "{name}",
+                        maxlength: "{MAX_NAME}",
+                        oninput: move |evt| name.set(evt.value()),
+                    }
+                }
+                button {
+                    onclick: move |_| async move {
+                        match crate::rpc::create_api_token(name()).await {
+                            Ok(token) => {
+                                status.set(None);
+                                created.set(Some(token));
+                                tokens.restart();
+                            }
+                            Err(err) => status.set(Some(err.to_string())),
+                        }
+                    },
+                    "Create a token"
+                }
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn Devices() -> Element {
+    let mut sessions = use_resource(crate::rpc::fetch_sessions);
+    let mut error = use_signal(|| None::<String>);
+    let now = now();
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Devices" }
+            match sessions() {
+                Some(Ok(sessions_list)) => rsx! {
+                    for session in sessions_list {
+                        div {
+                            class: "device",
+                            div {
+                                if session.current {
+                                    b { "This device" }
+                                    " · "
+                                }
+                                "Logged in {ago(now, session.created)}, last used {ago(now, session.last_used)}"
+                                if session.push { ", notifications on" } else { ", notifications off" }
+                            }
+                            if session.push {
+                                button {
+                                    onclick: move |_| async move {
+                                        match crate::rpc::disable_device_push_rpc(session.id.to_hex()).await {
+                                            Ok(()) => sessions.restart(),
+                                            Err(err) => error.set(Some(err.to_string())),
+                                        }
+                                    },
+                                    "Turn off notifications"
+                                }
+                            }
+                            if !session.current {
+                                button {
+                                    onclick: move |_| async move {
+                                        match crate::rpc::revoke_session_rpc(session.id.to_hex()).await {
+                                            Ok(()) => sessions.restart(),
+                                            Err(err) => error.set(Some(err.to_string())),
+                                        }
+                                    },
+                                    "Log out"
+                                }
+                            }
+                        }
+                    }
+                },
+                Some(Err(err)) => rsx! { div { class: "conflict", "{err}" } },
+                None => rsx! { "Loading..." },
+            }
+            if let Some(error) = error() {
+                div { class: "conflict", "{error}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn DeleteAccount() -> Element {
+    let mut password = use_signal(String::new);
+    let mut confirming = use_signal(|| false);
+    let mut error = use_signal(|| None::<String>);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Delete account" }
+            div {
+                "Your games stay around for your opponents, but your name is removed from them. This can't be undone."
+            }
+            label {
+                "Password: "
+                input {
+                    "type": "password",
+                    value: "{password}",
+                    oninput: move |evt| password.set(evt.value()),
+                }
+            }
+            if confirming() {
+                button {
+                    onclick: move |_| async move {
+                        match crate::rpc::delete_account_rpc(password()).await {
+                            Ok(()) => {
+                                crate::offline::forget();
+                                window().unwrap().location().reload().unwrap();
+                            }
+                            Err(err) => {
+                                confirming.set(false);
+                                error.set(Some(err.to_string()));
+                            }
+                        }
+                    },
+                    "Yes, delete my account"
+                }
+                button { onclick: move |_| confirming.set(false), "Cancel" }
+            } else {
+                button { onclick: move |_| confirming.set(true), "Delete account" }
+            }
+            if let Some(error) = error() {
+                div { class: "conflict", "{error}" }
+            }
+        }
+    }
+}
@@ -237,9 +237,7 @@ fn update<Board: ChessBoard>(
                     game.write().apply_duck(loc);
                     let some_turn = WithId::new(id, Board::wrap_turn(turn.t));
                     game.write().turns.push(turn.t);
-                    spawn(async move {
-                        crate::rpc::submit_turn_rpc(some_turn).await.unwrap();
-                    });
+                    spawn(crate::offline::submit_turn(some_turn));
                 })
                 .on_complete_callback(|| {
                     clear_style("moving_piece");
@@ -17,7 +17,7 @@ use modifiers::*;
 use reversable::*;
 use transition::*;
 
-pub use grid::Select;
+pub use grid::{FOLDER, Select};
 pub use menu::DrawMenuBoard;
 
 pub use crate::{prelude::*, route::Route};
@@ -136,6 +136,17 @@ pub fn some_game_preview(id: String, game: &Game) -> Element {
     }
 }
 
+pub fn request_preview(id: String, request: &GameRequest) -> Element {
+    match request.game_type {
+        game::GameTypes::Square => {
+            game_preview::<Board>(id, PlayerColor::None, Board::static_default())
+        }
+        game::GameTypes::Hex => {
+            game_preview::<Hexboard>(id, PlayerColor::None, Hexboard::static_default())
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BoardId {
     pub id: String,
@@ -4,7 +4,7 @@ use web_sys::HtmlElement;
 
 use super::*;
 
-static FOLDER: Asset = asset!("/assets/", AssetOptions::folder());
+pub static FOLDER: Asset = asset!("/assets/", AssetOptions::folder());
 
 #[component]
 pub fn DrawBlock<Loc: Gridable>(
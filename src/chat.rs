@@ -0,0 +1,155 @@
+use chat::{ChatMessage, MAX_MESSAGE};
+
+use crate::prelude::*;
+
+/// A collapsible chat between the two players of a game.
+#[component]
+pub fn Chat(id: String, opponent: Player) -> Element {
+    if !use_features().chat {
+        return rsx! {};
+    }
+    rsx! {
+        ChatBox { id, opponent }
+    }
+}
+
+#[component]
+fn ChatBox(id: String, opponent: Player) -> Element {
+    let player: Player = use_context();
+    let mut messages = use_signal(Vec::<ChatMessage>::new);
+    let mut draft = use_signal(String::new);
+    let mut error = use_signal(|| None::<String>);
+    let mut settings =
+        use_resource(|| async { crate::rpc::fetch_chat_settings().await.unwrap_or_default() });
+
+    use_future({
+        let id = id.clone();
+        move || {
+            let id = id.clone();
+            async move {
+                if let Ok(mut stream) = crate::rpc::chat_events(id).await {
+                    while let Some(Ok(message)) = stream.next().await {
+                        messages.write().push(message);
+                    }
+                }
+            }
+        }
+    });
+
+    let settings_value = settings.value()().unwrap_or_default();
+    let muted = opponent
+        .id
+        .is_some_and(|id| settings_value.muted.contains(&id));
+    let notify = settings_value.notify;
+    let opponent_id = opponent.id.map(|id| id.to_string()).unwrap_or_default();
+
+    let send = {
+        let id = id.clone();
+        move || {
+            let id = id.clone();
+            async move {
+                let text = draft();
+                if text.trim().is_empty() {
+                    return;
+                }
+                match crate::rpc::send_chat(id, text).await {
+                    Ok(()) => {
+                        draft.set(String::new());
+                        error.set(None);
+                    }
+                    Err(err) => error.set(Some(err.to_string())),
+                }
+            }
+        }
+    };
+    let send_on_enter = send.clone();
+
+    rsx! {
+        details {
+            class: "chat",
+            summary { "Chat with {opponent.name}" }
+            div {
+                class: "chatMessages",
+                for message in messages().into_iter().filter(|message| !muted || message.author.id != opponent.id) {
+                    div {
+                        class: if message.author.id == player.id { "chatMessage mine" } else { "chatMessage" },
+                        span { "{message.text}" }
+                        if message.author.id != player.id {
+                            button {
+                                class: "report",
+                                title: "Report this message",
+                                onclick: move |_| {
+                                    let message_id = message.id.map(|id| id.to_string()).unwrap_or_default();
+                                    async move {
+                                        match crate::rpc::report_chat(message_id).await {
+                                            Ok(()) => error.set(Some("Thanks, the message was reported.".into())),
+                                            Err(err) => error.set(Some(err.to_string())),
+                                        }
+                                    }
+                                },
+                                "Report"
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(error) = error() {
+                div { class: "chatNotice", "{error}" }
+            }
+            div {
+                class: "chatInput",
+                input {
+                    value: "{draft}",
+                    maxlength: "{MAX_MESSAGE}",
+                    placeholder: "Say something",
+                    oninput: move |evt| draft.set(evt.value()),
+                    onkeydown: move |evt| {
+                        let send = send_on_enter.clone();
+                        async move {
+                            if evt.key() == Key::Enter {
+                                send().await;
+                            }
+                        }
+                    },
+                }
+                button {
+                    onclick: move |_| {
+                        let send = send.clone();
+                        async move { send().await }
+                    },
+                    "Send"
+                }
+            }
+            div {
+                class: "chatOptions",
+                label {
+                    input {
+                        "type": "checkbox",
+                        checked: notify,
+                        onchange: move |evt| async move {
+                            if crate::rpc::set_chat_notify(evt.checked()).await.is_ok() {
+                                settings.restart();
+                            }
+                        },
+                    }
+                    "Notify me about messages"
+                }
+                label {
+                    input {
+                        "type": "checkbox",
+                        checked: muted,
+                        onchange: move |evt| {
+                            let opponent_id = opponent_id.clone();
+                            async move {
+                                if crate::rpc::set_chat_muted(opponent_id, evt.checked()).await.is_ok() {
+                                    settings.restart();
+                                }
+                            }
+                        },
+                    }
+                    "Mute {opponent.name}"
+                }
+            }
+        }
+    }
+}
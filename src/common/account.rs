@@ -0,0 +1,241 @@
+use bson::oid::ObjectId;
+
+use super::Player;
+use serde::{Deserialize, Serialize};
+
+/// Enforced by the server whenever a password is set
+pub const MIN_PASSWORD: usize = 8;
+pub const MAX_PASSWORD: usize = 128;
+pub const MAX_NAME: usize = 32;
+/// The longest address SMTP allows
+pub const MAX_EMAIL: usize = 254;
+
+/// One of the devices a player is logged in on
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionInfo {
+    pub id: ObjectId,
+    pub created: u64,
+    pub last_used: u64,
+    /// Whether notifications are sent to this device
+    pub push: bool,
+    /// Whether this is the device asking
+    pub current: bool,
+}
+
+/// How many recovery codes a player gets when they turn on two-factor authentication
+pub const RECOVERY_CODES: usize = 10;
+
+/// A second factor for logging in, from an authenticator app. Only ever read by the server.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct TwoFactor {
+    /// Base32 TOTP secret
+    pub secret: String,
+    /// Stays off until the player shows their app has the secret
+    pub enabled: bool,
+    /// Hashes of the codes that log in without the app, each one usable once
+    pub recovery: Vec<String>,
+    /// The time step of the last code from the app that worked, so a code can't be used twice
+    #[serde(default)]
+    pub last_step: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum LoginStep {
+    LoggedIn(Player),
+    /// The password was right, now send it again along with a code
+    NeedsCode,
+}
+
+/// What an authenticator app needs to start making codes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TotpSetup {
+    /// otpauth:// URI, the same thing QR codes for authenticator apps hold
+    pub uri: String,
+    /// The secret on its own for typing in by hand
+    pub secret: String,
+}
+
+/// A WebAuthn credential a player can log in with instead of their password. Only ever read by
+/// the server.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct Passkey {
+    /// Base64url credential id, which the browser uses to find the key
+    pub id: String,
+    /// Base64url SEC1 encoding of the P-256 public key
+    pub public_key: String,
+    /// Authenticators count up each time they sign, so a count going backwards means a cloned key
+    pub sign_count: u32,
+    pub name: String,
+    pub created: u64,
+}
+
+/// What the account page shows about a passkey
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PasskeyInfo {
+    pub id: String,
+    pub name: String,
+    pub created: u64,
+}
+
+/// Everything the browser needs to make or use a passkey besides fixed settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PasskeyChallenge {
+    pub challenge: Vec<u8>,
+    pub rp_id: String,
+    pub user_id: Vec<u8>,
+    pub user_name: String,
+    /// Passkeys the player already has. Registering skips these and logging in picks from them.
+    pub credentials: Vec<Vec<u8>>,
+}
+
+/// A new credential from `navigator.credentials.create`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PasskeyRegistration {
+    pub name: String,
+    pub client_data: Vec<u8>,
+    pub attestation_object: Vec<u8>,
+}
+
+/// A signed challenge from `navigator.credentials.get`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PasskeyAssertion {
+    /// Who's logging in
+    pub name: String,
+    pub credential: Vec<u8>,
+    pub client_data: Vec<u8>,
+    pub authenticator_data: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// A player's email address. Only ever read by the server.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct EmailSettings {
+    pub address: String,
+    /// Password resets only go to addresses the player has shown are theirs
+    pub verified: bool,
+    /// Whether they want a daily email listing the games waiting on their move
+    pub digest: bool,
+    /// The link that proves the address is theirs
+    pub verify: Option<EmailToken>,
+    /// The link that sets a new password
+    pub reset: Option<EmailToken>,
+}
+
+/// A link sent by email. Like session tokens, only a hash of the secret is kept.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct EmailToken {
+    pub hash: String,
+    pub expires: u64,
+}
+
+/// The features a server has on top of playing games. Ratings, tournaments and chat need storage
+/// only MongoDB has, so they're off with the other backends.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct Features {
+    pub ratings: bool,
+    pub tournaments: bool,
+    pub chat: bool,
+}
+
+/// What the account page shows about email
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmailStatus {
+    /// Whether the server can send email at all
+    pub available: bool,
+    pub address: Option<String>,
+    pub verified: bool,
+    pub digest: bool,
+}
+
+/// Which notifications a player gets, on every device they turned them on for
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// Someone joined one of the player's games, or a tournament paired them
+    pub game_start: bool,
+    pub turn: bool,
+    pub game_end: bool,
+    pub chat: bool,
+    /// Reminders that time is running out to move
+    pub deadlines: bool,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            game_start: true,
+            turn: true,
+            game_end: true,
+            chat: true,
+            deadlines: true,
+            quiet_hours: None,
+        }
+    }
+}
+
+/// A stretch of each day without notifications, in minutes after the player's midnight. It
+/// wraps past midnight when it ends before it starts.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuietHours {
+    pub start: u16,
+    pub end: u16,
+    /// Minutes ahead of UTC, as the player's browser reported when they were set
+    pub utc_offset: i32,
+}
+
+/// Most webhooks a player can have, each of which gets every event
+pub const MAX_WEBHOOKS: usize = 5;
+
+/// A URL the server POSTs a player's game events to. Only ever read by the server.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    /// Key for the HMAC-SHA256 signature on each request, so the receiver knows it came from us
+    pub secret: String,
+    pub created: u64,
+}
+
+/// One attempt at getting an event to a webhook, retries included
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookDelivery {
+    pub time: u64,
+    pub event: String,
+    pub game: String,
+    pub attempts: u32,
+    /// Why the last attempt failed, if it did
+    pub error: Option<String>,
+}
+
+/// What the account page shows about a webhook
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookInfo {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    pub created: u64,
+    /// Newest first, and only since the server last started
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+/// Most API tokens a player can have at once
+pub const MAX_API_TOKENS: usize = 10;
+
+/// Lets a program use the bot API as the player. Only ever read by the server.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    /// Hash of the secret half of the token, which is only shown once
+    pub hash: String,
+    pub created: u64,
+}
+
+/// What the account page shows about an API token
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiTokenInfo {
+    pub id: String,
+    pub name: String,
+    pub created: u64,
+}
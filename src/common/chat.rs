@@ -0,0 +1,35 @@
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use super::Player;
+
+/// Longest message we'll accept, in characters
+pub const MAX_MESSAGE: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChatMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    #[serde(default)]
+    pub id: Option<ObjectId>,
+    pub game: ObjectId,
+    pub author: Player,
+    pub text: String,
+    pub time: u64,
+}
+
+/// How a player wants chat to behave across all of their games.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct ChatSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    #[serde(default)]
+    pub id: Option<ObjectId>,
+    pub player: ObjectId,
+    /// Whether new messages should send a push notification
+    #[serde(default)]
+    pub notify: bool,
+    /// Players whose messages are hidden
+    #[serde(default)]
+    pub muted: Vec<ObjectId>,
+}
@@ -11,6 +11,6 @@ pub enum GameEvents {
     },
     NewState {
         id: String,
-        game: AnyGame,
+        game: Box<AnyGame>,
     },
 }
@@ -14,6 +14,9 @@ pub struct Game {
     pub joiner: Player,
     pub some_game: SomeGame,
     pub maker_color: Color,
+    /// Set once the game is over so results can be queried without looking at the board
+    #[serde(default)]
+    pub winner: Option<Color>,
 }
 
 impl Game {
@@ -39,11 +42,26 @@ impl Game {
         result
     }
 
-    pub fn game_over(&self) -> Option<Color> {
+    pub fn opponent(&self, player: &Player) -> &Player {
+        if self.maker.id == player.id {
+            &self.joiner
+        } else {
+            &self.maker
+        }
+    }
+
+    pub fn game_type(&self) -> GameTypes {
         match &self.some_game {
+            SomeGame::Square(_) => GameTypes::Square,
+            SomeGame::Hex(_) => GameTypes::Hex,
+        }
+    }
+
+    pub fn game_over(&self) -> Option<Color> {
+        self.winner.or_else(|| match &self.some_game {
             SomeGame::Square(game) => game.game_over(),
             SomeGame::Hex(game) => game.game_over(),
-        }
+        })
     }
 
     pub fn turn(&self) -> Color {
@@ -61,11 +79,7 @@ impl Game {
         if !self.is_player_turn(player) {
             bail!("Not your turn")
         }
-        match (&mut self.some_game, turn) {
-            (SomeGame::Square(game), SomeTurn::Square(turn)) => game.apply_turn(turn),
-            (SomeGame::Hex(game), SomeTurn::Hex(turn)) => game.apply_turn(turn),
-            _ => bail!("Turn and game did not match!"),
-        }
+        self.some_game.apply_turn(turn)
     }
 }
 
@@ -82,6 +96,14 @@ pub enum SomeLoc {
 }
 
 impl SomeGame {
+    pub fn apply_turn(&mut self, turn: SomeTurn) -> Result<()> {
+        match (self, turn) {
+            (SomeGame::Square(game), SomeTurn::Square(turn)) => game.apply_turn(turn),
+            (SomeGame::Hex(game), SomeTurn::Hex(turn)) => game.apply_turn(turn),
+            _ => bail!("Turn and game did not match!"),
+        }
+    }
+
     pub fn iter(&self) -> Box<dyn Iterator<Item = (SomeLoc, Square)> + '_> {
         match self {
             Self::Square(game) => Box::new(
@@ -116,19 +138,24 @@ pub enum SomeBoard<'a> {
     Hex(&'a Hexboard),
 }
 
-#[derive(Copy, Debug, Clone, Serialize, Deserialize)]
+#[derive(Copy, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum GameTypes {
     Square,
     Hex,
 }
 
 impl GameTypes {
+    pub fn all() -> [GameTypes; 2] {
+        [GameTypes::Square, GameTypes::Hex]
+    }
+
     pub fn mk_game(&self, maker: Player, joiner: Player, maker_color: Color) -> Game {
         match self {
             GameTypes::Square => Game {
                 maker,
                 joiner,
                 maker_color,
+                winner: None,
                 some_game: SomeGame::Square(GameRaw {
                     board: Board::default(),
                     turns: Vec::new(),
@@ -139,6 +166,7 @@ impl GameTypes {
                 maker,
                 joiner,
                 maker_color,
+                winner: None,
                 some_game: SomeGame::Hex(GameRaw {
                     board: Hexboard::default(),
                     turns: Vec::new(),
@@ -10,17 +10,24 @@ use std::{
     ops::{Add, Deref, DerefMut, Mul},
 };
 
+pub mod account;
 pub mod board;
 mod boardfocus;
+pub mod chat;
 pub mod chessboard;
 pub mod events;
 pub mod game;
 pub mod hexboard;
 pub mod hexgame;
 pub mod menuboard;
+pub mod profile;
+pub mod rating;
+pub mod seek;
+pub mod tournament;
 
 pub use board::Board;
 pub use game::Game;
+pub use rating::{LeaderboardEntry, Rating, RatingRecord};
 
 use crate::board::game::SomeLoc;
 
@@ -63,6 +70,21 @@ pub struct PasswordPlayer {
     pub password: String,
     #[serde(flatten)]
     pub player: Player,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_factor: Option<account::TwoFactor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passkeys: Vec<account::Passkey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<account::EmailSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<account::NotificationSettings>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<account::Webhook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub api_tokens: Vec<account::ApiToken>,
+    /// Goes up by one on every save so two writers can't silently overwrite each other
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Deref for PasswordPlayer {
@@ -107,6 +129,111 @@ pub struct AnyGame {
     #[serde(default)]
     pub id: Option<ObjectId>,
     pub game: GameOrRequest,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Casual games don't change anyone's rating
+    #[serde(default)]
+    pub rated: bool,
+    #[serde(default)]
+    pub time_control: TimeControl,
+    /// The tournament this game was paired for, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub tournament: Option<ObjectId>,
+    /// Goes up by one on every save so two writers can't silently overwrite each other
+    #[serde(default)]
+    pub version: u64,
+    #[serde(default)]
+    pub times: GameTimes,
+}
+
+impl AnyGame {
+    /// The last time anything happened to the game. Games from before times were recorded fall
+    /// back to when their id was made.
+    pub fn last_activity(&self) -> u64 {
+        let times = &self.times;
+        [
+            Some(times.created),
+            times.started,
+            times.ended,
+            times.turns.last().copied(),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .filter(|time| *time > 0)
+        .or_else(|| Some(self.id?.timestamp().timestamp_millis() as u64 / 1000))
+        .unwrap_or_default()
+    }
+}
+
+/// When things happened to a game in seconds since the Unix epoch, as recorded by the server.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct GameTimes {
+    pub created: u64,
+    /// When someone joined
+    pub started: Option<u64>,
+    pub ended: Option<u64>,
+    /// When each turn was made, in order
+    pub turns: Vec<u64>,
+}
+
+/// Who besides the two players is allowed to watch a game.
+#[derive(Debug, Hash, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum Visibility {
+    #[default]
+    Private,
+    /// Anyone with the link can watch
+    Unlisted,
+    /// Anyone can watch and the game is listed for spectators
+    Public,
+}
+
+impl Visibility {
+    pub fn all() -> [Visibility; 3] {
+        [
+            Visibility::Private,
+            Visibility::Unlisted,
+            Visibility::Public,
+        ]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Visibility::Private => "Only the players",
+            Visibility::Unlisted => "Anyone with the link",
+            Visibility::Public => "Everyone",
+        }
+    }
+}
+
+/// How long each player gets to make a move. Games are played by correspondence, so this is
+/// measured in days.
+#[derive(Debug, Hash, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum TimeControl {
+    #[default]
+    Unlimited,
+    DaysPerMove(u32),
+}
+
+impl TimeControl {
+    pub fn all() -> [TimeControl; 4] {
+        [
+            TimeControl::Unlimited,
+            TimeControl::DaysPerMove(1),
+            TimeControl::DaysPerMove(3),
+            TimeControl::DaysPerMove(7),
+        ]
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            TimeControl::Unlimited => "No time limit".into(),
+            TimeControl::DaysPerMove(1) => "1 day per move".into(),
+            TimeControl::DaysPerMove(days) => format!("{days} days per move"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
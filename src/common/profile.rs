@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use super::game::GameTypes;
+use super::{AnyGame, Color, Player};
+
+/// Win, draw, and loss counts over some set of completed games.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Record {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/// A player's record in one variant while playing one color.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VariantRecord {
+    pub game_type: GameTypes,
+    pub color: Color,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+/// A player's record against one particular opponent.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HeadToHead {
+    pub opponent: Player,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlayerProfile {
+    pub player: Player,
+    pub records: Vec<VariantRecord>,
+    pub head_to_head: Vec<HeadToHead>,
+}
+
+impl PlayerProfile {
+    pub fn record(&self, game_type: GameTypes, color: Color) -> Record {
+        self.records
+            .iter()
+            .find(|record| record.game_type == game_type && record.color == color)
+            .map_or_else(Record::default, |record| record.record)
+    }
+}
+
+/// One page of a player's completed games, newest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GamePage {
+    pub games: Vec<AnyGame>,
+    pub more: bool,
+}
@@ -0,0 +1,54 @@
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use super::Player;
+use super::game::GameTypes;
+
+/// A Glicko-2 rating, kept in the familiar 1500-centered scale.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: 1500.0,
+            deviation: 350.0,
+            volatility: 0.06,
+        }
+    }
+}
+
+/// A player's rating in one variant along with every rated game that changed it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RatingRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    #[serde(default)]
+    pub id: Option<ObjectId>,
+    pub player: ObjectId,
+    pub game_type: GameTypes,
+    pub current: Rating,
+    pub history: Vec<RatingChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RatingChange {
+    pub game: ObjectId,
+    pub before: Rating,
+    pub after: Rating,
+    pub time: u64,
+}
+
+/// One row of a variant's leaderboard.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LeaderboardEntry {
+    pub player: Player,
+    pub rating: Rating,
+    /// How many rated games the player has finished in this variant
+    pub games: u32,
+    pub last_played: u64,
+}
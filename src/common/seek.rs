@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+use super::TimeControl;
+use super::game::GameTypes;
+
+/// What a player in the play now queue is willing to play.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Seek {
+    pub game_type: GameTypes,
+    pub time_control: TimeControl,
+    pub rated: bool,
+    /// How far from their own rating an opponent can be, or any opponent if there's no limit
+    pub range: Option<u32>,
+}
+
+impl Seek {
+    pub fn ranges() -> [Option<u32>; 4] {
+        [Some(100), Some(200), Some(400), None]
+    }
+
+    pub fn range_label(range: Option<u32>) -> String {
+        match range {
+            Some(range) => format!("±{range}"),
+            None => "Anyone".into(),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use super::game::GameTypes;
+use super::{Color, Player, TimeControl};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TournamentFormat {
+    /// Everyone plays everyone else once
+    RoundRobin,
+    /// Players with similar scores are paired each round for a fixed number of rounds
+    Swiss { rounds: u32 },
+}
+
+impl TournamentFormat {
+    pub fn label(&self) -> String {
+        match self {
+            TournamentFormat::RoundRobin => "Round robin".into(),
+            TournamentFormat::Swiss { rounds } => format!("Swiss, {rounds} rounds"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TournamentStatus {
+    Registering,
+    Running,
+    Finished,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Tournament {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    #[serde(default)]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub organizer: Player,
+    pub game_type: GameTypes,
+    pub time_control: TimeControl,
+    pub format: TournamentFormat,
+    pub rated: bool,
+    pub status: TournamentStatus,
+    pub players: Vec<Player>,
+    /// Round robins schedule every round when they start. Swiss rounds are added one at a time.
+    pub rounds: Vec<Round>,
+    /// The round currently being played
+    pub current_round: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Round {
+    pub pairings: Vec<Pairing>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Pairing {
+    pub white: Player,
+    /// Nobody is left to play the white player, so they get a bye
+    pub black: Option<Player>,
+    pub game: Option<ObjectId>,
+    pub finished: bool,
+    pub winner: Option<Color>,
+}
+
+impl Pairing {
+    /// The points each side earned, once the game is over
+    fn scores(&self) -> Option<(f64, f64)> {
+        if !self.finished {
+            return None;
+        }
+        Some(match self.winner {
+            Some(Color::White) => (1.0, 0.0),
+            Some(Color::Black) => (0.0, 1.0),
+            None => (0.5, 0.5),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    pub player: Player,
+    pub score: f64,
+    /// The sum of every opponent's score
+    pub buchholz: f64,
+    /// The sum of the scores of opponents beaten plus half of those drawn
+    pub sonneborn_berger: f64,
+    pub wins: u32,
+}
+
+impl Tournament {
+    pub fn scores(&self) -> HashMap<ObjectId, f64> {
+        let mut scores: HashMap<ObjectId, f64> = self
+            .players
+            .iter()
+            .filter_map(|player| Some((player.id?, 0.0)))
+            .collect();
+        for pairing in self.rounds.iter().flat_map(|round| &round.pairings) {
+            if let Some((white, black)) = pairing.scores() {
+                *scores.entry(pairing.white.id.unwrap()).or_default() += white;
+                if let Some(player) = &pairing.black {
+                    *scores.entry(player.id.unwrap()).or_default() += black;
+                }
+            }
+        }
+        scores
+    }
+
+    /// Players sorted by score, with Swiss tournaments breaking ties by Buchholz first since
+    /// not everyone faced the same opponents.
+    pub fn standings(&self) -> Vec<Standing> {
+        let scores = self.scores();
+        let mut standings: Vec<Standing> = self
+            .players
+            .iter()
+            .map(|player| {
+                let id = player.id.unwrap();
+                let mut standing = Standing {
+                    player: player.clone(),
+                    score: scores[&id],
+                    buchholz: 0.0,
+                    sonneborn_berger: 0.0,
+                    wins: 0,
+                };
+                for pairing in self.rounds.iter().flat_map(|round| &round.pairings) {
+                    let (Some((white, black)), Some(opponent)) = (pairing.scores(), &pairing.black)
+                    else {
+                        continue;
+                    };
+                    let (mine, opponent) = if pairing.white.id == Some(id) {
+                        (white, opponent.id.unwrap())
+                    } else if opponent.id == Some(id) {
+                        (black, pairing.white.id.unwrap())
+                    } else {
+                        continue;
+                    };
+                    let opponent_score = scores[&opponent];
+                    standing.buchholz += opponent_score;
+                    standing.sonneborn_berger += mine * opponent_score;
+                    if mine == 1.0 {
+                        standing.wins += 1;
+                    }
+                }
+                standing
+            })
+            .collect();
+
+        let swiss = matches!(self.format, TournamentFormat::Swiss { .. });
+        standings.sort_by(|a, b| {
+            let tiebreaks = if swiss {
+                [
+                    b.buchholz.total_cmp(&a.buchholz),
+                    b.sonneborn_berger.total_cmp(&a.sonneborn_berger),
+                ]
+            } else {
+                [
+                    b.sonneborn_berger.total_cmp(&a.sonneborn_berger),
+                    Ordering::Equal,
+                ]
+            };
+            b.score
+                .total_cmp(&a.score)
+                .then(tiebreaks[0])
+                .then(tiebreaks[1])
+                .then(b.wins.cmp(&a.wins))
+        });
+        standings
+    }
+
+    pub fn total_rounds(&self) -> u32 {
+        match self.format {
+            TournamentFormat::RoundRobin => self.rounds.len() as u32,
+            TournamentFormat::Swiss { rounds } => rounds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(name: &str) -> Player {
+        Player {
+            id: Some(ObjectId::new()),
+            name: name.into(),
+        }
+    }
+
+    fn game(white: &Player, black: &Player, winner: Option<Color>) -> Pairing {
+        Pairing {
+            white: white.clone(),
+            black: Some(black.clone()),
+            game: Some(ObjectId::new()),
+            finished: true,
+            winner,
+        }
+    }
+
+    /// Four players, three rounds:
+    ///
+    /// | Round | Games                  |
+    /// |-------|------------------------|
+    /// | 1     | A beats B, C draws D   |
+    /// | 2     | A draws C, B beats D   |
+    /// | 3     | D beats A, B beats C   |
+    ///
+    /// Scores are A 1.5, B 2, C 1, D 1.5.
+    #[test]
+    fn tiebreaks_match_a_hand_worked_table() {
+        let (a, b, c, d) = (player("A"), player("B"), player("C"), player("D"));
+        let rounds = vec![
+            Round {
+                pairings: vec![game(&a, &b, Some(Color::White)), game(&c, &d, None)],
+            },
+            Round {
+                pairings: vec![game(&a, &c, None), game(&b, &d, Some(Color::White))],
+            },
+            Round {
+                pairings: vec![
+                    game(&d, &a, Some(Color::White)),
+                    game(&b, &c, Some(Color::White)),
+                ],
+            },
+        ];
+        for format in [
+            TournamentFormat::RoundRobin,
+            TournamentFormat::Swiss { rounds: 3 },
+        ] {
+            let tournament = Tournament {
+                id: None,
+                name: "Test".into(),
+                organizer: a.clone(),
+                game_type: GameTypes::Square,
+                time_control: TimeControl::Unlimited,
+                format,
+                rated: false,
+                status: TournamentStatus::Finished,
+                players: vec![a.clone(), b.clone(), c.clone(), d.clone()],
+                rounds: rounds.clone(),
+                current_round: 2,
+            };
+            let standings = tournament.standings();
+            let table: Vec<(&str, f64, f64, f64, u32)> = standings
+                .iter()
+                .map(|standing| {
+                    (
+                        standing.player.name.as_str(),
+                        standing.score,
+                        standing.buchholz,
+                        standing.sonneborn_berger,
+                        standing.wins,
+                    )
+                })
+                .collect();
+            // A and D tie on score and Buchholz, and A's win over B counts for more
+            assert_eq!(
+                table,
+                [
+                    ("B", 2.0, 4.0, 2.5, 2),
+                    ("A", 1.5, 4.5, 2.5, 1),
+                    ("D", 1.5, 4.5, 2.0, 1),
+                    ("C", 1.0, 5.0, 1.5, 0),
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use account::{MAX_EMAIL, MAX_PASSWORD, MIN_PASSWORD};
+
+use crate::{prelude::*, route::Route};
+
+/// The email section of the account page
+#[component]
+pub fn Email() -> Element {
+    let mut status = use_resource(crate::rpc::fetch_email_rpc);
+    let mut address = use_signal(String::new);
+    let mut password = use_signal(String::new);
+    let mut message = use_signal(|| None::<String>);
+
+    let Some(Ok(email)) = status() else {
+        return rsx! {};
+    };
+    if !email.available {
+        return rsx! {};
+    }
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Email" }
+            match &email.address {
+                Some(current) if email.verified => rsx! { div { "Your email is {current}." } },
+                Some(current) => rsx! {
+                    div { "Check {current} for a link to confirm it's yours." }
+                },
+                None => rsx! {
+                    div { "Add an email address to be able to reset your password." }
+                },
+            }
+            if email.verified {
+                label {
+                    input {
+                        "type": "checkbox",
+                        checked: email.digest,
+                        onchange: move |evt| async move {
+                            match crate::rpc::set_digest_rpc(evt.checked()).await {
+                                Ok(()) => status.restart(),
+                                Err(err) => message.set(Some(err.to_string())),
+                            }
+                        },
+                    }
+                    " Email me once a day about games waiting on my move"
+                }
+            }
+            label {
+                "New email: "
+                input {
+                    "type": "email",
+                    maxlength: "{MAX_EMAIL}",
+                    placeholder: "Leave empty to remove",
+                    value: "{address}",
+                    oninput: move |evt| address.set(evt.value()),
+                }
+            }
+            label {
+                "Password: "
+                input {
+                    "type": "password",
+                    value: "{password}",
+                    oninput: move |evt| password.set(evt.value()),
+                }
+            }
+            button {
+                onclick: move |_| async move {
+                    match crate::rpc::set_email_rpc(address(), password()).await {
+                        Ok(()) => {
+                            address.set(String::new());
+                            password.set(String::new());
+                            message.set(None);
+                            status.restart();
+                        }
+                        Err(err) => message.set(Some(err.to_string())),
+                    }
+                },
+                "Change email"
+            }
+            if let Some(message) = message() {
+                div { class: "conflict", "{message}" }
+            }
+        }
+    }
+}
+
+/// Where the link in the confirmation email goes
+#[component]
+pub fn VerifyEmail(token: String) -> Element {
+    let result = use_resource(move || crate::rpc::verify_email_rpc(token.clone()));
+
+    rsx! {
+        div {
+            class: "profile",
+            div {
+                class: "header",
+                h1 { "Email" }
+                div {
+                    class: "buttonMenu",
+                    Link { to: Route::Account {}, "Account" }
+                }
+            }
+            match result() {
+                Some(Ok(())) => rsx! { div { "Your email address is confirmed." } },
+                Some(Err(err)) => rsx! { div { class: "conflict", "{err}" } },
+                None => rsx! { "Loading..." },
+            }
+        }
+    }
+}
+
+/// Where the link in the reset email goes. Works logged in or not.
+#[component]
+pub fn ResetPassword(token: String) -> Element {
+    let mut password = use_signal(String::new);
+    let mut confirm = use_signal(String::new);
+    let mut done = use_signal(|| false);
+    let mut status = use_signal(|| None::<String>);
+
+    if done() {
+        return rsx! {
+            div {
+                class: "login",
+                h1 { "Duck Chess" }
+                div { "Your password is changed and you've been logged out everywhere." }
+                a { href: "/", "Log in" }
+            }
+        };
+    }
+
+    rsx! {
+        div {
+            class: "login",
+            h1 { "Duck Chess" }
+            div { "Choose a new password between {MIN_PASSWORD} and {MAX_PASSWORD} characters." }
+            div {
+                input {
+                    "type": "password",
+                    placeholder: "New password",
+                    value: "{password}",
+                    oninput: move |evt| password.set(evt.value()),
+                }
+            }
+            div {
+                input {
+                    "type": "password",
+                    placeholder: "Confirm new password",
+                    value: "{confirm}",
+                    oninput: move |evt| confirm.set(evt.value()),
+                }
+            }
+            button {
+                onclick: move |_| {
+                    let token = token.clone();
+                    async move {
+                        if password() != confirm() {
+                            status.set(Some("The passwords don't match".into()));
+                            return;
+                        }
+                        match crate::rpc::reset_password_rpc(token, password()).await {
+                            Ok(()) => {
+                                crate::offline::forget();
+                                done.set(true);
+                            }
+                            Err(err) => status.set(Some(err.to_string())),
+                        }
+                    }
+                },
+                "Set password"
+            }
+            if let Some(status) = status() {
+                div { class: "conflict", "{status}" }
+            }
+        }
+    }
+}
+
+/// Sends a reset link to the account's email, if it has one
+#[component]
+pub fn ForgotPassword(name: String) -> Element {
+    let mut status = use_signal(|| None::<String>);
+
+    rsx! {
+        div {
+            button {
+                onclick: move |_| {
+                    let name = name.clone();
+                    async move {
+                        let message = match crate::rpc::request_reset_rpc(name).await {
+                            Ok(()) => "If that account has a confirmed email address, a link to reset the password is on its way.".into(),
+                            Err(ServerFnError::ServerError { message, .. }) => message,
+                            Err(err) => err.to_string(),
+                        };
+                        status.set(Some(message));
+                    }
+                },
+                "Forgot password"
+            }
+            if let Some(status) = status() {
+                div { "{status}" }
+            }
+        }
+    }
+}
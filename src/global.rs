@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use dioxus::prelude::dioxus_fullstack::JsonStream;
 use dioxus::{
     core::ScopeId,
     hooks::{use_future, use_signal},
@@ -7,7 +8,9 @@ use dioxus::{
 };
 
 use crate::{
-    board::{AnyGame, Square, SquareId},
+    board::{AnyGame, GameOrRequest, SomeTurn, Square, SquareId, WithId},
+    common::account::Features,
+    offline,
     style::{clear_style, set_style},
     transition::transition_callback,
 };
@@ -41,9 +44,13 @@ pub fn use_game(id: String) -> Signal<Option<Signal<AnyGame>>> {
                 listen_game(id.clone(), *game).await;
             } else {
                 load_games().await;
-                let game = *GAMES.resolve().read().games.get(&id).unwrap();
-                result.set(Some(game));
-                listen_game(id.clone(), game).await;
+                let game = GAMES.resolve().read().games.get(&id).copied();
+                if let Some(game) = game {
+                    result.set(Some(game));
+                    listen_game(id.clone(), game).await;
+                } else {
+                    spectate_game(id.clone(), result).await;
+                }
             }
         }
     });
@@ -51,44 +58,125 @@ pub fn use_game(id: String) -> Signal<Option<Signal<AnyGame>>> {
 }
 
 async fn load_games() {
-    let games = crate::rpc::fetch_games().await.unwrap_or_default();
+    let games = match crate::rpc::fetch_games().await {
+        Ok(games) => {
+            offline::cache_games(&games);
+            games
+        }
+        Err(error) if offline::is_offline(&error) => offline::cached_games(),
+        Err(_) => Vec::new(),
+    };
     for any_game in games {
         add_game(&any_game);
     }
 }
 
-async fn listen_game(id: String, mut holder: Signal<AnyGame>) {
-    if let Ok(mut stream) = crate::rpc::game_events(id).await {
-        let mut locations = HashMap::new();
-        while let Some(Ok(value)) = stream.next().await {
-            let mut style = String::new();
-            for (loc, square) in value.game.pieces() {
-                let id = match square {
-                    Square::Piece(_, _, SquareId(id)) => format!("_{id}"),
-                    Square::Duck => "duck".to_string(),
-                    Square::Empty => continue,
-                };
-                if let Some(old_loc) = locations.insert(square, loc)
-                    && old_loc != loc
-                {
-                    style.push_str(&format!(
-                        "#{id} {{ view-transition-name: {id}; view-transition-class: bulk_piece; }}"
-                    ));
-                }
+/// Updates games in place so components already holding their signals see the new state.
+pub fn refresh_games(games: &[AnyGame]) {
+    offline::cache_games(games);
+    for any_game in games {
+        let existing = GAMES
+            .read()
+            .games
+            .get(&any_game.id.unwrap().to_string())
+            .copied();
+        if let Some(mut holder) = existing {
+            holder.set(any_game.clone());
+        } else {
+            add_game(any_game);
+        }
+    }
+}
+
+/// Shows a turn that couldn't reach the server as if it had been accepted.
+pub fn apply_local_turn(turn: &WithId<SomeTurn>) {
+    let existing = GAMES.read().games.get(&turn.id.to_string()).copied();
+    if let Some(mut holder) = existing {
+        let mut any_game = holder();
+        if let GameOrRequest::Game(game) = &mut any_game.game
+            && game.some_game.apply_turn(**turn).is_ok()
+        {
+            offline::cache_game(&any_game);
+            holder.set(any_game);
+        }
+    }
+}
+
+async fn listen_game(id: String, holder: Signal<AnyGame>) {
+    loop {
+        if let Ok(stream) = crate::rpc::game_events(id.clone()).await {
+            show_updates(stream, holder, true).await;
+        }
+        // The stream also ends when the connection drops, so pick it back up once we're online
+        if offline::online() {
+            break;
+        }
+        offline::wait_until(true).await;
+    }
+}
+
+// Games we're only watching stay out of GAMES so they don't show up as ours on the main menu
+async fn spectate_game(id: String, mut result: Signal<Option<Signal<AnyGame>>>) {
+    if let Ok(mut stream) = crate::rpc::spectate_events(id).await
+        && let Some(Ok(initial)) = stream.next().await
+    {
+        let holder = Signal::new(initial);
+        result.set(Some(holder));
+        show_updates(stream, holder, false).await;
+    }
+}
+
+async fn show_updates(
+    mut stream: JsonStream<AnyGame>,
+    mut holder: Signal<AnyGame>,
+    remember: bool,
+) {
+    let mut locations = HashMap::new();
+    while let Some(Ok(value)) = stream.next().await {
+        let mut style = String::new();
+        for (loc, square) in value.game.pieces() {
+            let id = match square {
+                Square::Piece(_, _, SquareId(id)) => format!("_{id}"),
+                Square::Duck => "duck".to_string(),
+                Square::Empty => continue,
+            };
+            if let Some(old_loc) = locations.insert(square, loc)
+                && old_loc != loc
+            {
+                style.push_str(&format!(
+                    "#{id} {{ view-transition-name: {id}; view-transition-class: bulk_piece; }}"
+                ));
             }
-            set_style("game_pushed", style);
-            transition_callback(move || {
-                holder.set(value);
-            })
-            .on_complete_callback(|| {
-                clear_style("game_pushed");
-            });
         }
+        if remember {
+            offline::cache_game(&value);
+        }
+        set_style("game_pushed", style);
+        transition_callback(move || {
+            holder.set(value);
+        })
+        .on_complete_callback(|| {
+            clear_style("game_pushed");
+        });
     }
 }
 
 static GAMES: GlobalSignal<AllGames> = Signal::global(AllGames::default);
 
+static FEATURES: GlobalSignal<Option<Features>> = Signal::global(|| None);
+
+/// What the server supports, fetched once. Features count as off until the answer comes in, so
+/// nothing shows up only to fail.
+pub fn use_features() -> Features {
+    use_future(|| async {
+        if FEATURES.peek().is_none() {
+            let features = crate::rpc::fetch_features().await.unwrap_or_default();
+            *FEATURES.write() = Some(features);
+        }
+    });
+    FEATURES.read().unwrap_or_default()
+}
+
 #[derive(Default, Debug, Clone)]
 struct AllGames {
     games: HashMap<String, Signal<AnyGame>>,
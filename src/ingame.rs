@@ -1,6 +1,8 @@
 use crate::activegame::SomeActiveGame;
 use crate::board::DrawSomeGame;
+use crate::chat::Chat;
 use crate::joinablegame::JoinableGame;
+use crate::spectate::{GameVisibility, SpectatorCount};
 use crate::style::use_style;
 use crate::{notification, prelude::*};
 
@@ -12,6 +14,7 @@ pub enum ServerTurn {
     MyTurn(ObjectId, Game),
     OtherTurn(Game),
     Ended(Color, Game),
+    Spectating(Game),
 }
 
 #[component]
@@ -22,10 +25,12 @@ pub fn InGame(id: String) -> Element {
     );
     provide_context(crate::board::BoardId::new_hero(id.clone()));
     let player: Player = use_context();
-    let game_or_request = use_game(id);
+    let game_or_request = use_game(id.clone());
+    let mut visibility = Visibility::default();
 
     let server_turn = if let Some(with_id) = game_or_request() {
         let with_id = with_id();
+        visibility = with_id.visibility;
         match with_id.game {
             GameOrRequest::Request(request) => ServerTurn::NotStarted(with_id.id.unwrap(), request),
             GameOrRequest::Game(game) | GameOrRequest::Completed(game) => {
@@ -34,6 +39,7 @@ pub fn InGame(id: String) -> Element {
                     TurnState::MyTurn => ServerTurn::MyTurn(with_id.id.unwrap(), game),
                     TurnState::OtherTurn => ServerTurn::OtherTurn(game),
                     TurnState::Ended(winner) => ServerTurn::Ended(winner, game),
+                    TurnState::Spectating => ServerTurn::Spectating(game),
                 }
             }
         }
@@ -44,21 +50,25 @@ pub fn InGame(id: String) -> Element {
     match server_turn {
         ServerTurn::Loading => spinner(),
         ServerTurn::Invalid => rsx! { "Invalid game id" },
-        ServerTurn::NotStarted(id, request) => rsx! {
+        ServerTurn::NotStarted(game_id, request) => rsx! {
             div {
                 class: "headed",
                 if request.maker.id == player.id {
-                    "Your game hasn't started yet. Share this page to invite someone."
+                    div {
+                        class: "turnHeaderDiv",
+                        "Your game hasn't started yet. Share this page to invite someone."
+                        GameVisibility { id, value: visibility }
+                    }
                 } else {
                     div {}
                 }
                 JoinableGame {
                     request,
-                    id,
+                    id: game_id,
                 }
             }
         },
-        ServerTurn::MyTurn(id, game) => rsx! {
+        ServerTurn::MyTurn(game_id, game) => rsx! {
             div {
                 class: "headed",
                 div {
@@ -68,9 +78,12 @@ pub fn InGame(id: String) -> Element {
                         "It is your turn!"
                     }
                     notification::subscribe {}
+                    GameVisibility { id: id.clone(), value: visibility }
+                    SpectatorCount { id: id.clone() }
+                    Chat { id, opponent: game.opponent(&player).clone() }
                 }
                 SomeActiveGame {
-                    id,
+                    id: game_id,
                     game,
                 }
             }
@@ -85,6 +98,9 @@ pub fn InGame(id: String) -> Element {
                         "It is not your turn"
                     }
                     notification::subscribe {}
+                    GameVisibility { id: id.clone(), value: visibility }
+                    SpectatorCount { id: id.clone() }
+                    Chat { id, opponent: game.opponent(&player).clone() }
                 }
                 DrawSomeGame {
                     game,
@@ -95,11 +111,36 @@ pub fn InGame(id: String) -> Element {
             div {
                 class: "headed",
                 "{winner:?} Won!"
+                if game.player(&player) != PlayerColor::None {
+                    Chat { id, opponent: game.opponent(&player).clone() }
+                }
                 DrawSomeGame {
                     game,
                 }
             }
         },
+        ServerTurn::Spectating(game) => {
+            let (white, black) = if game.maker_color == Color::White {
+                (&game.maker.name, &game.joiner.name)
+            } else {
+                (&game.joiner.name, &game.maker.name)
+            };
+            rsx! {
+                div {
+                    class: "headed",
+                    div {
+                        class: "turnHeaderDiv",
+                        span {
+                            class: "turnHeader",
+                            "Watching {white} (White) vs {black} (Black). {game.turn():?} to move."
+                        }
+                    }
+                    DrawSomeGame {
+                        game,
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -107,11 +148,14 @@ enum TurnState {
     MyTurn,
     OtherTurn,
     Ended(Color),
+    Spectating,
 }
 
 fn get_game_state(game: &Game, player: &Player) -> TurnState {
     if let Some(color) = game.game_over() {
         TurnState::Ended(color)
+    } else if game.player(player) == PlayerColor::None {
+        TurnState::Spectating
     } else if game.player(player).contains(&game.turn()) {
         TurnState::MyTurn
     } else {
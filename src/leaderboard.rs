@@ -0,0 +1,91 @@
+use game::GameTypes;
+
+use crate::{prelude::*, ratings::format_rating, route::Route};
+
+#[component]
+pub fn Leaderboard() -> Element {
+    let mut game_type = use_signal(|| GameTypes::Square);
+    let mut active_only = use_signal(|| true);
+    let mut min_games = use_signal(|| 5u32);
+    let entries = use_resource(move || async move {
+        crate::rpc::fetch_leaderboard(game_type(), active_only(), min_games())
+            .await
+            .unwrap_or_default()
+    });
+
+    rsx! {
+        div {
+            class: "profile",
+            div {
+                class: "header",
+                h1 { "Leaderboard" }
+                div {
+                    class: "buttonMenu",
+                    Link { to: Route::MainMenu {}, "Back" }
+                }
+            }
+            div {
+                class: "pages",
+                label {
+                    "Variant: "
+                    select {
+                        onchange: move |evt| {
+                            if let Ok(i) = evt.value().parse::<usize>() {
+                                game_type.set(GameTypes::all()[i]);
+                            }
+                        },
+                        for (i, option) in GameTypes::all().into_iter().enumerate() {
+                            option {
+                                value: "{i}",
+                                selected: option == game_type(),
+                                "{option:?}"
+                            }
+                        }
+                    }
+                }
+                label {
+                    input {
+                        "type": "checkbox",
+                        checked: active_only(),
+                        onchange: move |evt| active_only.set(evt.checked()),
+                    }
+                    "Played in the last 30 days"
+                }
+                label {
+                    "Minimum games: "
+                    input {
+                        "type": "number",
+                        min: "0",
+                        value: "{min_games}",
+                        onchange: move |evt| {
+                            if let Ok(games) = evt.value().parse() {
+                                min_games.set(games);
+                            }
+                        },
+                    }
+                }
+            }
+            table {
+                tr {
+                    th { "#" }
+                    th { "Player" }
+                    th { "Rating" }
+                    th { "Games" }
+                }
+                for (i, entry) in entries.value()().unwrap_or_default().into_iter().enumerate() {
+                    tr {
+                        td { "{i + 1}" }
+                        td {
+                            Link {
+                                to: Route::Profile { name: entry.player.name.clone() },
+                                "{entry.player.name}"
+                            }
+                        }
+                        td { {format_rating(entry.rating)} }
+                        td { "{entry.games}" }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,12 @@
+use account::LoginStep;
+
 use crate::prelude::*;
 
 #[component]
 pub fn login_buttons<T: 'static + Eq + Clone>(
     player: ReadSignal<PasswordPlayer>,
-    errors: Signal<&'static str>,
+    errors: Signal<String>,
+    code: Signal<Option<String>>,
     session: Resource<T>,
 ) -> Element {
     let mut loading = use_signal(|| false);
@@ -15,12 +18,22 @@ pub fn login_buttons<T: 'static + Eq + Clone>(
             button {
                 onclick: move |_| async move {
                     loading.set(true);
-                    let result: Result<Player, _> = crate::rpc::login(player()).await;
-                    if result.is_ok() {
-                        session.restart();
-                    } else {
-                        errors.set("Invalid login credentials");
-                        loading.set(false);
+                    match crate::rpc::login(player(), code()).await {
+                        Ok(LoginStep::LoggedIn(_)) => session.restart(),
+                        Ok(LoginStep::NeedsCode) => {
+                            code.set(Some(String::new()));
+                            errors.set("Enter the code from your authenticator app".into());
+                            loading.set(false);
+                        }
+                        Err(error) => {
+                            let fallback = if code().is_some() {
+                                "That code didn't work"
+                            } else {
+                                "Invalid login credentials"
+                            };
+                            errors.set(login_error(error, fallback));
+                            loading.set(false);
+                        }
                     }
                 },
                 "Login"
@@ -30,15 +43,40 @@ pub fn login_buttons<T: 'static + Eq + Clone>(
                 onclick: move |_| async move {
                     loading.set(true);
                     let result = crate::rpc::signup(player()).await;
-                    if result.is_ok() {
-                        session.restart();
-                    } else {
-                        errors.set("Unable to sign up. Your username might already be taken or your password didn't have at least 8 characters.");
-                        loading.set(false);
+                    match result {
+                        Ok(_) => session.restart(),
+                        Err(error) => {
+                            errors.set(login_error(error, "Unable to sign up. Your username might already be taken or your password didn't have at least 8 characters."));
+                            loading.set(false);
+                        }
                     }
                 },
                 "Signup"
             }
+
+            button {
+                onclick: move |_| async move {
+                    loading.set(true);
+                    match crate::passkeys::login(player().name.clone()).await {
+                        Ok(_) => session.restart(),
+                        Err(error) => {
+                            errors.set(error);
+                            loading.set(false);
+                        }
+                    }
+                },
+                "Login with a passkey"
+            }
         }
     }
 }
+
+/// Says how long to wait when the server is refusing attempts, otherwise the usual message
+fn login_error(error: ServerFnError, fallback: &str) -> String {
+    match error {
+        ServerFnError::ServerError {
+            code: 429, message, ..
+        } => message,
+        _ => fallback.into(),
+    }
+}
@@ -1,23 +1,33 @@
+mod account;
 mod activegame;
 mod board;
+mod chat;
 mod common;
+mod email;
 mod global;
 mod ingame;
 mod joinablegame;
 mod keyed;
+mod leaderboard;
 mod loading;
 mod loginbuttons;
 mod mainmenu;
 mod newgame;
 mod notification;
+mod offline;
 mod padding;
+mod passkeys;
 mod prelude;
+mod profile;
+mod ratings;
 mod route;
 mod rpc;
 #[cfg(feature = "server")]
 mod server;
 mod some;
+mod spectate;
 mod style;
+mod tournaments;
 mod tracked;
 mod transition;
 mod unauth;
@@ -29,14 +39,27 @@ use std::sync::Arc;
 use prelude::*;
 
 use crate::{
+    board::FOLDER,
     style::GlobalStyle,
     transition::transition,
     update_gate::{Gate, close_gate, open_gate},
 };
 
 fn app() -> Element {
-    let player_future = use_resource(|| async { rpc::fetch_session().await.ok().flatten() });
+    let player_future = use_resource(|| async {
+        match rpc::fetch_session().await {
+            Ok(player) => {
+                offline::remember_player(&player);
+                player
+            }
+            Err(error) if offline::is_offline(&error) => offline::cached_player(),
+            Err(_) => None,
+        }
+    });
     use_effect(|| {
+        spawn(async {
+            offline::register_worker().await;
+        });
         let history = history();
         history.updater(Arc::new(|| {
             log::warn!("In the updater callback");
@@ -54,15 +77,18 @@ fn app() -> Element {
             use_context_provider(|| player);
             rsx! {
                 document::Stylesheet { href: asset!("index.css") }
+                document::Link { rel: "manifest", href: "{FOLDER}/manifest.json" }
                 GlobalStyle {
                     Gate {
                         Router::<route::Route> {}
                     }
+                    offline::SyncStatus {}
                 }
             }
         } else {
             rsx! {
                 document::Stylesheet { href: asset!("index.css") }
+                document::Link { rel: "manifest", href: "{FOLDER}/manifest.json" }
                 unauth::unauth {
                     session: player_future
                 }
@@ -78,7 +104,7 @@ fn main() -> anyhow::Result<()> {
     serve_web();
 
     #[cfg(feature = "server")]
-    serve_server();
+    serve_server()?;
 
     Ok(())
 }
@@ -90,12 +116,22 @@ fn serve_web() {
 }
 
 #[cfg(feature = "server")]
-fn serve_server() {
-    dioxus::serve(|| async move {
+fn serve_server() -> anyhow::Result<()> {
+    dioxus::logger::initialize_default();
+    tokio::runtime::Runtime::new()?.block_on(async {
         let router = server::build_state(dioxus::server::router(app))
             .await?
             .layer(tower_cookies::CookieManagerLayer::new());
 
-        Ok(router)
-    });
+        // Served here instead of with dioxus::serve, which doesn't pass on the connecting address
+        // that the login limits need
+        let address = dioxus::cli_config::fullstack_address_or_localhost();
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .await?;
+        Ok(())
+    })
 }
@@ -1,29 +1,46 @@
+use std::{cmp::Reverse, time::SystemTime};
+
 use web_sys::window;
 
-use crate::board::{game_preview, some_game_preview};
-use crate::{notification, prelude::*};
+use crate::board::{request_preview, some_game_preview};
+use crate::{notification, prelude::*, ratings::RatingSummary, route::Route};
 
 #[component]
 pub fn MainMenu() -> Element {
     let player: Player = use_context();
+    let features = use_features();
 
     let mut my_turn = Vec::new();
     let mut other_turn = Vec::new();
     let mut completed = Vec::new();
     let mut open = Vec::new();
 
-    for any_game in use_all_games().iter() {
-        let id = any_game.read().id.unwrap().to_string();
-        match &any_game.read().game {
-            GameOrRequest::Game(game) if game.is_player_turn(&player) => {
-                my_turn.push(some_game_preview(id, game))
-            }
-            GameOrRequest::Game(game) => other_turn.push(some_game_preview(id, game)),
-            GameOrRequest::Completed(game) => completed.push(some_game_preview(id, game)),
-            GameOrRequest::Request(_) => open.push(game_preview::<Board>(
-                id,
-                PlayerColor::None,
-                Board::static_default(),
+    // Whatever happened most recently comes first
+    let mut all_games: Vec<AnyGame> = use_all_games()
+        .iter()
+        .map(|any_game| any_game.read().clone())
+        .collect();
+    all_games.sort_by_key(|any_game| Reverse(any_game.last_activity()));
+
+    let now = now();
+    for any_game in all_games.iter() {
+        let id = any_game.id.unwrap().to_string();
+        let ago = ago(now, any_game.last_activity());
+        match &any_game.game {
+            GameOrRequest::Game(game) if game.is_player_turn(&player) => my_turn.push(
+                with_caption(some_game_preview(id, game), format!("Last move {ago}")),
+            ),
+            GameOrRequest::Game(game) => other_turn.push(with_caption(
+                some_game_preview(id, game),
+                format!("Last move {ago}"),
+            )),
+            GameOrRequest::Completed(game) => completed.push(with_caption(
+                some_game_preview(id, game),
+                format!("Ended {ago}"),
+            )),
+            GameOrRequest::Request(request) => open.push(with_caption(
+                request_preview(id, request),
+                format!("Created {ago}"),
             )),
         }
     }
@@ -33,19 +50,39 @@ pub fn MainMenu() -> Element {
             class: "mainMenu",
             div {
                 class: "header",
-                h1 {
-                    "Duck Chess"
+                div {
+                    h1 {
+                        "Duck Chess"
+                    }
+                    RatingSummary { player: player.clone() }
                 }
                 div {
                     class: "buttonMenu",
                     notification::subscribe {}
+                    button {
+                        onclick: move |_| async {
+                            crate::rpc::logout_device().await.unwrap();
+                            crate::offline::forget();
+                            window().unwrap().location().reload().unwrap();
+                        },
+                        "Logout"
+                    }
                     button {
                         onclick: move |_| async {
                             crate::rpc::logout().await.unwrap();
+                            crate::offline::forget();
                             window().unwrap().location().reload().unwrap();
                         },
                         "Logout all devices"
                     }
+                    Link { to: Route::Profile { name: player.name.clone() }, "Profile" }
+                    Link { to: Route::Account {}, "Account" }
+                    if features.ratings {
+                        Link { to: Route::Leaderboard {}, "Leaderboard" }
+                    }
+                    if features.tournaments {
+                        Link { to: Route::Tournaments {}, "Tournaments" }
+                    }
                     Link { to: "/ui/newgame", "New Game" }
                 }
             }
@@ -60,3 +97,38 @@ pub fn MainMenu() -> Element {
         }
     }
 }
+
+fn with_caption(preview: Element, caption: String) -> Element {
+    rsx! {
+        div {
+            class: "preview",
+            {preview}
+            div { class: "caption", "{caption}" }
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    // The server renders this page too, and it doesn't have the browser's clock
+    if cfg!(feature = "web") {
+        (js_sys::Date::now() / 1000.0) as u64
+    } else {
+        SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs()
+    }
+}
+
+/// Roughly how long ago something happened, like "3 hours ago"
+pub fn ago(now: u64, then: u64) -> String {
+    let seconds = now.saturating_sub(then);
+    let (amount, unit) = match seconds {
+        0..60 => return "just now".into(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    if amount == 1 {
+        format!("1 {unit} ago")
+    } else {
+        format!("{amount} {unit}s ago")
+    }
+}
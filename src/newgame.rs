@@ -1,19 +1,33 @@
-use crate::{board::game_preview, prelude::*, route::Route};
+use dioxus::core::Task;
+use game::GameTypes;
+use seek::Seek;
+
+use crate::{
+    board::{request_preview, some_game_preview},
+    prelude::*,
+    route::Route,
+    spectate::VisibilitySelect,
+};
 
 #[component]
 pub fn NewGame() -> Element {
+    let mut game_type = use_signal(|| GameTypes::Square);
+    let mut visibility = use_signal(Visibility::default);
+    let mut rated = use_signal(|| true);
+    let mut time_control = use_signal(TimeControl::default);
+    let features = use_features();
+    let mut range = use_signal(|| Some(200));
+    let mut searching = use_signal(|| None::<Task>);
     let open_games =
         use_resource(|| async { crate::rpc::fetch_open_games().await.unwrap_or_default() });
+    let public_games =
+        use_resource(|| async { crate::rpc::fetch_public_games().await.unwrap_or_default() });
 
     let mut previews = Vec::new();
     if let Some(games) = open_games.value()() {
         for game in games {
             let id = game.id.to_string();
-            previews.push(game_preview::<Board>(
-                id,
-                PlayerColor::None,
-                Board::static_default(),
-            ));
+            previews.push(request_preview(id, &game));
         }
         if previews.is_empty() {
             previews.push(rsx! {
@@ -22,12 +36,110 @@ pub fn NewGame() -> Element {
         }
     }
 
+    let mut watchable = Vec::new();
+    for any_game in public_games.value()().unwrap_or_default() {
+        if let GameOrRequest::Game(game) = &any_game.game {
+            watchable.push(some_game_preview(any_game.id.unwrap().to_string(), game));
+        }
+    }
+
     rsx! {
         div {
             class: "newGame",
+            label {
+                "Variant: "
+                select {
+                    onchange: move |evt| {
+                        if let Ok(i) = evt.value().parse::<usize>() {
+                            game_type.set(GameTypes::all()[i]);
+                        }
+                    },
+                    for (i, option) in GameTypes::all().into_iter().enumerate() {
+                        option {
+                            value: "{i}",
+                            selected: option == game_type(),
+                            "{option:?}"
+                        }
+                    }
+                }
+            }
+            if features.ratings {
+                label {
+                    input {
+                        "type": "checkbox",
+                        checked: rated(),
+                        onchange: move |evt| rated.set(evt.checked()),
+                    }
+                    "Rated"
+                }
+            }
+            TimeControlSelect {
+                value: time_control(),
+                onchange: move |value| time_control.set(value),
+            }
+            if features.ratings {
+                label {
+                    "Opponent rating: "
+                    select {
+                        onchange: move |evt| {
+                            if let Ok(i) = evt.value().parse::<usize>() {
+                                range.set(Seek::ranges()[i]);
+                            }
+                        },
+                        for (i, option) in Seek::ranges().into_iter().enumerate() {
+                            option {
+                                value: "{i}",
+                                selected: option == range(),
+                                "{Seek::range_label(option)}"
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(task) = searching() {
+                div {
+                    "Looking for an opponent..."
+                    button {
+                        onclick: move |_| {
+                            task.cancel();
+                            searching.set(None);
+                        },
+                        "Cancel"
+                    }
+                }
+            } else {
+                button {
+                    onclick: move |_| {
+                        let seek = Seek {
+                            game_type: game_type(),
+                            time_control: time_control(),
+                            rated: rated() && features.ratings,
+                            range: range().filter(|_| features.ratings),
+                        };
+                        let task = spawn(async move {
+                            if let Ok(mut games) = crate::rpc::play_now(seek).await
+                                && let Some(Ok(id)) = games.next().await
+                            {
+                                navigator().push(Route::InGame { id: id.to_string() });
+                            }
+                            searching.set(None);
+                        });
+                        searching.set(Some(task));
+                    },
+                    "Play now"
+                }
+            }
+            "Or set up your own game"
+            VisibilitySelect {
+                value: visibility(),
+                onchange: move |value| visibility.set(value),
+            }
             button {
                 onclick: move |_| async move {
-                        let id = crate::rpc::create_game().await.unwrap().to_string();
+                        let id = crate::rpc::create_game(game_type(), visibility(), rated() && features.ratings, time_control())
+                            .await
+                            .unwrap()
+                            .to_string();
                         navigator().push(Route::InGame {id});
                 },
                 "Create a new game"
@@ -38,6 +150,37 @@ pub fn NewGame() -> Element {
                 class: "newGamePreviews",
                 {previews.into_iter()}
             }
+            if !watchable.is_empty() {
+                "Or watch a game"
+                hr {}
+                div {
+                    class: "newGamePreviews",
+                    {watchable.into_iter()}
+                }
+            }
+        }
+    }
+}
+
+#[component]
+pub fn TimeControlSelect(value: TimeControl, onchange: EventHandler<TimeControl>) -> Element {
+    rsx! {
+        label {
+            "Time control: "
+            select {
+                onchange: move |evt| {
+                    if let Ok(i) = evt.value().parse::<usize>() {
+                        onchange(TimeControl::all()[i]);
+                    }
+                },
+                for (i, option) in TimeControl::all().into_iter().enumerate() {
+                    option {
+                        value: "{i}",
+                        selected: option == value,
+                        "{option.label()}"
+                    }
+                }
+            }
         }
     }
 }
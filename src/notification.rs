@@ -1,7 +1,9 @@
-use crate::prelude::*;
+use account::{NotificationSettings, QuietHours};
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{Notification, PushSubscriptionOptionsInit, ServiceWorkerRegistration, window};
+use web_sys::{Notification, PushSubscription, PushSubscriptionOptionsInit};
+
+use crate::prelude::*;
 
 pub fn subscribe() -> Element {
     let mut enabled =
@@ -26,24 +28,15 @@ async fn subscribe_me() {
     JsFuture::from(Notification::request_permission().unwrap())
         .await
         .unwrap();
-    let registration = JsFuture::from(
-        window()
-            .unwrap()
-            .navigator()
-            .service_worker()
-            .register(&asset!("assets/worker.js").to_string()),
-    )
-    .await
-    .unwrap();
-    let key_encoded = crate::rpc::public_key_rpc().await.unwrap();
+    let registration = crate::offline::register_worker().await.unwrap();
+    // Servers that don't send push notifications have no key
+    let Ok(key_encoded) = crate::rpc::public_key_rpc().await else {
+        return;
+    };
     let options = PushSubscriptionOptionsInit::new();
     options.set_application_server_key(&JsValue::from_str(&key_encoded));
     options.set_user_visible_only(true);
-    let registration = registration
-        .dyn_ref::<ServiceWorkerRegistration>()
-        .unwrap()
-        .push_manager()
-        .unwrap();
+    let registration = registration.push_manager().unwrap();
     let result = JsFuture::from(registration.subscribe_with_options(&options).unwrap())
         .await
         .unwrap();
@@ -56,3 +49,187 @@ async fn subscribe_me() {
     .await
     .unwrap();
 }
+
+/// Stops notifications here, both on the server and in the browser
+async fn unsubscribe_me() -> Result<(), String> {
+    crate::rpc::unsubscribe_rpc()
+        .await
+        .map_err(|error| error.to_string())?;
+    if let Some(registration) = crate::offline::register_worker().await
+        && let Ok(promise) = registration
+            .push_manager()
+            .and_then(|manager| manager.get_subscription())
+        && let Ok(subscription) = JsFuture::from(promise).await
+        && let Ok(subscription) = subscription.dyn_into::<PushSubscription>()
+        && let Ok(promise) = subscription.unsubscribe()
+    {
+        // Nothing is sent to it anymore, so there's nothing to do if this fails
+        let _ = JsFuture::from(promise).await;
+    }
+    Ok(())
+}
+
+type Field = fn(&mut NotificationSettings) -> &mut bool;
+
+const EVENTS: [(&str, Field); 5] = [
+    ("A game starts", |settings| &mut settings.game_start),
+    ("It's my turn", |settings| &mut settings.turn),
+    ("A game ends", |settings| &mut settings.game_end),
+    ("I get a chat message", |settings| &mut settings.chat),
+    ("Time to move is running out", |settings| {
+        &mut settings.deadlines
+    }),
+];
+
+fn time_value(minutes: u16) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+fn parse_time(value: &str) -> Option<u16> {
+    let (hours, minutes) = value.split_once(':')?;
+    Some(hours.parse::<u16>().ok()? * 60 + minutes.parse::<u16>().ok()?)
+}
+
+/// Minutes ahead of UTC where the browser is
+fn utc_offset() -> i32 {
+    -(js_sys::Date::new_0().get_timezone_offset() as i32)
+}
+
+/// The notifications section of the account page
+#[component]
+pub fn Notifications() -> Element {
+    let mut settings = use_resource(crate::rpc::fetch_notification_settings);
+    let mut enabled =
+        use_resource(|| async { crate::rpc::notifications_enabled().await.unwrap_or(false) });
+    let mut message = use_signal(|| None::<String>);
+
+    let Some(Ok(current)) = settings() else {
+        return rsx! {};
+    };
+    let save = move |new: NotificationSettings| {
+        spawn(async move {
+            match crate::rpc::set_notification_settings(new).await {
+                Ok(()) => {
+                    message.set(None);
+                    settings.restart();
+                }
+                Err(err) => message.set(Some(err.to_string())),
+            }
+        });
+    };
+
+    let toggles = EVENTS.into_iter().map(|(label, field)| {
+        let checked = *field(&mut current.clone());
+        let current = current.clone();
+        rsx! {
+            label {
+                input {
+                    "type": "checkbox",
+                    checked,
+                    onchange: move |evt| {
+                        let mut new = current.clone();
+                        *field(&mut new) = evt.checked();
+                        save(new);
+                    },
+                }
+                " {label}"
+            }
+        }
+    });
+    let quiet = current.quiet_hours.clone();
+    let quiet_on = quiet.is_some();
+    let set_quiet = {
+        let current = current.clone();
+        move |quiet_hours: Option<QuietHours>| {
+            save(NotificationSettings {
+                quiet_hours,
+                ..current.clone()
+            })
+        }
+    };
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Notifications" }
+            if enabled() == Some(true) {
+                div {
+                    "Notifications are on for this device. "
+                    button {
+                        onclick: move |_| async move {
+                            match unsubscribe_me().await {
+                                Ok(()) => enabled.restart(),
+                                Err(err) => message.set(Some(err)),
+                            }
+                        },
+                        "Turn off on this device"
+                    }
+                }
+            } else {
+                div { {subscribe()} }
+            }
+            div { "Notify me when:" }
+            {toggles}
+            label {
+                input {
+                    "type": "checkbox",
+                    checked: quiet_on,
+                    onchange: {
+                        let set_quiet = set_quiet.clone();
+                        move |evt: FormEvent| {
+                            set_quiet(evt.checked().then(|| QuietHours {
+                                start: 22 * 60,
+                                end: 7 * 60,
+                                utc_offset: utc_offset(),
+                            }))
+                        }
+                    },
+                }
+                " Quiet hours"
+            }
+            if let Some(quiet) = quiet {
+                label {
+                    "From "
+                    input {
+                        "type": "time",
+                        value: time_value(quiet.start),
+                        onchange: {
+                            let set_quiet = set_quiet.clone();
+                            let quiet = quiet.clone();
+                            move |evt: FormEvent| {
+                                if let Some(start) = parse_time(&evt.value()) {
+                                    set_quiet(Some(QuietHours {
+                                        start,
+                                        utc_offset: utc_offset(),
+                                        ..quiet.clone()
+                                    }))
+                                }
+                            }
+                        },
+                    }
+                    " to "
+                    input {
+                        "type": "time",
+                        value: time_value(quiet.end),
+                        onchange: {
+                            let set_quiet = set_quiet.clone();
+                            let quiet = quiet.clone();
+                            move |evt: FormEvent| {
+                                if let Some(end) = parse_time(&evt.value()) {
+                                    set_quiet(Some(QuietHours {
+                                        end,
+                                        utc_offset: utc_offset(),
+                                        ..quiet.clone()
+                                    }))
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+            if let Some(message) = message() {
+                div { class: "conflict", "{message}" }
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use futures::channel::oneshot;
+use gloo_events::EventListener;
+use serde::{Serialize, de::DeserializeOwned};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{RegistrationOptions, ServiceWorkerRegistration, Storage, window};
+
+use crate::global::{apply_local_turn, refresh_games};
+use crate::prelude::*;
+
+const PLAYER_KEY: &str = "duck_chess_player";
+const GAMES_KEY: &str = "duck_chess_games";
+const TURNS_KEY: &str = "duck_chess_turns";
+/// How many times to resend a turn that lost a race with another save of the same game
+const CONFLICT_RETRIES: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct SyncState {
+    pub online: bool,
+    pub queued: usize,
+    pub conflicts: Vec<String>,
+}
+
+static SYNC: GlobalSignal<SyncState> = Signal::global(|| SyncState {
+    online: true,
+    queued: 0,
+    conflicts: Vec::new(),
+});
+
+pub fn is_offline(error: &ServerFnError) -> bool {
+    matches!(error, ServerFnError::Request(_))
+}
+
+pub fn online() -> bool {
+    window().is_none_or(|window| window.navigator().on_line())
+}
+
+// The worker has to control every page (not just /assets) to serve the app offline, so the server
+// sends a Service-Worker-Allowed header to let us widen the scope.
+pub async fn register_worker() -> Option<ServiceWorkerRegistration> {
+    let options = RegistrationOptions::new();
+    options.set_scope("/");
+    let registration = JsFuture::from(
+        window()?
+            .navigator()
+            .service_worker()
+            .register_with_options(&asset!("assets/worker.js").to_string(), &options),
+    )
+    .await
+    .ok()?;
+    registration.dyn_into().ok()
+}
+
+fn storage() -> Option<Storage> {
+    // The server renders the same components but has no local storage to talk to
+    if cfg!(feature = "web") {
+        window()?.local_storage().ok().flatten()
+    } else {
+        None
+    }
+}
+
+fn load<T: DeserializeOwned>(key: &str) -> Option<T> {
+    let json = storage()?.get_item(key).ok()??;
+    serde_json::from_str(&json).ok()
+}
+
+fn save<T: Serialize>(key: &str, value: &T) {
+    if let Some(storage) = storage() {
+        let _ = storage.set_item(key, &serde_json::to_string(value).unwrap());
+    }
+}
+
+pub fn remember_player(player: &Option<Player>) {
+    match player {
+        Some(player) => save(PLAYER_KEY, player),
+        None => forget(),
+    }
+}
+
+pub fn cached_player() -> Option<Player> {
+    load(PLAYER_KEY)
+}
+
+/// Drops everything saved for the current player, including turns that were never sent.
+pub fn forget() {
+    if let Some(storage) = storage() {
+        for key in [PLAYER_KEY, GAMES_KEY, TURNS_KEY] {
+            let _ = storage.remove_item(key);
+        }
+    }
+}
+
+pub fn cached_games() -> Vec<AnyGame> {
+    load::<HashMap<String, AnyGame>>(GAMES_KEY)
+        .unwrap_or_default()
+        .into_values()
+        .collect()
+}
+
+pub fn cache_games(games: &[AnyGame]) {
+    let games: HashMap<String, &AnyGame> = games
+        .iter()
+        .map(|game| (game.id.unwrap().to_string(), game))
+        .collect();
+    save(GAMES_KEY, &games);
+}
+
+pub fn cache_game(game: &AnyGame) {
+    let mut games: HashMap<String, AnyGame> = load(GAMES_KEY).unwrap_or_default();
+    games.insert(game.id.unwrap().to_string(), game.clone());
+    save(GAMES_KEY, &games);
+}
+
+fn queued_turns() -> Vec<WithId<SomeTurn>> {
+    load(TURNS_KEY).unwrap_or_default()
+}
+
+fn save_turns(turns: &[WithId<SomeTurn>]) {
+    save(TURNS_KEY, &turns);
+    SYNC.write().queued = turns.len();
+}
+
+/// The server answers with a 409 when someone else saved the game first. Sending the same turn
+/// again is always safe since the server ignores turns it already has.
+async fn send_turn(turn: &WithId<SomeTurn>) -> Result<(), ServerFnError> {
+    let mut result = crate::rpc::submit_turn_rpc(turn.clone()).await;
+    for _ in 0..CONFLICT_RETRIES {
+        match result {
+            Err(ServerFnError::ServerError { code: 409, .. }) => {
+                result = crate::rpc::submit_turn_rpc(turn.clone()).await;
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Sends a turn to the server, or keeps it on this device until we're back online.
+pub async fn submit_turn(turn: WithId<SomeTurn>) {
+    match send_turn(&turn).await {
+        Ok(()) => {}
+        Err(error) if is_offline(&error) => {
+            apply_local_turn(&turn);
+            let mut turns = queued_turns();
+            turns.push(turn);
+            save_turns(&turns);
+        }
+        Err(error) => report_conflict(&turn, error).await,
+    }
+}
+
+async fn flush_turns() {
+    // Turns are sent in the order they were made. Anything the server rejects is reported and
+    // dropped along with the later turns in that game, which were made on top of it, but a network
+    // failure leaves the rest of the queue for the next reconnect.
+    let mut handled = 0;
+    let mut rejected = HashSet::new();
+    for turn in queued_turns() {
+        if !rejected.contains(&turn.id) {
+            match send_turn(&turn).await {
+                Ok(()) => {}
+                Err(error) if is_offline(&error) => break,
+                Err(error) => {
+                    rejected.insert(turn.id);
+                    report_conflict(&turn, error).await;
+                }
+            }
+        }
+        handled += 1;
+    }
+    let mut turns = queued_turns();
+    turns.drain(..handled.min(turns.len()));
+    save_turns(&turns);
+}
+
+async fn report_conflict(turn: &WithId<SomeTurn>, error: ServerFnError) {
+    let reason = match error {
+        ServerFnError::ServerError { message, .. } => message,
+        error => error.to_string(),
+    };
+    let game = match crate::rpc::fetch_games().await {
+        Ok(games) => {
+            refresh_games(&games);
+            games.into_iter().find(|game| game.id == Some(turn.id))
+        }
+        Err(_) => None,
+    };
+    let player = cached_player().unwrap_or_default();
+    let message = match game.map(|game| game.game) {
+        Some(GameOrRequest::Completed(game)) => format!(
+            "Your game against {} ended before your move could be sent.",
+            game.opponent(&player).name
+        ),
+        Some(GameOrRequest::Game(game)) => format!(
+            "Your move against {} was rejected: {reason}",
+            game.opponent(&player).name
+        ),
+        _ => format!("Your move could not be sent: {reason}"),
+    };
+    SYNC.write().conflicts.push(message);
+}
+
+/// Resolves once the browser's connectivity matches `online`.
+pub async fn wait_until(online: bool) {
+    let Some(window) = window() else {
+        return;
+    };
+    if window.navigator().on_line() == online {
+        return;
+    }
+    let (sender, receiver) = oneshot::channel();
+    let event = if online { "online" } else { "offline" };
+    let _listener = EventListener::once(&window, event, move |_| {
+        let _ = sender.send(());
+    });
+    let _ = receiver.await;
+}
+
+#[component]
+pub fn SyncStatus() -> Element {
+    use_future(|| async {
+        SYNC.write().queued = queued_turns().len();
+        loop {
+            wait_until(true).await;
+            SYNC.write().online = true;
+            flush_turns().await;
+            wait_until(false).await;
+            SYNC.write().online = false;
+        }
+    });
+
+    let SyncState {
+        online,
+        queued,
+        conflicts,
+    } = SYNC();
+
+    rsx! {
+        div {
+            class: "syncStatus",
+            if !online {
+                div { "You're offline. Showing the games saved on this device." }
+            }
+            if queued > 0 {
+                div { "{queued} move(s) will be sent when you reconnect." }
+            }
+            for (i, conflict) in conflicts.into_iter().enumerate() {
+                div {
+                    class: "conflict",
+                    "{conflict}"
+                    button {
+                        onclick: move |_| {
+                            SYNC.write().conflicts.remove(i);
+                        },
+                        "Dismiss"
+                    }
+                }
+            }
+        }
+    }
+}
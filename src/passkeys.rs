@@ -0,0 +1,146 @@
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{CredentialCreationOptions, CredentialRequestOptions, window};
+
+use account::{PasskeyAssertion, PasskeyChallenge, PasskeyRegistration};
+
+use crate::prelude::*;
+
+/// Site name authenticators show when saving a passkey
+const RP_NAME: &str = "Duck Chess";
+
+fn object(fields: &[(&str, JsValue)]) -> JsValue {
+    let object = Object::new();
+    for (key, value) in fields {
+        Reflect::set(&object, &(*key).into(), value).unwrap();
+    }
+    object.into()
+}
+
+fn bytes(bytes: &[u8]) -> JsValue {
+    Uint8Array::from(bytes).into()
+}
+
+/// Reads an ArrayBuffer field, like the parts of a credential's response
+fn field(value: &JsValue, path: &[&str]) -> Vec<u8> {
+    let mut value = value.clone();
+    for key in path {
+        value = Reflect::get(&value, &(*key).into()).unwrap();
+    }
+    Uint8Array::new(&value).to_vec()
+}
+
+fn descriptors(credentials: &[Vec<u8>]) -> JsValue {
+    credentials
+        .iter()
+        .map(|id| object(&[("type", "public-key".into()), ("id", bytes(id))]))
+        .collect::<Array>()
+        .into()
+}
+
+fn error_message(error: JsValue) -> String {
+    error
+        .dyn_ref::<js_sys::Error>()
+        .map(|error| String::from(error.message()))
+        .unwrap_or_else(|| "The passkey couldn't be used".into())
+}
+
+/// Makes a passkey for the logged in player and adds it to their account, which needs their
+/// password
+pub async fn register(name: String, password: String) -> Result<(), String> {
+    let PasskeyChallenge {
+        challenge,
+        rp_id,
+        user_id,
+        user_name,
+        credentials,
+    } = crate::rpc::start_passkey_registration(password.clone())
+        .await
+        .map_err(|error| error.to_string())?;
+    let public_key = object(&[
+        ("challenge", bytes(&challenge)),
+        (
+            "rp",
+            object(&[("id", rp_id.into()), ("name", RP_NAME.into())]),
+        ),
+        (
+            "user",
+            object(&[
+                ("id", bytes(&user_id)),
+                ("name", user_name.clone().into()),
+                ("displayName", user_name.into()),
+            ]),
+        ),
+        (
+            "pubKeyCredParams",
+            Array::of1(&object(&[
+                ("type", "public-key".into()),
+                ("alg", (-7).into()),
+            ]))
+            .into(),
+        ),
+        ("excludeCredentials", descriptors(&credentials)),
+        (
+            "authenticatorSelection",
+            object(&[
+                ("residentKey", "preferred".into()),
+                ("userVerification", "required".into()),
+            ]),
+        ),
+        ("attestation", "none".into()),
+    ]);
+    let options: CredentialCreationOptions = object(&[("publicKey", public_key)]).unchecked_into();
+    let promise = window()
+        .unwrap()
+        .navigator()
+        .credentials()
+        .create_with_options(&options)
+        .map_err(error_message)?;
+    let credential = JsFuture::from(promise).await.map_err(error_message)?;
+    crate::rpc::finish_passkey_registration(
+        PasskeyRegistration {
+            name,
+            client_data: field(&credential, &["response", "clientDataJSON"]),
+            attestation_object: field(&credential, &["response", "attestationObject"]),
+        },
+        password,
+    )
+    .await
+    .map_err(|error| error.to_string())
+}
+
+/// Logs in with one of the player's passkeys, which starts a session just like a password would
+pub async fn login(name: String) -> Result<Player, String> {
+    let PasskeyChallenge {
+        challenge,
+        rp_id,
+        credentials,
+        ..
+    } = crate::rpc::start_passkey_login(name.clone())
+        .await
+        .map_err(|error| error.to_string())?;
+    let public_key = object(&[
+        ("challenge", bytes(&challenge)),
+        ("rpId", rp_id.into()),
+        ("allowCredentials", descriptors(&credentials)),
+        ("userVerification", "required".into()),
+    ]);
+    let options: CredentialRequestOptions = object(&[("publicKey", public_key)]).unchecked_into();
+    let promise = window()
+        .unwrap()
+        .navigator()
+        .credentials()
+        .get_with_options(&options)
+        .map_err(error_message)?;
+    let credential = JsFuture::from(promise).await.map_err(error_message)?;
+    crate::rpc::finish_passkey_login(PasskeyAssertion {
+        name,
+        credential: field(&credential, &["rawId"]),
+        client_data: field(&credential, &["response", "clientDataJSON"]),
+        authenticator_data: field(&credential, &["response", "authenticatorData"]),
+        signature: field(&credential, &["response", "signature"]),
+    })
+    .await
+    .map_err(|error| error.to_string())
+}
@@ -0,0 +1,102 @@
+use game::GameTypes;
+use profile::Record;
+
+use crate::{board::some_game_preview, prelude::*, ratings::RatingSummary, route::Route};
+
+#[component]
+pub fn Profile(name: String) -> Element {
+    let mut page = use_signal(|| 0u64);
+    let profile = use_resource({
+        let name = name.clone();
+        move || crate::rpc::fetch_profile(name.clone())
+    });
+    let games = use_resource({
+        let name = name.clone();
+        move || crate::rpc::fetch_completed_games(name.clone(), page())
+    });
+
+    let profile = match profile.value()() {
+        Some(Ok(profile)) => profile,
+        Some(Err(_)) => return rsx! { div { class: "profile", "No player named {name}" } },
+        None => return rsx! {},
+    };
+
+    let mut previews = Vec::new();
+    let mut more = false;
+    if let Some(Ok(page)) = games.value()() {
+        more = page.more;
+        for any_game in page.games {
+            if let GameOrRequest::Completed(game) = &any_game.game {
+                previews.push(some_game_preview(any_game.id.unwrap().to_string(), game));
+            }
+        }
+    }
+
+    rsx! {
+        div {
+            class: "profile",
+            div {
+                class: "header",
+                div {
+                    h1 { "{profile.player.name}" }
+                    RatingSummary { player: profile.player.clone() }
+                }
+                div {
+                    class: "buttonMenu",
+                    Link { to: Route::MainMenu {}, "Back" }
+                }
+            }
+            h2 { "Results" }
+            table {
+                tr {
+                    th {}
+                    for color in Color::all() {
+                        th { "As {color:?}" }
+                    }
+                }
+                for game_type in GameTypes::all() {
+                    tr {
+                        th { "{game_type:?}" }
+                        for color in Color::all() {
+                            td { {record_text(profile.record(game_type, color))} }
+                        }
+                    }
+                }
+            }
+            if !profile.head_to_head.is_empty() {
+                h2 { "Head to head" }
+                table {
+                    for versus in profile.head_to_head {
+                        tr {
+                            td {
+                                Link {
+                                    to: Route::Profile { name: versus.opponent.name.clone() },
+                                    "{versus.opponent.name}"
+                                }
+                            }
+                            td { {record_text(versus.record)} }
+                        }
+                    }
+                }
+            }
+            h2 { "Completed Games" }
+            div {
+                class: "newGamePreviews",
+                {previews.into_iter()}
+            }
+            div {
+                class: "pages",
+                if page() > 0 {
+                    button { onclick: move |_| page -= 1, "Newer" }
+                }
+                if more {
+                    button { onclick: move |_| page += 1, "Older" }
+                }
+            }
+        }
+    }
+}
+
+fn record_text(record: Record) -> String {
+    format!("{}W {}D {}L", record.wins, record.draws, record.losses)
+}
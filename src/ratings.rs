@@ -0,0 +1,41 @@
+use game::GameTypes;
+
+use crate::prelude::*;
+
+#[component]
+pub fn RatingSummary(player: Player) -> Element {
+    let features = use_features();
+    let ratings = use_resource(move || {
+        let id = player.id.unwrap().to_string();
+        async move { crate::rpc::fetch_ratings(id).await.unwrap_or_default() }
+    });
+    let ratings = ratings.value()().unwrap_or_default();
+    if !features.ratings {
+        return rsx! {};
+    }
+
+    rsx! {
+        div {
+            class: "ratings",
+            for game_type in GameTypes::all() {
+                span {
+                    {rating_text(game_type, &ratings)}
+                }
+            }
+        }
+    }
+}
+
+pub fn rating_text(game_type: GameTypes, ratings: &[RatingRecord]) -> String {
+    let rating = ratings
+        .iter()
+        .find(|record| record.game_type == game_type)
+        .map_or_else(Rating::default, |record| record.current);
+    format!("{game_type:?}: {}", format_rating(rating))
+}
+
+pub fn format_rating(rating: Rating) -> String {
+    // Glicko treats a deviation this high as not knowing much about the player yet
+    let provisional = if rating.deviation > 110.0 { "?" } else { "" };
+    format!("{:.0}{provisional}", rating.rating.round())
+}
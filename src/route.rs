@@ -1,7 +1,12 @@
+use crate::account::Account;
+use crate::email::{ResetPassword, VerifyEmail};
 use crate::ingame::InGame;
+use crate::leaderboard::Leaderboard;
 use crate::mainmenu::MainMenu;
 use crate::newgame::NewGame;
 use crate::prelude::*;
+use crate::profile::Profile;
+use crate::tournaments::{TournamentPage, Tournaments};
 
 // #[rustfmt::skip]
 #[derive(Clone, Debug, PartialEq, Routable)]
@@ -12,4 +17,18 @@ pub enum Route {
     InGame { id: String },
     #[route("/ui/newgame")]
     NewGame {},
+    #[route("/ui/player/:name")]
+    Profile { name: String },
+    #[route("/ui/leaderboard")]
+    Leaderboard {},
+    #[route("/ui/tournaments")]
+    Tournaments {},
+    #[route("/ui/tournament/:id")]
+    TournamentPage { id: String },
+    #[route("/ui/account")]
+    Account {},
+    #[route("/ui/verify/:token")]
+    VerifyEmail { token: String },
+    #[route("/ui/reset/:token")]
+    ResetPassword { token: String },
 }
@@ -1,31 +1,116 @@
 use dioxus::prelude::*;
 
 use crate::prelude::*;
+use account::{
+    ApiTokenInfo, EmailStatus, Features, LoginStep, NotificationSettings, PasskeyAssertion,
+    PasskeyChallenge, PasskeyInfo, PasskeyRegistration, SessionInfo, TotpSetup, WebhookInfo,
+};
+use chat::{ChatMessage, ChatSettings};
+use game::GameTypes;
+use profile::{GamePage, PlayerProfile};
+use seek::Seek;
+use tournament::{Tournament, TournamentFormat};
 
 #[cfg(feature = "server")]
-use crate::server::state::{DB, Notifier, SessionRecord};
+use crate::server::{
+    bots,
+    chat::Chats,
+    email::{self, Mailer},
+    leaderboard::Leaderboards,
+    limits::{ClientIp, Limiter},
+    matchmaking::Matchmaker,
+    notifications::{self, Notifier},
+    passkeys::{self, Challenges, RelyingParty},
+    spectators::Spectators,
+    state::{DB, SessionRecord},
+    storage::{Games, Players, Sessions, server_error},
+    webhooks,
+};
 
 #[post("/rpc/session", session: Option<SessionRecord>)]
 pub async fn fetch_session() -> ServerFnResult<Option<Player>> {
     Ok(session.map(|session| session.player))
 }
 
-#[get("/rpc/games", session: SessionRecord, games: DB<AnyGame>)]
+#[get("/rpc/features", ratings: Option<DB<RatingRecord>>, tournaments: Option<DB<Tournament>>, chats: Option<Extension<Chats>>)]
+pub async fn fetch_features() -> ServerFnResult<Features> {
+    Ok(Features {
+        ratings: ratings.is_some(),
+        tournaments: tournaments.is_some(),
+        chat: chats.is_some(),
+    })
+}
+
+#[get("/rpc/games", session: SessionRecord, games: Games)]
 pub async fn fetch_games() -> ServerFnResult<Vec<AnyGame>> {
-    Ok(crate::server::games::get_player_games(&session.player, &games).await?)
+    Ok(crate::server::games::get_player_games(&session.player, &**games).await?)
 }
 
-#[get("/rpc/games/open", _: SessionRecord, games: DB<AnyGame>)]
+#[get("/rpc/games/open", _: SessionRecord, games: Games)]
 pub async fn fetch_open_games() -> ServerFnResult<Vec<WithId<GameRequest>>> {
-    Ok(crate::server::games::get_open_games(&games).await?)
+    Ok(crate::server::games::get_open_games(&**games).await?)
+}
+
+#[get("/rpc/games/public", session: SessionRecord, games: Games)]
+pub async fn fetch_public_games() -> ServerFnResult<Vec<AnyGame>> {
+    Ok(crate::server::games::get_public_games(&session.player, &**games).await?)
+}
+
+#[post("/rpc/games/new", session: SessionRecord, games: Games)]
+pub async fn create_game(
+    game_type: GameTypes,
+    visibility: Visibility,
+    rated: bool,
+    time_control: TimeControl,
+) -> ServerFnResult<ObjectId> {
+    Ok(crate::server::games::new_open_game(
+        session.player,
+        game_type,
+        visibility,
+        rated,
+        time_control,
+        &**games,
+    )
+    .await?)
+}
+
+#[post("/rpc/games/play_now", session: SessionRecord, games: Games, notifier: Extension<Notifier>, ratings: Option<DB<RatingRecord>>, matchmaker: Extension<Matchmaker>)]
+pub async fn play_now(seek: Seek) -> Result<JsonStream<ObjectId>> {
+    use async_stream::stream;
+
+    let player = session.player;
+    let rating = match ratings {
+        Some(ratings) => {
+            crate::server::ratings::get_rating(player.id.unwrap(), seek.game_type, &ratings).await?
+        }
+        None => Rating::default(),
+    };
+    if let Some(opponent) = matchmaker.find(&player, &seek, rating.rating) {
+        let game_id =
+            crate::server::matchmaking::start_match(opponent, player, seek, &**games, &notifier)
+                .await?;
+        return Ok(JsonStream::new(stream! { yield game_id; }));
+    }
+
+    let (guard, found) = matchmaker.wait(player, seek, rating.rating);
+    let game_stream = stream! {
+        // Leaving the queue is as simple as closing the connection
+        let _guard = guard;
+        if let Ok(game_id) = found.await {
+            yield game_id;
+        }
+    };
+    Ok(JsonStream::new(game_stream))
 }
 
-#[post("/rpc/games/new", session: SessionRecord, games: DB<AnyGame>)]
-pub async fn create_game() -> ServerFnResult<ObjectId> {
-    Ok(crate::server::games::new_open_game(session.player, &games).await?)
+#[post("/rpc/games/visibility", session: SessionRecord, games: Games)]
+pub async fn set_visibility_rpc(game_id: String, visibility: Visibility) -> Result<()> {
+    let game_id = ObjectId::parse_str(game_id)?;
+    crate::server::games::set_visibility(game_id, visibility, &session.player, &**games).await?;
+    Ok(())
 }
 
-#[post("/rpc/games/join", session: SessionRecord, games: DB<AnyGame>, sessions: DB<SessionRecord>, notifier: Extension<Notifier>)]
+#[post("/rpc/games/join", session: SessionRecord, games: Games, notifier: Extension<Notifier>)]
 pub async fn join_game_rpc(game_id: String) -> ServerFnResult<()> {
     let game_id =
         bson::oid::ObjectId::parse_str(game_id).map_err(|error| ServerFnError::ServerError {
@@ -33,16 +118,179 @@ pub async fn join_game_rpc(game_id: String) -> ServerFnResult<()> {
             code: 400,
             details: None,
         })?;
-    crate::server::games::join_open_game(game_id, session.player, &games, &sessions, &notifier)
+    crate::server::games::join_open_game(game_id, session.player, &**games, &notifier)
         .await
-        .map_err(ServerFnError::from)
+        .map_err(server_error)
 }
 
-#[post("/rpc/games/turn", session: SessionRecord, games: DB<AnyGame>, sessions: DB<SessionRecord>, notifier: Extension<Notifier>)]
+#[post("/rpc/games/turn", session: SessionRecord, games: Games, notifier: Extension<Notifier>, ratings: Option<DB<RatingRecord>>, tournaments: Option<DB<Tournament>>)]
 pub async fn submit_turn_rpc(turn: WithId<SomeTurn>) -> ServerFnResult<()> {
-    crate::server::games::apply_turn(turn, session.player, &sessions, &notifier, &games)
-        .await
-        .map_err(ServerFnError::from)
+    crate::server::games::apply_turn(
+        turn,
+        session.player,
+        &notifier,
+        &**games,
+        ratings.as_deref(),
+        tournaments.as_deref(),
+    )
+    .await
+    .map_err(server_error)
+}
+
+#[post("/rpc/ratings", _: SessionRecord, ratings: DB<RatingRecord>)]
+pub async fn fetch_ratings(player_id: String) -> Result<Vec<RatingRecord>> {
+    let player_id = ObjectId::parse_str(player_id)?;
+    Ok(crate::server::ratings::get_ratings(player_id, &ratings).await?)
+}
+
+#[get("/rpc/tournaments", _: SessionRecord, tournaments: DB<Tournament>)]
+pub async fn fetch_tournaments() -> Result<Vec<Tournament>> {
+    Ok(crate::server::tournaments::get_tournaments(&tournaments).await?)
+}
+
+#[post("/rpc/tournaments/get", _: SessionRecord, tournaments: DB<Tournament>)]
+pub async fn fetch_tournament(tournament_id: String) -> Result<Tournament> {
+    let tournament_id = ObjectId::parse_str(tournament_id)?;
+    Ok(crate::server::tournaments::get_tournament(tournament_id, &tournaments).await?)
+}
+
+#[post("/rpc/tournaments/new", session: SessionRecord, tournaments: DB<Tournament>)]
+pub async fn create_tournament(
+    name: String,
+    game_type: GameTypes,
+    time_control: TimeControl,
+    format: TournamentFormat,
+    rated: bool,
+) -> Result<ObjectId> {
+    Ok(crate::server::tournaments::new_tournament(
+        session.player,
+        name,
+        game_type,
+        time_control,
+        format,
+        rated,
+        &tournaments,
+    )
+    .await?)
+}
+
+#[post("/rpc/tournaments/register", session: SessionRecord, tournaments: DB<Tournament>)]
+pub async fn set_registered(tournament_id: String, registered: bool) -> Result<()> {
+    let tournament_id = ObjectId::parse_str(tournament_id)?;
+    crate::server::tournaments::set_registered(
+        tournament_id,
+        session.player,
+        registered,
+        &tournaments,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/rpc/tournaments/start", session: SessionRecord, tournaments: DB<Tournament>, games: Games, notifier: Extension<Notifier>)]
+pub async fn start_tournament(tournament_id: String) -> Result<()> {
+    let tournament_id = ObjectId::parse_str(tournament_id)?;
+    crate::server::tournaments::start_tournament(
+        tournament_id,
+        &session.player,
+        &tournaments,
+        &**games,
+        &notifier,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/rpc/leaderboard", _: SessionRecord, leaderboards: Extension<Leaderboards>, ratings: DB<RatingRecord>, players: Players)]
+pub async fn fetch_leaderboard(
+    game_type: GameTypes,
+    active_only: bool,
+    min_games: u32,
+) -> Result<Vec<LeaderboardEntry>> {
+    Ok(leaderboards
+        .get(game_type, active_only, min_games, &ratings, &**players)
+        .await?)
+}
+
+#[post("/rpc/profile", session: SessionRecord, players: Players, games: Games)]
+pub async fn fetch_profile(name: String) -> Result<PlayerProfile> {
+    let player = crate::server::profiles::find_player(&name, &**players).await?;
+    Ok(crate::server::profiles::get_profile(player, &session.player, &**games).await?)
+}
+
+#[post("/rpc/profile/games", session: SessionRecord, players: Players, games: Games)]
+pub async fn fetch_completed_games(name: String, page: u64) -> Result<GamePage> {
+    let player = crate::server::profiles::find_player(&name, &**players).await?;
+    Ok(crate::server::profiles::get_completed_games(
+        player.id.unwrap(),
+        page,
+        &session.player,
+        &**games,
+    )
+    .await?)
+}
+
+#[post("/rpc/chat/send", session: SessionRecord, games: Games, chats: Extension<Chats>, notifier: Extension<Notifier>)]
+pub async fn send_chat(game_id: String, text: String) -> Result<()> {
+    let game_id = ObjectId::parse_str(game_id)?;
+    let recipient = crate::server::chat::send_message(
+        game_id,
+        text,
+        &session.player,
+        &**games,
+        &chats.messages,
+        &notifier.webhooks,
+    )
+    .await?;
+    if let Some(recipient) = recipient {
+        crate::server::chat::notify_recipient(
+            game_id,
+            recipient,
+            &session.player,
+            &chats.settings,
+            &notifier,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[get("/rpc/chat/settings", session: SessionRecord, chats: Extension<Chats>)]
+pub async fn fetch_chat_settings() -> Result<ChatSettings> {
+    Ok(crate::server::chat::get_settings(session.player.id.unwrap(), &chats.settings).await?)
+}
+
+#[post("/rpc/chat/notify", session: SessionRecord, chats: Extension<Chats>)]
+pub async fn set_chat_notify(notify: bool) -> Result<()> {
+    crate::server::chat::set_notify(session.player.id.unwrap(), notify, &chats.settings).await?;
+    Ok(())
+}
+
+#[post("/rpc/chat/mute", session: SessionRecord, chats: Extension<Chats>)]
+pub async fn set_chat_muted(player_id: String, muted: bool) -> Result<()> {
+    let player_id = ObjectId::parse_str(player_id)?;
+    crate::server::chat::set_muted(
+        session.player.id.unwrap(),
+        player_id,
+        muted,
+        &chats.settings,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/rpc/chat/report", session: SessionRecord, games: Games, chats: Extension<Chats>)]
+pub async fn report_chat(message_id: String) -> Result<()> {
+    let message_id = ObjectId::parse_str(message_id)?;
+    crate::server::chat::report_message(
+        message_id,
+        session.player,
+        &**games,
+        &chats.messages,
+        &chats.reports,
+    )
+    .await?;
+    Ok(())
 }
 
 #[get("/rpc/notifications/enabled", session: SessionRecord)]
@@ -51,48 +299,310 @@ pub async fn notifications_enabled() -> ServerFnResult<bool> {
 }
 
 #[get("/rpc/notifications/public-key", notifier: Extension<Notifier>)]
-pub async fn public_key_rpc() -> ServerFnResult<String> {
-    Ok(base64_url::encode(&notifier.crypto.get_public_key()))
+pub async fn public_key_rpc() -> Result<String> {
+    Ok(notifications::public_key(&notifier)?)
 }
 
-#[post("/rpc/notifications/subscribe", session: SessionRecord, sessions: DB<SessionRecord>)]
+#[post("/rpc/notifications/subscribe", session: SessionRecord, sessions: Sessions)]
 pub async fn subscribe_rpc(subscription_json: String) -> ServerFnResult<()> {
     let subscription = serde_json::from_str(&subscription_json).map_err(ServerFnError::from)?;
-    update_session(subscription, session, &sessions)
+    update_session(subscription, session, &**sessions)
         .await
         .map_err(ServerFnError::from)
 }
 
-#[post("/rpc/signup", jar: Cookies, players: DB<Player>, sessions: DB<SessionRecord>)]
-pub async fn signup(player: PasswordPlayer) -> Result<Player> {
-    let player = new_user(&players, player.name.clone(), player.password.clone()).await?;
-    let cookie = create_session_cookie(player.clone(), &sessions).await?;
+#[post("/rpc/notifications/unsubscribe", session: SessionRecord, sessions: Sessions)]
+pub async fn unsubscribe_rpc() -> Result<()> {
+    disable_push(&session, session.id.unwrap(), &**sessions).await?;
+    Ok(())
+}
+
+#[post("/rpc/notifications/device/disable", session: SessionRecord, sessions: Sessions)]
+pub async fn disable_device_push_rpc(id: String) -> Result<()> {
+    let id = ObjectId::parse_str(id)?;
+    disable_push(&session, id, &**sessions).await?;
+    Ok(())
+}
+
+#[get("/rpc/notifications/settings", session: SessionRecord, players: Players)]
+pub async fn fetch_notification_settings() -> Result<NotificationSettings> {
+    Ok(notifications::get_settings(&session.player, &**players).await?)
+}
+
+#[post("/rpc/notifications/settings", session: SessionRecord, players: Players)]
+pub async fn set_notification_settings(settings: NotificationSettings) -> Result<()> {
+    notifications::set_settings(&session.player, settings, &**players).await?;
+    Ok(())
+}
+
+#[post("/rpc/signup", jar: Cookies, players: Players, sessions: Sessions, limiter: Extension<Limiter>, ip: ClientIp)]
+pub async fn signup(player: PasswordPlayer) -> ServerFnResult<Player> {
+    let player = new_user(
+        &**players,
+        &limiter,
+        &ip,
+        player.name.clone(),
+        player.password.clone(),
+    )
+    .await
+    .map_err(server_error)?;
+    let cookie = create_session_cookie(player.clone(), &**sessions)
+        .await
+        .map_err(server_error)?;
     jar.add(cookie);
     Ok(player)
 }
 
-#[post("/rpc/login", jar: Cookies, players: DB<Player>, sessions: DB<SessionRecord>)]
-pub async fn login(player: PasswordPlayer) -> dioxus::prelude::Result<Player> {
-    let player = login_user(&players, player.name.clone(), player.password.clone()).await?;
-    let cookie = create_session_cookie(player.clone(), &sessions).await?;
+#[post("/rpc/login", jar: Cookies, players: Players, sessions: Sessions, limiter: Extension<Limiter>, ip: ClientIp)]
+pub async fn login(player: PasswordPlayer, code: Option<String>) -> ServerFnResult<LoginStep> {
+    let step = login_user(
+        &**players,
+        &limiter,
+        &ip,
+        player.name.clone(),
+        player.password.clone(),
+        code,
+    )
+    .await
+    .map_err(server_error)?;
+    if let LoginStep::LoggedIn(player) = &step {
+        let cookie = create_session_cookie(player.clone(), &**sessions)
+            .await
+            .map_err(server_error)?;
+        jar.add(cookie);
+    }
+    Ok(step)
+}
+
+#[post("/rpc/passkeys/login/start", players: Players, challenges: Extension<Challenges>, rp: RelyingParty)]
+pub async fn start_passkey_login(name: String) -> Result<PasskeyChallenge> {
+    Ok(passkeys::start_login(&name, &**players, &challenges, &rp).await?)
+}
+
+#[post("/rpc/passkeys/login/finish", jar: Cookies, players: Players, sessions: Sessions, challenges: Extension<Challenges>, rp: RelyingParty)]
+pub async fn finish_passkey_login(assertion: PasskeyAssertion) -> Result<Player> {
+    let player = passkeys::finish_login(assertion, &**players, &challenges, &rp).await?;
+    let cookie = create_session_cookie(player.clone(), &**sessions).await?;
     jar.add(cookie);
     Ok(player)
 }
 
-#[post("/rpc/logout", session: SessionRecord, jar: Cookies, sessions: DB<SessionRecord>)]
+#[post("/rpc/logout", session: SessionRecord, jar: Cookies, sessions: Sessions)]
 pub async fn logout() -> Result<()> {
-    clear_player_sessions(&session, &sessions).await?;
+    clear_player_sessions(&session, &**sessions).await?;
     jar.remove(removal_cookie());
     Ok(())
 }
 
-#[post("/rpc/game_events", session: SessionRecord, games: DB<AnyGame>)]
+#[post("/rpc/logout/device", session: SessionRecord, jar: Cookies, sessions: Sessions)]
+pub async fn logout_device() -> Result<()> {
+    revoke_session(&session, session.id.unwrap(), &**sessions).await?;
+    jar.remove(removal_cookie());
+    Ok(())
+}
+
+#[get("/rpc/account/sessions", session: SessionRecord, sessions: Sessions)]
+pub async fn fetch_sessions() -> Result<Vec<SessionInfo>> {
+    Ok(list_sessions(&session, &**sessions).await?)
+}
+
+#[post("/rpc/account/sessions/revoke", session: SessionRecord, sessions: Sessions)]
+pub async fn revoke_session_rpc(id: String) -> Result<()> {
+    let id = ObjectId::parse_str(id)?;
+    revoke_session(&session, id, &**sessions).await?;
+    Ok(())
+}
+
+#[post("/rpc/account/password", session: SessionRecord, players: Players, limiter: Extension<Limiter>)]
+pub async fn change_password_rpc(current: String, new: String) -> Result<()> {
+    change_password(&session.player, &current, &new, &**players, &limiter).await?;
+    Ok(())
+}
+
+#[get("/rpc/account/two_factor", session: SessionRecord, players: Players)]
+pub async fn fetch_two_factor_rpc() -> Result<bool> {
+    Ok(two_factor_enabled(&session.player, &**players).await?)
+}
+
+#[post("/rpc/account/two_factor/start", session: SessionRecord, players: Players, limiter: Extension<Limiter>)]
+pub async fn start_two_factor_rpc(password: String) -> Result<TotpSetup> {
+    Ok(start_two_factor(&session.player, &password, &**players, &limiter).await?)
+}
+
+#[post("/rpc/account/two_factor/confirm", session: SessionRecord, players: Players)]
+pub async fn confirm_two_factor_rpc(code: String) -> Result<Vec<String>> {
+    Ok(confirm_two_factor(&session.player, &code, &**players).await?)
+}
+
+#[post("/rpc/account/two_factor/disable", session: SessionRecord, players: Players, limiter: Extension<Limiter>)]
+pub async fn disable_two_factor_rpc(password: String) -> Result<()> {
+    disable_two_factor(&session.player, &password, &**players, &limiter).await?;
+    Ok(())
+}
+
+#[get("/rpc/account/passkeys", session: SessionRecord, players: Players)]
+pub async fn fetch_passkeys() -> Result<Vec<PasskeyInfo>> {
+    Ok(passkeys::list_passkeys(&session.player, &**players).await?)
+}
+
+#[post("/rpc/account/passkeys/start", session: SessionRecord, players: Players, limiter: Extension<Limiter>, challenges: Extension<Challenges>, rp: RelyingParty)]
+pub async fn start_passkey_registration(password: String) -> Result<PasskeyChallenge> {
+    Ok(passkeys::start_registration(
+        &session.player,
+        &password,
+        &**players,
+        &limiter,
+        &challenges,
+        &rp,
+    )
+    .await?)
+}
+
+#[post("/rpc/account/passkeys/finish", session: SessionRecord, players: Players, limiter: Extension<Limiter>, challenges: Extension<Challenges>, rp: RelyingParty)]
+pub async fn finish_passkey_registration(
+    registration: PasskeyRegistration,
+    password: String,
+) -> Result<()> {
+    passkeys::finish_registration(
+        &session.player,
+        &password,
+        registration,
+        &**players,
+        &limiter,
+        &challenges,
+        &rp,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/rpc/account/passkeys/remove", session: SessionRecord, players: Players)]
+pub async fn remove_passkey_rpc(id: String) -> Result<()> {
+    passkeys::remove_passkey(&session.player, &id, &**players).await?;
+    Ok(())
+}
+
+#[get("/rpc/account/tokens", session: SessionRecord, players: Players)]
+pub async fn fetch_api_tokens() -> Result<Vec<ApiTokenInfo>> {
+    Ok(bots::list_tokens(&session.player, &**players).await?)
+}
+
+#[post("/rpc/account/tokens/create", session: SessionRecord, players: Players)]
+pub async fn create_api_token(name: String) -> Result<String> {
+    Ok(bots::create_token(&session.player, name, &**players).await?)
+}
+
+#[post("/rpc/account/tokens/revoke", session: SessionRecord, players: Players)]
+pub async fn revoke_api_token(id: String) -> Result<()> {
+    bots::revoke_token(&session.player, &id, &**players).await?;
+    Ok(())
+}
+
+#[get("/rpc/account/webhooks", session: SessionRecord, players: Players, notifier: Extension<Notifier>)]
+pub async fn fetch_webhooks() -> Result<Vec<WebhookInfo>> {
+    Ok(webhooks::list_webhooks(&session.player, &**players, &notifier.webhooks).await?)
+}
+
+#[post("/rpc/account/webhooks/add", session: SessionRecord, players: Players, notifier: Extension<Notifier>)]
+pub async fn add_webhook_rpc(url: String) -> Result<()> {
+    webhooks::add_webhook(&session.player, url, &**players, &notifier.webhooks).await?;
+    Ok(())
+}
+
+#[post("/rpc/account/webhooks/remove", session: SessionRecord, players: Players, notifier: Extension<Notifier>)]
+pub async fn remove_webhook_rpc(id: String) -> Result<()> {
+    webhooks::remove_webhook(&session.player, &id, &**players, &notifier.webhooks).await?;
+    Ok(())
+}
+
+#[get("/rpc/account/email", session: SessionRecord, players: Players, mailer: Option<Extension<Mailer>>)]
+pub async fn fetch_email_rpc() -> Result<EmailStatus> {
+    Ok(email::email_status(&session.player, &**players, mailer.as_deref()).await?)
+}
+
+#[post("/rpc/account/email", session: SessionRecord, players: Players, limiter: Extension<Limiter>, mailer: Option<Extension<Mailer>>)]
+pub async fn set_email_rpc(address: String, password: String) -> Result<()> {
+    email::set_email(
+        &session.player,
+        address,
+        &password,
+        &**players,
+        &limiter,
+        mailer.as_deref(),
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/rpc/account/email/digest", session: SessionRecord, players: Players)]
+pub async fn set_digest_rpc(digest: bool) -> Result<()> {
+    email::set_digest(&session.player, digest, &**players).await?;
+    Ok(())
+}
+
+#[post("/rpc/email/verify", players: Players)]
+pub async fn verify_email_rpc(token: String) -> Result<()> {
+    email::verify_email(&token, &**players).await?;
+    Ok(())
+}
+
+#[post("/rpc/email/reset/request", players: Players, mailer: Option<Extension<Mailer>>, limiter: Extension<Limiter>)]
+pub async fn request_reset_rpc(name: String) -> ServerFnResult<()> {
+    email::request_reset(&name, players.0, mailer.as_deref(), &limiter)
+        .await
+        .map_err(server_error)
+}
+
+#[post("/rpc/email/reset", players: Players, sessions: Sessions)]
+pub async fn reset_password_rpc(token: String, password: String) -> Result<()> {
+    email::reset_password(&token, &password, &**players, &**sessions).await?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/rpc/account/name", session: SessionRecord, players: Players, limiter: Extension<Limiter>, games: Games, sessions: Sessions, chats: Option<Extension<Chats>>, tournaments: Option<DB<Tournament>>)]
+pub async fn change_name_rpc(name: String, password: String) -> Result<Player> {
+    Ok(change_name(
+        &session.player,
+        name,
+        &password,
+        &**players,
+        &limiter,
+        &**games,
+        &**sessions,
+        chats.as_deref(),
+        tournaments.as_deref(),
+    )
+    .await?)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/rpc/account/delete", session: SessionRecord, players: Players, limiter: Extension<Limiter>, games: Games, sessions: Sessions, chats: Option<Extension<Chats>>, ratings: Option<DB<RatingRecord>>, tournaments: Option<DB<Tournament>>, notifier: Extension<Notifier>)]
+pub async fn delete_account_rpc(password: String) -> Result<()> {
+    delete_account(
+        &session.player,
+        &password,
+        &**players,
+        &limiter,
+        &**games,
+        &**sessions,
+        chats.as_deref(),
+        ratings.as_deref(),
+        tournaments.as_deref(),
+        &notifier,
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/rpc/game_events", session: SessionRecord, games: Games)]
 pub async fn game_events(game_id: String) -> Result<JsonStream<AnyGame>> {
     use async_stream::stream;
 
     let game_id = ObjectId::parse_str(game_id)?;
     let (initial, mut change_stream) =
-        crate::server::games::create_change_stream(game_id, session.player.clone(), &games).await?;
+        crate::server::games::create_change_stream(game_id, session.player.clone(), &**games)
+            .await?;
 
     let event_stream = stream! {
         yield initial;
@@ -112,3 +622,88 @@ pub async fn game_events(game_id: String) -> Result<JsonStream<AnyGame>> {
 
     Ok(JsonStream::new(event_stream))
 }
+
+#[post("/rpc/game_events/chat", session: SessionRecord, games: Games, chats: Extension<Chats>)]
+pub async fn chat_events(game_id: String) -> Result<JsonStream<ChatMessage>> {
+    use async_stream::stream;
+
+    let game_id = ObjectId::parse_str(game_id)?;
+    let (history, mut change_stream) = crate::server::chat::create_chat_stream(
+        game_id,
+        &session.player,
+        &**games,
+        &chats.messages,
+    )
+    .await?;
+
+    let message_stream = stream! {
+        for message in history {
+            yield message;
+        }
+        loop {
+            match crate::server::chat::next_chat_message(&mut change_stream).await {
+                Ok(Some(message)) => yield message,
+                Ok(None) => {
+                    break;
+                }
+                Err(error) => {
+                    log::error!("chat stream failed: {error:?}");
+                    break;
+                }
+            }
+        }
+    };
+
+    Ok(JsonStream::new(message_stream))
+}
+
+#[post("/rpc/game_events/spectate", session: SessionRecord, games: Games, spectators: Extension<Spectators>)]
+pub async fn spectate_events(game_id: String) -> Result<JsonStream<AnyGame>> {
+    use async_stream::stream;
+
+    let game_id = ObjectId::parse_str(game_id)?;
+    let (initial, mut change_stream) =
+        crate::server::games::create_spectator_stream(game_id, &session.player, &**games).await?;
+    let guard = spectators.join(game_id);
+
+    let event_stream = stream! {
+        let _guard = guard;
+        yield initial;
+        loop {
+            match crate::server::games::next_spectator_update(&session.player, &mut change_stream).await {
+                Ok(Some(game)) => yield game,
+                Ok(None) => {
+                    break;
+                }
+                Err(error) => {
+                    log::error!("spectator event stream failed: {error:?}");
+                    break;
+                }
+            }
+        }
+    };
+
+    Ok(JsonStream::new(event_stream))
+}
+
+#[post("/rpc/game_events/spectators", session: SessionRecord, games: Games, spectators: Extension<Spectators>)]
+pub async fn spectator_count(game_id: String) -> Result<JsonStream<usize>> {
+    use async_stream::stream;
+
+    let game_id = ObjectId::parse_str(game_id)?;
+    // Only players get to see who's watching
+    crate::server::games::get_player_game(game_id, &session.player, &**games).await?;
+    let mut counts = spectators.watch(game_id);
+
+    let count_stream = stream! {
+        loop {
+            let count = *counts.receiver.borrow_and_update();
+            yield count;
+            if counts.receiver.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+
+    Ok(JsonStream::new(count_stream))
+}
@@ -1,110 +1,607 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use axum_extra::extract::cookie::Cookie;
-use dioxus::logger::tracing;
+use axum::{
+    extract::Request,
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tower_cookies::{Cookies, cookie::time};
 
-use super::{prelude::*, state::SessionRecord};
+use super::{
+    chat::{self, Chats},
+    games,
+    limits::{ClientIp, Key, Limiter},
+    notifications::Notifier,
+    prelude::*,
+    ratings,
+    state::SessionRecord,
+    storage::{GameStore, PlayerStore, SessionStore},
+    tournaments,
+};
+use crate::common::{
+    account::{
+        LoginStep, MAX_NAME, MAX_PASSWORD, MIN_PASSWORD, RECOVERY_CODES, SessionInfo, TotpSetup,
+        TwoFactor,
+    },
+    tournament::Tournament,
+};
 
 pub const TOKEN_COOKIE: &str = "token";
 
+/// Name authenticator apps show next to the codes
+const ISSUER: &str = "Duck Chess";
+
+/// What deleted players are called in the games they played
+const DELETED_NAME: &str = "Deleted player";
+
+/// Sessions expire after going this long without being used
+const SESSION_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Saving every use would mean a write on every request, so renewals are spaced out
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Seconds each code from an authenticator app lasts, the usual 30
+const TOTP_PERIOD: u64 = 30;
+
+/// Checks a login, refusing to even hash the password once there have been too many failures
+/// from the address or for the account. Players with two-factor authentication on also need a
+/// code, and only get logged in once it's right.
 pub async fn login_user(
-    players: &Collection<Player>,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    ip: &ClientIp,
     name: String,
     real_password: String,
-) -> Result<Player> {
-    let players = players.clone_with_type::<PasswordPlayer>();
-    tracing::warn!("{:?}", &players);
-    if let Some(found_player) = players.find_one(doc! {"name": &name}).await? {
+    code: Option<String>,
+) -> Result<LoginStep> {
+    let keys = [Key::Ip(ip.0.clone()), Key::Name(name.clone())];
+    limiter.check(&keys)?;
+    if let Some(found_player) = players.find_by_name(&name).await? {
         let hasher = HashBuilder::from_phc(&found_player.password)?;
         if hasher.is_valid(&real_password) {
-            Ok(Player {
+            let player = Player {
                 id: found_player.id,
                 name,
-            })
-        } else {
-            bail!("incorrect")
+            };
+            let needs_code = found_player
+                .two_factor
+                .as_ref()
+                .is_some_and(|two_factor| two_factor.enabled);
+            if needs_code {
+                let Some(code) = code else {
+                    return Ok(LoginStep::NeedsCode);
+                };
+                if !check_code(found_player, &code, players).await? {
+                    limiter.failed(&keys);
+                    bail!("That code didn't work")
+                }
+            }
+            limiter.succeeded(&keys[1]);
+            return Ok(LoginStep::LoggedIn(player));
         }
-    } else {
-        bail!("incorrect")
     }
+    limiter.failed(&keys);
+    bail!("Invalid login credentials")
+}
+
+fn totp(secret: &str, now: u64) -> Result<libreauth::oath::TOTP> {
+    Ok(libreauth::oath::TOTPBuilder::new()
+        .base32_key(secret)
+        .period(TOTP_PERIOD as u32)
+        .timestamp(now as i64)
+        .finalize()?)
+}
+
+/// Checks a code from the player's app. Codes only work once, so anything from the same time step
+/// as the last code that worked, or earlier, is turned down. The caller saves the new step.
+fn check_app_code(two_factor: &mut TwoFactor, code: &str) -> Result<bool> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let step = now / TOTP_PERIOD;
+    if step <= two_factor.last_step || !totp(&two_factor.secret, now)?.is_valid(code.trim()) {
+        return Ok(false);
+    }
+    two_factor.last_step = step;
+    Ok(true)
+}
+
+/// Recovery codes are compared without spaces, dashes or case so they're easy to type
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Accepts a code from the player's app or one of their recovery codes, which is then used up
+async fn check_code(
+    mut stored: PasswordPlayer,
+    code: &str,
+    players: &dyn PlayerStore,
+) -> Result<bool> {
+    let Some(two_factor) = &mut stored.two_factor else {
+        return Ok(false);
+    };
+    if check_app_code(two_factor, code)? {
+        players.replace(stored).await?;
+        return Ok(true);
+    }
+    let hash = hash_token(&normalize_code(code));
+    let Some(index) = two_factor.recovery.iter().position(|known| *known == hash) else {
+        return Ok(false);
+    };
+    two_factor.recovery.remove(index);
+    players.replace(stored).await?;
+    Ok(true)
+}
+
+/// Gives the player a new secret for their app. Two-factor authentication only turns on once they
+/// send back a code made from it.
+pub async fn start_two_factor(
+    player: &Player,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+) -> Result<TotpSetup> {
+    let stored = reauthenticate(player, password, players, limiter).await?;
+    if stored
+        .two_factor
+        .as_ref()
+        .is_some_and(|two_factor| two_factor.enabled)
+    {
+        bail!("Two-factor authentication is already on")
+    }
+    let secret = libreauth::key::KeyBuilder::new()
+        .size(20)
+        .generate()
+        .as_base32();
+    let uri = totp(&secret, 0)?
+        .key_uri_format(ISSUER, &stored.name)
+        .finalize();
+    players
+        .replace(PasswordPlayer {
+            two_factor: Some(TwoFactor {
+                secret: secret.clone(),
+                enabled: false,
+                recovery: Vec::new(),
+                last_step: 0,
+            }),
+            ..stored
+        })
+        .await?;
+    Ok(TotpSetup { uri, secret })
+}
+
+/// Turns on two-factor authentication once the player's app makes the right code, and returns
+/// their recovery codes. Only hashes of them are kept, so this is the only time they're shown.
+pub async fn confirm_two_factor(
+    player: &Player,
+    code: &str,
+    players: &dyn PlayerStore,
+) -> Result<Vec<String>> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let mut stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?;
+    let Some(two_factor) = &mut stored.two_factor else {
+        bail!("Two-factor authentication hasn't been set up")
+    };
+    if two_factor.enabled {
+        bail!("Two-factor authentication is already on")
+    }
+    if !check_app_code(two_factor, code)? {
+        bail!("That code didn't work")
+    }
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            libreauth::key::KeyBuilder::new()
+                .size(10)
+                .generate()
+                .as_base32()
+        })
+        .collect();
+    two_factor.enabled = true;
+    two_factor.recovery = codes.iter().map(|code| hash_token(code)).collect();
+    players.replace(stored).await?;
+    Ok(codes)
+}
+
+pub async fn disable_two_factor(
+    player: &Player,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+) -> Result<()> {
+    let stored = reauthenticate(player, password, players, limiter).await?;
+    players
+        .replace(PasswordPlayer {
+            two_factor: None,
+            ..stored
+        })
+        .await
+}
+
+pub async fn two_factor_enabled(player: &Player, players: &dyn PlayerStore) -> Result<bool> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?;
+    Ok(stored
+        .two_factor
+        .is_some_and(|two_factor| two_factor.enabled))
 }
 
 pub async fn new_user(
-    players: &Collection<Player>,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    ip: &ClientIp,
     name: String,
     real_password: String,
 ) -> Result<Player> {
-    name_unique(players, &name).await?;
-    let players = players.clone_with_type::<PasswordPlayer>();
-    let hasher =
-        HashBuilder::new_std(libreauth::pass::PasswordStorageStandard::NoStandard).finalize()?;
-    let hashed_password = hasher.hash(&real_password)?;
+    let keys = [Key::Signup(ip.0.clone())];
+    limiter.check(&keys)?;
+    limiter.failed(&keys);
+    check_name(&name)?;
+    if players.find_by_name(&name).await?.is_some() {
+        bail!("Name already taken")
+    }
     let with_password = PasswordPlayer {
-        password: hashed_password,
+        password: hash_password(&real_password)?,
         player: Player {
             id: None,
             name: name.clone(),
         },
+        two_factor: None,
+        passkeys: Vec::new(),
+        email: None,
+        notifications: None,
+        webhooks: Vec::new(),
+        api_tokens: Vec::new(),
+        version: 0,
     };
-    let result = players.insert_one(with_password).await?;
-    Ok(Player {
-        id: result.inserted_id.as_object_id(),
+    let id = players.insert(with_password).await?;
+    Ok(Player { id: Some(id), name })
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.trim() != name {
+        bail!("Names can't be empty or start or end with spaces")
+    }
+    if name.chars().count() > MAX_NAME {
+        bail!("Names can be at most {MAX_NAME} characters")
+    }
+    if name == DELETED_NAME {
+        bail!("Name already taken")
+    }
+    Ok(())
+}
+
+/// Checks the password policy before hashing
+pub fn hash_password(password: &str) -> Result<String> {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD {
+        bail!("Passwords need at least {MIN_PASSWORD} characters")
+    }
+    if length > MAX_PASSWORD {
+        bail!("Passwords can be at most {MAX_PASSWORD} characters")
+    }
+    let hasher =
+        HashBuilder::new_std(libreauth::pass::PasswordStorageStandard::NoStandard).finalize()?;
+    Ok(hasher.hash(password)?)
+}
+
+/// Makes someone who's already logged in type their password again before changing anything
+/// important. Wrong guesses count against the account just like failed logins, so a stolen
+/// session can't be used to guess the password any faster.
+pub async fn reauthenticate(
+    player: &Player,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+) -> Result<PasswordPlayer> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?;
+    let keys = [Key::Name(stored.name.clone())];
+    limiter.check(&keys)?;
+    if !HashBuilder::from_phc(&stored.password)?.is_valid(password) {
+        limiter.failed(&keys);
+        bail!("Your current password is incorrect")
+    }
+    limiter.succeeded(&keys[0]);
+    Ok(stored)
+}
+
+pub async fn change_password(
+    player: &Player,
+    current: &str,
+    new: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+) -> Result<()> {
+    let stored = reauthenticate(player, current, players, limiter).await?;
+    players
+        .replace(PasswordPlayer {
+            password: hash_password(new)?,
+            ..stored
+        })
+        .await
+}
+
+/// Renames the player everywhere a copy of them is kept. Chat and tournaments are only there when
+/// the server uses MongoDB.
+#[allow(clippy::too_many_arguments)]
+pub async fn change_name(
+    player: &Player,
+    name: String,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    games: &dyn GameStore,
+    sessions: &dyn SessionStore,
+    chats: Option<&Chats>,
+    tournaments: Option<&Collection<Tournament>>,
+) -> Result<Player> {
+    check_name(&name)?;
+    let stored = reauthenticate(player, password, players, limiter).await?;
+    let renamed = Player {
+        id: stored.player.id,
         name,
-    })
+    };
+    players
+        .replace(PasswordPlayer {
+            player: renamed.clone(),
+            ..stored
+        })
+        .await?;
+    update_copies(&renamed, games, sessions, chats, tournaments).await?;
+    Ok(renamed)
+}
+
+/// Deletes the account and logs out everywhere. Games still being played are resigned first. They
+/// stay around for their opponents but no longer say who they were, while their ratings and chat
+/// settings go with the account.
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_account(
+    player: &Player,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    games: &dyn GameStore,
+    sessions: &dyn SessionStore,
+    chats: Option<&Chats>,
+    ratings: Option<&Collection<RatingRecord>>,
+    tournaments: Option<&Collection<Tournament>>,
+    notifier: &Notifier,
+) -> Result<()> {
+    reauthenticate(player, password, players, limiter).await?;
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    games::resign_all(player, notifier, games, ratings, tournaments).await?;
+    let anonymous = Player {
+        id: Some(id),
+        name: DELETED_NAME.into(),
+    };
+    games.delete_requests(id).await?;
+    update_copies(&anonymous, games, sessions, chats, tournaments).await?;
+    if let Some(chats) = chats {
+        chat::forget_player(id, chats).await?;
+    }
+    if let Some(ratings) = ratings {
+        ratings::delete_ratings(id, ratings).await?;
+    }
+    sessions.delete_player_sessions(id).await?;
+    players.delete(id).await
+}
+
+async fn update_copies(
+    player: &Player,
+    games: &dyn GameStore,
+    sessions: &dyn SessionStore,
+    chats: Option<&Chats>,
+    tournaments: Option<&Collection<Tournament>>,
+) -> Result<()> {
+    games.rename_player(player).await?;
+    sessions.rename_player(player).await?;
+    if let Some(chats) = chats {
+        chat::rename_author(player, chats).await?;
+    }
+    if let Some(tournaments) = tournaments {
+        tournaments::rename_player(player, tournaments).await?;
+    }
+    Ok(())
 }
 
 pub async fn create_session_cookie(
     player: Player,
-    sessions: &Collection<SessionRecord>,
+    sessions: &dyn SessionStore,
 ) -> Result<Cookie<'static>> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let secret = new_secret();
     let session = SessionRecord {
         id: None,
         subscription: None,
-        time: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        time: now,
+        last_used: now,
+        token: Some(hash_token(&secret)),
         player,
     };
-    let result = sessions.insert_one(session).await?;
+    let id = sessions.insert(session).await?;
+    Ok(session_cookie(format!("{}.{secret}", id.to_hex())))
+}
+
+fn session_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((TOKEN_COOKIE, token))
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .path("/")
+        .max_age(time::Duration::seconds(SESSION_LIFETIME.as_secs() as i64))
+        .build()
+}
+
+/// A random secret for a token that only ever gets stored hashed
+pub fn new_secret() -> String {
+    base64_url::encode(&rand::random::<[u8; 32]>())
+}
+
+pub fn hash_token(secret: &str) -> String {
+    base64_url::encode(&Sha256::digest(secret.as_bytes()))
+}
+
+/// Compares in constant time so response times don't give away how much of a guess was right
+pub fn secret_matches(hash: &str, secret: &str) -> bool {
+    hash.as_bytes().ct_eq(hash_token(secret).as_bytes()).into()
+}
+
+/// Looks up the session for a token cookie, which holds the session id and a random secret.
+/// Cookies that don't match a live session are removed.
+pub async fn find_session(
+    token: &str,
+    cookies: &Cookies,
+    sessions: &dyn SessionStore,
+) -> Result<Option<SessionRecord>> {
+    // Cookies from before sessions had a secret are a quoted id and fail to parse here
+    let Some((id, secret)) = token.split_once('.') else {
+        cookies.remove(removal_cookie());
+        return Ok(None);
+    };
+    let Ok(id) = ObjectId::parse_str(id) else {
+        cookies.remove(removal_cookie());
+        return Ok(None);
+    };
+    let Some(session) = sessions.get(id).await? else {
+        cookies.remove(removal_cookie());
+        return Ok(None);
+    };
+    let Some(hash) = &session.token else {
+        sessions.delete(id).await?;
+        cookies.remove(removal_cookie());
+        return Ok(None);
+    };
+    if !secret_matches(hash, secret) {
+        cookies.remove(removal_cookie());
+        return Ok(None);
+    }
+    renew_session(session, token, cookies, sessions).await
+}
 
-    Ok(Cookie::build((
-        TOKEN_COOKIE,
-        serde_json::to_string(&result.inserted_id).unwrap(),
-    ))
-    .secure(true)
-    .http_only(true)
-    .path("/")
-    .build())
+/// Expires sessions that went unused for too long and pushes back the expiry of the rest, along
+/// with their cookie.
+async fn renew_session(
+    mut session: SessionRecord,
+    token: &str,
+    cookies: &Cookies,
+    sessions: &dyn SessionStore,
+) -> Result<Option<SessionRecord>> {
+    let id = session.id.ok_or_else(|| anyhow!("Session has no id"))?;
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let idle = now.saturating_sub(session.last_used.max(session.time));
+    if idle > SESSION_LIFETIME.as_secs() {
+        sessions.delete(id).await?;
+        cookies.remove(removal_cookie());
+        return Ok(None);
+    }
+    if idle > RENEW_AFTER.as_secs() {
+        session.last_used = now;
+        sessions.replace(session.clone()).await?;
+        cookies.add(session_cookie(token.to_string()));
+    }
+    Ok(Some(session))
+}
+
+pub async fn list_sessions(
+    current: &SessionRecord,
+    sessions: &dyn SessionStore,
+) -> Result<Vec<SessionInfo>> {
+    let player = current
+        .player
+        .id
+        .ok_or_else(|| anyhow!("Player has no id"))?;
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let mut live = Vec::new();
+    for session in sessions.player_sessions(player).await? {
+        let idle = now.saturating_sub(session.last_used.max(session.time));
+        match session.token {
+            // Expired, but never used again to get cleaned up on the way in
+            Some(_) if idle > SESSION_LIFETIME.as_secs() => {
+                sessions.delete(session.id.unwrap()).await?
+            }
+            Some(_) => live.push(session),
+            // Left over from before sessions had a secret, so nothing can log into them
+            None => sessions.delete(session.id.unwrap()).await?,
+        }
+    }
+    let mut infos: Vec<SessionInfo> = live
+        .into_iter()
+        .map(|session| SessionInfo {
+            id: session.id.unwrap(),
+            created: session.time,
+            last_used: session.last_used.max(session.time),
+            push: session.subscription.is_some(),
+            current: session.id == current.id,
+        })
+        .collect();
+    infos.sort_by_key(|info| std::cmp::Reverse(info.last_used));
+    Ok(infos)
+}
+
+/// Logs out one of the player's devices
+pub async fn revoke_session(
+    current: &SessionRecord,
+    id: ObjectId,
+    sessions: &dyn SessionStore,
+) -> Result<()> {
+    match sessions.get(id).await? {
+        Some(session) if session.player.id == current.player.id => sessions.delete(id).await,
+        _ => bail!("No session for id"),
+    }
 }
 
 pub async fn update_session(
     subscription: web_push::SubscriptionInfo,
     session: SessionRecord,
-    sessions: &Collection<SessionRecord>,
+    sessions: &dyn SessionStore,
 ) -> Result<()> {
-    let id = session.id.unwrap();
     let updated = SessionRecord {
         subscription: Some(subscription),
         ..session
     };
-    sessions.replace_one(doc! { "_id": id }, updated).await?;
-    Ok(())
+    sessions.replace(updated).await
 }
 
-pub async fn clear_player_sessions(
-    session: &SessionRecord,
-    sessions: &Collection<SessionRecord>,
+/// Stops notifications to one of the player's devices
+pub async fn disable_push(
+    current: &SessionRecord,
+    id: ObjectId,
+    sessions: &dyn SessionStore,
 ) -> Result<()> {
-    sessions
-        .delete_many(doc! { "player._id": session.player.id })
-        .await?;
-    Ok(())
+    match sessions.get(id).await? {
+        Some(session) if session.player.id == current.player.id => {
+            sessions
+                .replace(SessionRecord {
+                    subscription: None,
+                    ..session
+                })
+                .await
+        }
+        _ => bail!("No session for id"),
+    }
 }
 
-async fn name_unique(players: &Collection<Player>, name: &String) -> Result<()> {
-    if players.find_one(doc! { "name": name }).await?.is_some() {
-        bail!("Name already taken")
-    } else {
-        Ok(())
-    }
+pub async fn clear_player_sessions(
+    session: &SessionRecord,
+    sessions: &dyn SessionStore,
+) -> Result<()> {
+    let player = session
+        .player
+        .id
+        .ok_or_else(|| anyhow!("Player has no id"))?;
+    sessions.delete_player_sessions(player).await
 }
 
 pub fn removal_cookie() -> Cookie<'static> {
@@ -113,3 +610,216 @@ pub fn removal_cookie() -> Cookie<'static> {
         .http_only(true)
         .build()
 }
+
+/// Rejects cross-site POSTs so other pages can't act with a player's cookie. Browsers say where
+/// a request came from; clients that don't say aren't browsers and don't carry the cookie for
+/// someone else.
+pub async fn check_origin(request: Request, next: Next) -> Response {
+    if request.method() != Method::POST || same_origin(&request) {
+        next.run(request).await
+    } else {
+        (StatusCode::FORBIDDEN, "Cross-site request").into_response()
+    }
+}
+
+fn same_origin(request: &Request) -> bool {
+    let headers = request.headers();
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return matches!(site.to_str(), Ok("same-origin" | "none"));
+    }
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    let origin_host = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, host)| host);
+    origin_host.is_some() && origin_host == host
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        common::game::GameTypes,
+        server::{
+            games::{join_open_game, new_open_game},
+            limits::TooManyAttempts,
+            notifications::Recording,
+            storage::{Conflict, MemoryGames, MemoryPlayers, MemorySessions, sqlite},
+            webhooks::Webhooks,
+        },
+    };
+
+    const PASSWORD: &str = "passwordA1";
+
+    async fn alice(players: &dyn PlayerStore) -> Player {
+        new_user(
+            players,
+            &Limiter::default(),
+            &ClientIp("127.0.0.1".into()),
+            "alice".into(),
+            PASSWORD.into(),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn login(players: &dyn PlayerStore, code: &str) -> Result<LoginStep> {
+        login_user(
+            players,
+            &Limiter::default(),
+            &ClientIp("127.0.0.1".into()),
+            "alice".into(),
+            PASSWORD.into(),
+            Some(code.into()),
+        )
+        .await
+    }
+
+    /// The code alice's app would show right now
+    async fn app_code(players: &dyn PlayerStore, player: &Player) -> String {
+        let stored = players.get(player.id.unwrap()).await.unwrap().unwrap();
+        let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        totp(&stored.two_factor.unwrap().secret, now)
+            .unwrap()
+            .generate()
+    }
+
+    #[tokio::test]
+    async fn app_codes_only_work_once() {
+        let players = MemoryPlayers::default();
+        let player = alice(&players).await;
+        start_two_factor(&player, PASSWORD, &players, &Limiter::default())
+            .await
+            .unwrap();
+        let code = app_code(&players, &player).await;
+        confirm_two_factor(&player, &code, &players).await.unwrap();
+        // The code that turned it on can't log in too
+        assert!(login(&players, &code).await.is_err());
+
+        let stored = players.get(player.id.unwrap()).await.unwrap().unwrap();
+        // As if the last code was from a while ago
+        let mut two_factor = stored.two_factor.clone().unwrap();
+        two_factor.last_step -= 1;
+        players
+            .replace(PasswordPlayer {
+                two_factor: Some(two_factor),
+                ..stored
+            })
+            .await
+            .unwrap();
+        let code = app_code(&players, &player).await;
+        assert!(login(&players, &code).await.is_ok());
+        assert!(login(&players, &code).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stale_players_conflict() {
+        let (_, sqlite_players, _) = sqlite::open(":memory:").unwrap();
+        let stores: [&dyn PlayerStore; 2] = [&MemoryPlayers::default(), &sqlite_players];
+        for players in stores {
+            let player = alice(players).await;
+            let stored = players.get(player.id.unwrap()).await.unwrap().unwrap();
+            players.replace(stored.clone()).await.unwrap();
+            let error = players.replace(stored.clone()).await.unwrap_err();
+            assert!(error.is::<Conflict>());
+            // Saving someone who's gone doesn't quietly do nothing either
+            players.delete(player.id.unwrap()).await.unwrap();
+            let error = players.replace(stored).await.unwrap_err();
+            assert!(error.is::<Conflict>());
+        }
+    }
+
+    #[tokio::test]
+    async fn deleting_an_account_resigns_its_games() {
+        let players: Arc<dyn PlayerStore> = Arc::new(MemoryPlayers::default());
+        let games = MemoryGames::default();
+        let webhooks = Webhooks::start(players.clone(), false).unwrap();
+        let notifier = Notifier::start(
+            None,
+            Arc::new(Recording::default()),
+            players.clone(),
+            webhooks,
+        );
+        let alice = alice(&*players).await;
+        let bob = new_user(
+            &*players,
+            &Limiter::default(),
+            &ClientIp("127.0.0.2".into()),
+            "bob".into(),
+            PASSWORD.into(),
+        )
+        .await
+        .unwrap();
+        let id = new_open_game(
+            bob.clone(),
+            GameTypes::Square,
+            Visibility::Private,
+            false,
+            TimeControl::Unlimited,
+            &games,
+        )
+        .await
+        .unwrap();
+        join_open_game(id, alice.clone(), &games, &notifier)
+            .await
+            .unwrap();
+
+        delete_account(
+            &alice,
+            PASSWORD,
+            &*players,
+            &Limiter::default(),
+            &games,
+            &MemorySessions::default(),
+            None,
+            None,
+            None,
+            &notifier,
+        )
+        .await
+        .unwrap();
+        let game = games.get(id).await.unwrap().unwrap();
+        let GameOrRequest::Completed(game) = game.game else {
+            panic!("the game should be over")
+        };
+        assert_eq!(game.winner, Some(game.maker_color));
+        assert_eq!(game.joiner.name, DELETED_NAME);
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_while_logged_in_lock_the_account() {
+        let players = MemoryPlayers::default();
+        let limiter = Limiter::default();
+        let player = alice(&players).await;
+        let mut locked = false;
+        for _ in 0..20 {
+            let error = reauthenticate(&player, "wrongPassword1", &players, &limiter)
+                .await
+                .unwrap_err();
+            if error.is::<TooManyAttempts>() {
+                locked = true;
+                break;
+            }
+        }
+        assert!(locked);
+        // Logging in counts against the same account, so it's locked there too
+        let login = login_user(
+            &players,
+            &limiter,
+            &ClientIp("127.0.0.2".into()),
+            "alice".into(),
+            PASSWORD.into(),
+            None,
+        )
+        .await;
+        assert!(login.unwrap_err().is::<TooManyAttempts>());
+    }
+}
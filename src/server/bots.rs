@@ -0,0 +1,415 @@
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{FromRequestParts, Path},
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use dioxus::server::ServerFnError;
+use futures::StreamExt;
+
+use super::{
+    auth::{hash_token, new_secret, secret_matches},
+    games,
+    notation::{parse_some_turn, some_moves},
+    notifications::Notifier,
+    prelude::*,
+    state::DB,
+    storage::{Games, PlayerStore, Players, server_error},
+};
+use crate::common::{
+    account::{ApiToken, ApiTokenInfo, MAX_API_TOKENS, MAX_NAME},
+    game::GameTypes,
+    tournament::Tournament,
+};
+
+/// The player a request's `Authorization: Bearer` API token belongs to. Bots never get a session
+/// cookie, so nothing else logs them in.
+pub struct Bot(pub Player);
+
+impl<T: Send + Sync> FromRequestParts<T> for Bot {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &T,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let Extension(players) = Players::from_request_parts(parts, state).await?;
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(unauthorized())?;
+        find_token_player(token, &*players)
+            .await?
+            .map(Bot)
+            .ok_or(unauthorized())
+    }
+}
+
+/// Tokens hold the player's id and a random secret, so checking one only needs that player
+async fn find_token_player(token: &str, players: &dyn PlayerStore) -> Result<Option<Player>> {
+    let Some((id, secret)) = token.trim().split_once('.') else {
+        return Ok(None);
+    };
+    let Ok(id) = ObjectId::parse_str(id) else {
+        return Ok(None);
+    };
+    let Some(stored) = players.get(id).await? else {
+        return Ok(None);
+    };
+    // Every token gets compared so timing doesn't say which one was close
+    let matched = stored.api_tokens.iter().fold(false, |matched, token| {
+        secret_matches(&token.hash, secret) | matched
+    });
+    Ok(matched.then_some(stored.player))
+}
+
+async fn stored_player(player: &Player, players: &dyn PlayerStore) -> Result<PasswordPlayer> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))
+}
+
+pub async fn list_tokens(player: &Player, players: &dyn PlayerStore) -> Result<Vec<ApiTokenInfo>> {
+    Ok(stored_player(player, players)
+        .await?
+        .api_tokens
+        .into_iter()
+        .map(|token| ApiTokenInfo {
+            id: token.id,
+            name: token.name,
+            created: token.created,
+        })
+        .collect())
+}
+
+/// Returns the whole token, which can't be seen again after this
+pub async fn create_token(
+    player: &Player,
+    name: String,
+    players: &dyn PlayerStore,
+) -> Result<String> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME {
+        bail!("Token names have to be between 1 and {MAX_NAME} characters")
+    }
+    let mut stored = stored_player(player, players).await?;
+    if stored.api_tokens.len() >= MAX_API_TOKENS {
+        bail!("You can have at most {MAX_API_TOKENS} API tokens")
+    }
+    let secret = new_secret();
+    stored.api_tokens.push(ApiToken {
+        id: ObjectId::new().to_hex(),
+        name,
+        hash: hash_token(&secret),
+        created: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+    });
+    let token = format!("{}.{secret}", stored.id.unwrap().to_hex());
+    players.replace(stored).await?;
+    Ok(token)
+}
+
+pub async fn revoke_token(player: &Player, token: &str, players: &dyn PlayerStore) -> Result<()> {
+    let mut stored = stored_player(player, players).await?;
+    let before = stored.api_tokens.len();
+    stored.api_tokens.retain(|stored| stored.id != token);
+    if stored.api_tokens.len() == before {
+        bail!("No API token with that id")
+    }
+    players.replace(stored).await
+}
+
+/// A game as a bot sees it, with the turns in coordinate notation so it doesn't need to know how
+/// boards are stored
+#[derive(Serialize)]
+struct BotGame {
+    id: String,
+    variant: GameTypes,
+    white: String,
+    black: String,
+    /// The side the bot plays
+    color: Color,
+    /// Every turn so far, like `e2e4@e5`
+    moves: Vec<String>,
+    turn: Color,
+    your_turn: bool,
+    over: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    winner: Option<Color>,
+    rated: bool,
+    time_control: TimeControl,
+}
+
+impl BotGame {
+    fn new(id: ObjectId, with_id: &AnyGame, game: &Game, player: &Player) -> Self {
+        let (white, black) = if game.maker_color == Color::White {
+            (&game.maker, &game.joiner)
+        } else {
+            (&game.joiner, &game.maker)
+        };
+        let color = if game.maker.id == player.id {
+            game.maker_color
+        } else {
+            game.maker_color.other()
+        };
+        let over = matches!(with_id.game, GameOrRequest::Completed(_));
+        Self {
+            id: id.to_hex(),
+            variant: game.game_type(),
+            white: white.name.clone(),
+            black: black.name.clone(),
+            color,
+            moves: some_moves(&game.some_game),
+            turn: game.turn(),
+            your_turn: !over && game.is_player_turn(player),
+            over,
+            winner: game.game_over().filter(|_| over),
+            rated: with_id.rated,
+            time_control: with_id.time_control,
+        }
+    }
+}
+
+/// An open game someone else made, which the bot can accept
+#[derive(Serialize)]
+struct BotChallenge {
+    id: String,
+    from: String,
+    variant: GameTypes,
+    rated: bool,
+    time_control: TimeControl,
+}
+
+/// A quiet stream sends an empty line this often, so bots and proxies can tell it's still open
+const HEARTBEAT: Duration = Duration::from_secs(30);
+
+/// One line of the event stream
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotEvent {
+    Game(BotGame),
+    Challenge(BotChallenge),
+    /// Someone else accepted the challenge first
+    ChallengeGone {
+        id: String,
+    },
+    /// The stream is about to close, reconnecting starts over with everything current
+    Error {
+        message: String,
+    },
+}
+
+/// What the bot should hear about a game that was just saved, if anything. Keeps track of the
+/// challenges it was told about so it also hears when they're taken.
+fn bot_event(
+    player: &Player,
+    with_id: &AnyGame,
+    challenges: &mut HashSet<ObjectId>,
+) -> Option<BotEvent> {
+    let id = with_id.id?;
+    match &with_id.game {
+        GameOrRequest::Request(request) if request.maker.id != player.id => {
+            challenges.insert(id);
+            Some(BotEvent::Challenge(BotChallenge {
+                id: id.to_hex(),
+                from: request.maker.name.clone(),
+                variant: request.game_type,
+                rated: with_id.rated,
+                time_control: with_id.time_control,
+            }))
+        }
+        GameOrRequest::Game(game) | GameOrRequest::Completed(game)
+            if with_id.game.in_game(player) =>
+        {
+            challenges.remove(&id);
+            Some(BotEvent::Game(BotGame::new(id, with_id, game, player)))
+        }
+        _ => challenges
+            .remove(&id)
+            .then(|| BotEvent::ChallengeGone { id: id.to_hex() }),
+    }
+}
+
+fn json_line(event: &BotEvent) -> Result<String> {
+    Ok(serde_json::to_string(event)? + "\n")
+}
+
+/// Newline-delimited JSON: the bot's games in progress and the open challenges first, then every
+/// change to either as it happens. Empty lines in between only keep the connection alive.
+async fn stream(
+    Bot(player): Bot,
+    Extension(games): Games,
+) -> std::result::Result<Response, ServerFnError> {
+    // Watching first means nothing saved while the rest is read gets missed
+    let mut feed = games.watch_all().await.map_err(server_error)?;
+    let current = games::get_player_games(&player, &*games)
+        .await
+        .map_err(server_error)?;
+    let open = games.open_requests().await.map_err(server_error)?;
+
+    let lines = async_stream::stream! {
+        let mut challenges = HashSet::new();
+        let playing = current
+            .iter()
+            .filter(|game| matches!(game.game, GameOrRequest::Game(_)));
+        for game in playing.chain(&open) {
+            if let Some(event) = bot_event(&player, game, &mut challenges) {
+                match json_line(&event) {
+                    Ok(line) => yield line,
+                    Err(error) => log::error!("serializing bot event failed: {error:?}"),
+                }
+            }
+        }
+        loop {
+            let game = match tokio::time::timeout(HEARTBEAT, feed.next()).await {
+                Err(_) => {
+                    yield "\n".to_string();
+                    continue;
+                }
+                Ok(None) => break,
+                Ok(Some(Ok(game))) => game,
+                Ok(Some(Err(error))) => {
+                    log::error!("bot event stream failed: {error:?}");
+                    let event = BotEvent::Error {
+                        message: "The event stream failed, please reconnect".into(),
+                    };
+                    if let Ok(line) = json_line(&event) {
+                        yield line;
+                    }
+                    break;
+                }
+            };
+            if let Some(event) = bot_event(&player, &game, &mut challenges) {
+                match json_line(&event) {
+                    Ok(line) => yield line,
+                    Err(error) => log::error!("serializing bot event failed: {error:?}"),
+                }
+            }
+        }
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines.map(Ok::<_, Infallible>)),
+    )
+        .into_response())
+}
+
+async fn account(Bot(player): Bot) -> Json<Player> {
+    Json(player)
+}
+
+async fn list_games(
+    Bot(player): Bot,
+    Extension(games): Games,
+) -> std::result::Result<Json<Vec<BotGame>>, ServerFnError> {
+    Ok(Json(
+        games::get_player_games(&player, &*games)
+            .await
+            .map_err(server_error)?
+            .iter()
+            .filter_map(|with_id| match (&with_id.game, with_id.id) {
+                (GameOrRequest::Game(game), Some(id)) => {
+                    Some(BotGame::new(id, with_id, game, &player))
+                }
+                _ => None,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct TurnRequest {
+    /// Like `e2e4@e5`, with a letter after the move for promotions, like `e7e8q@d4`
+    turn: String,
+}
+
+async fn play_turn(
+    Bot(player): Bot,
+    Path(game_id): Path<String>,
+    Extension(games): Games,
+    Extension(notifier): Extension<Notifier>,
+    ratings: Option<DB<RatingRecord>>,
+    tournaments: Option<DB<Tournament>>,
+    Json(request): Json<TurnRequest>,
+) -> std::result::Result<StatusCode, ServerFnError> {
+    let play = async {
+        let game_id = ObjectId::parse_str(&game_id)?;
+        let with_id = games::get_player_game(game_id, &player, &*games).await?;
+        let GameOrRequest::Game(game) = with_id.game else {
+            bail!("This game isn't being played")
+        };
+        let turn = parse_some_turn(&game.some_game, &request.turn)?;
+        games::apply_turn(
+            WithId::new(game_id, turn),
+            player,
+            &notifier,
+            &*games,
+            ratings.as_deref(),
+            tournaments.as_deref(),
+        )
+        .await
+    };
+    play.await.map_err(server_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn resign(
+    Bot(player): Bot,
+    Path(game_id): Path<String>,
+    Extension(games): Games,
+    Extension(notifier): Extension<Notifier>,
+    ratings: Option<DB<RatingRecord>>,
+    tournaments: Option<DB<Tournament>>,
+) -> std::result::Result<StatusCode, ServerFnError> {
+    let resign = async {
+        let game_id = ObjectId::parse_str(&game_id)?;
+        games::resign(
+            game_id,
+            player,
+            &notifier,
+            &*games,
+            ratings.as_deref(),
+            tournaments.as_deref(),
+        )
+        .await
+    };
+    resign.await.map_err(server_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn accept_challenge(
+    Bot(player): Bot,
+    Path(game_id): Path<String>,
+    Extension(games): Games,
+    Extension(notifier): Extension<Notifier>,
+) -> std::result::Result<StatusCode, ServerFnError> {
+    let accept = async {
+        let game_id = ObjectId::parse_str(&game_id)?;
+        games::join_open_game(game_id, player, &*games, &notifier).await
+    };
+    accept.await.map_err(server_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The bot API, which is documented in the README
+pub fn routes() -> Router {
+    Router::new()
+        .route("/api/bot/account", get(account))
+        .route("/api/bot/stream", get(stream))
+        .route("/api/bot/games", get(list_games))
+        .route("/api/bot/games/{id}/turn", post(play_turn))
+        .route("/api/bot/games/{id}/resign", post(resign))
+        .route("/api/bot/challenges/{id}/accept", post(accept_challenge))
+}
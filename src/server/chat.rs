@@ -0,0 +1,225 @@
+use std::time::SystemTime;
+
+use futures::TryStreamExt;
+use mongodb::change_stream::{
+    ChangeStream,
+    event::{ChangeStreamEvent, OperationType},
+};
+
+use super::{
+    games::get_player_game,
+    notifications::{Event, Notifier, Payload, send_notification},
+    prelude::*,
+    storage::GameStore,
+    webhooks::{ChatLine, GameEvent, HookEvent, Webhooks, send_webhooks},
+};
+use crate::common::chat::{ChatMessage, ChatSettings, MAX_MESSAGE};
+
+/// A message someone flagged, kept with a copy of the message in case it's later edited away.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    #[serde(default)]
+    pub id: Option<ObjectId>,
+    pub reporter: Player,
+    pub message: ChatMessage,
+    pub time: u64,
+}
+
+/// Everything chat keeps in the database, bundled so RPCs only need one extension.
+#[derive(Clone)]
+pub struct Chats {
+    pub messages: Collection<ChatMessage>,
+    pub settings: Collection<ChatSettings>,
+    pub reports: Collection<ChatReport>,
+}
+
+pub async fn get_settings(
+    player: ObjectId,
+    settings: &Collection<ChatSettings>,
+) -> Result<ChatSettings> {
+    Ok(settings
+        .find_one(doc! {"player": player})
+        .await?
+        .unwrap_or(ChatSettings {
+            player,
+            ..ChatSettings::default()
+        }))
+}
+
+pub async fn set_notify(
+    player: ObjectId,
+    notify: bool,
+    settings: &Collection<ChatSettings>,
+) -> Result<()> {
+    settings
+        .update_one(doc! {"player": player}, doc! {"$set": {"notify": notify}})
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_muted(
+    player: ObjectId,
+    other: ObjectId,
+    muted: bool,
+    settings: &Collection<ChatSettings>,
+) -> Result<()> {
+    let update = if muted {
+        doc! {"$addToSet": {"muted": other}}
+    } else {
+        doc! {"$pull": {"muted": other}}
+    };
+    settings
+        .update_one(doc! {"player": player}, update)
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+/// Saves a message and returns who it was sent to, if that's someone other than the author.
+pub async fn send_message(
+    game_id: ObjectId,
+    text: String,
+    author: &Player,
+    games: &dyn GameStore,
+    chats: &Collection<ChatMessage>,
+    webhooks: &Webhooks,
+) -> Result<Option<ObjectId>> {
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        bail!("Messages can't be empty")
+    }
+    if text.chars().count() > MAX_MESSAGE {
+        bail!("Messages can be at most {MAX_MESSAGE} characters")
+    }
+    let game = match get_player_game(game_id, author, games).await?.game {
+        GameOrRequest::Game(game) | GameOrRequest::Completed(game) => game,
+        GameOrRequest::Request(_) => bail!("Nobody has joined this game yet"),
+    };
+    let recipient = game.opponent(author).id.filter(|id| Some(*id) != author.id);
+
+    chats
+        .insert_one(ChatMessage {
+            id: None,
+            game: game_id,
+            author: author.clone(),
+            text: text.clone(),
+            time: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        })
+        .await?;
+    send_webhooks(
+        &game,
+        GameEvent {
+            message: Some(ChatLine {
+                author: author.name.clone(),
+                text,
+            }),
+            ..GameEvent::new(HookEvent::Chat, game_id, &game)
+        },
+        webhooks,
+    );
+    Ok(recipient)
+}
+
+/// Lets the recipient know about a new message if they asked to be and haven't muted the author.
+pub async fn notify_recipient(
+    game_id: ObjectId,
+    recipient: ObjectId,
+    author: &Player,
+    settings: &Collection<ChatSettings>,
+    notifier: &Notifier,
+) -> Result<()> {
+    let settings = get_settings(recipient, settings).await?;
+    if settings.notify && !settings.muted.contains(&author.id.unwrap()) {
+        let body = format!("{} sent you a message", author.name);
+        send_notification(
+            recipient,
+            Payload::new(Event::Chat, game_id, author, None, body),
+            notifier,
+        );
+    }
+    Ok(())
+}
+
+pub async fn report_message(
+    message_id: ObjectId,
+    reporter: Player,
+    games: &dyn GameStore,
+    chats: &Collection<ChatMessage>,
+    reports: &Collection<ChatReport>,
+) -> Result<()> {
+    let message = chats
+        .find_one(doc! {"_id": message_id})
+        .await?
+        .ok_or_else(|| anyhow!("No message for id"))?;
+    // Only someone who could have seen the message gets to report it
+    get_player_game(message.game, &reporter, games).await?;
+    reports
+        .insert_one(ChatReport {
+            id: None,
+            reporter,
+            message,
+            time: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        })
+        .await?;
+    Ok(())
+}
+
+/// Updates the author's name on every message they sent
+pub async fn rename_author(player: &Player, chats: &Chats) -> Result<()> {
+    chats
+        .messages
+        .update_many(
+            doc! {"author._id": player.id},
+            doc! {"$set": {"author.name": &player.name}},
+        )
+        .await?;
+    Ok(())
+}
+
+/// Drops the player's chat settings and takes them off everyone's muted list. Their messages stay,
+/// already renamed by the caller.
+pub async fn forget_player(player: ObjectId, chats: &Chats) -> Result<()> {
+    chats.settings.delete_one(doc! {"player": player}).await?;
+    chats
+        .settings
+        .update_many(doc! {"muted": player}, doc! {"$pull": {"muted": player}})
+        .await?;
+    Ok(())
+}
+
+/// Returns the messages sent so far along with a stream of the ones that haven't been sent yet.
+/// The stream is opened first so nothing sent in between gets lost.
+pub async fn create_chat_stream(
+    game_id: ObjectId,
+    player: &Player,
+    games: &dyn GameStore,
+    chats: &Collection<ChatMessage>,
+) -> Result<(
+    Vec<ChatMessage>,
+    ChangeStream<ChangeStreamEvent<ChatMessage>>,
+)> {
+    get_player_game(game_id, player, games).await?;
+    let matcher = doc! {"$match": {"fullDocument.game": game_id, "operationType": "insert"}};
+    let change_stream = chats.watch().pipeline([matcher]).await?;
+    let history = chats
+        .find(doc! {"game": game_id})
+        .sort(doc! {"_id": 1})
+        .await?
+        .try_collect()
+        .await?;
+    Ok((history, change_stream))
+}
+
+pub async fn next_chat_message(
+    change_stream: &mut ChangeStream<ChangeStreamEvent<ChatMessage>>,
+) -> Result<Option<ChatMessage>> {
+    while let Some(change) = change_stream.try_next().await? {
+        if let OperationType::Insert = change.operation_type {
+            return Ok(change.full_document);
+        }
+    }
+    Ok(None)
+}
@@ -1,30 +1,202 @@
-use std::env;
+use std::{env, net::IpAddr};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+
+use crate::common::account::Features;
 
 #[derive(Clone, Debug)]
 pub struct ServerConfig {
-    pub mongo_url: String,
-    pub prefix: String,
-    pub pem: String,
+    pub storage: Storage,
+    /// Ratings, tournaments and chat, which all need MongoDB
+    pub features: Features,
+    pub notifier: NotifierConfig,
+    /// Email is turned off unless an SMTP server is set
+    pub email: Option<EmailConfig>,
+    /// Lets players point webhooks at loopback and private network addresses, which is only
+    /// safe when nothing on the server's network trusts requests from it
+    pub private_webhooks: bool,
+    /// Proxies in front of the server, whose `X-Forwarded-For` headers say who the client is.
+    /// Nobody else's are believed, since clients can send whatever they like.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Where the app is hosted, which passkeys are tied to. Passkeys are turned off without it.
+    pub public_url: Option<String>,
+}
+
+/// Where games, players and sessions are kept. Ratings, chat and tournaments only exist in
+/// MongoDB, so the other backends need them turned off with `FEATURES`.
+#[derive(Clone, Debug)]
+pub enum Storage {
+    Mongo {
+        url: String,
+        prefix: String,
+    },
+    /// A single file, which is plenty for a small self-hosted server
+    Sqlite {
+        path: String,
+    },
+    /// Everything is lost on restart, which is handy for running locally and in tests
+    Memory,
+}
+
+/// How notifications reach players
+#[derive(Clone, Debug)]
+pub enum NotifierConfig {
+    /// Browser push notifications. The subject is a mailto: or https: URL push services can use
+    /// to contact whoever runs the server, and the PEM is the VAPID key they're signed with.
+    WebPush { subject: String, pem: String },
+    /// Every notification POSTed as JSON to one URL
+    Webhook { url: String },
+    /// Notifications are only kept in memory, for running without a push service
+    Recording,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    /// Login for the SMTP server, if it wants one
+    pub credentials: Option<(String, String)>,
+    pub from: String,
+    /// Where the app is hosted, for links in emails
+    pub public_url: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Upgrade to TLS after connecting, usually on port 587
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// No encryption, only for a relay or test server on the same machine
+    None,
 }
 
 impl ServerConfig {
     pub fn from_env() -> Result<Self> {
+        let storage = match env::var("STORAGE").as_deref() {
+            Ok("mongo") | Err(_) => Storage::Mongo {
+                url: required_env("MONGO")
+                    .or_else(|_| required_env("MONGO_URL"))
+                    .context("missing MONGO or MONGO_URL")?,
+                prefix: required_env("PREFIX")
+                    .or_else(|_| required_env("COLLECTION_PREFIX"))
+                    .context("missing PREFIX or COLLECTION_PREFIX")?,
+            },
+            Ok("sqlite") => Storage::Sqlite {
+                path: required_env("SQLITE_PATH")?,
+            },
+            Ok("memory") => Storage::Memory,
+            Ok(other) => bail!("unknown STORAGE {other}, expected mongo, sqlite or memory"),
+        };
+        let features = match env::var("FEATURES") {
+            Ok(features) => parse_features(&features)?,
+            Err(_) => Features {
+                ratings: true,
+                tournaments: true,
+                chat: true,
+            },
+        };
+        if features != Features::default() && !matches!(storage, Storage::Mongo { .. }) {
+            bail!(
+                "ratings, tournaments and chat need STORAGE=mongo, set FEATURES=none to run without them"
+            )
+        }
+        let notifier = match env::var("NOTIFIER").as_deref() {
+            Ok("webpush") | Err(_) => NotifierConfig::WebPush {
+                subject: required_env("VAPID_SUBJECT")
+                    .or_else(|_| required_env("PUBLIC_URL"))
+                    .context("missing VAPID_SUBJECT or PUBLIC_URL")?,
+                pem: required_env("PEM")
+                    .or_else(|_| required_env("VAPID_PEM"))
+                    .context("missing PEM or VAPID_PEM")?,
+            },
+            Ok("webhook") => NotifierConfig::Webhook {
+                url: required_env("NOTIFIER_WEBHOOK_URL")?,
+            },
+            Ok("recording") => NotifierConfig::Recording,
+            Ok(other) => {
+                bail!("unknown NOTIFIER {other}, expected webpush, webhook or recording")
+            }
+        };
+        let email = match env::var("SMTP_HOST") {
+            Ok(host) => {
+                let security = match env::var("SMTP_SECURITY").as_deref() {
+                    Ok("starttls") | Err(_) => SmtpSecurity::StartTls,
+                    Ok("tls") => SmtpSecurity::Tls,
+                    Ok("none") => SmtpSecurity::None,
+                    Ok(other) => {
+                        bail!("unknown SMTP_SECURITY {other}, expected starttls, tls or none")
+                    }
+                };
+                let default_port = match security {
+                    SmtpSecurity::StartTls => 587,
+                    SmtpSecurity::Tls => 465,
+                    SmtpSecurity::None => 25,
+                };
+                let port = match env::var("SMTP_PORT") {
+                    Ok(port) => port.parse().context("SMTP_PORT isn't a port")?,
+                    Err(_) => default_port,
+                };
+                let credentials = match (env::var("SMTP_USER"), env::var("SMTP_PASSWORD")) {
+                    (Ok(user), Ok(password)) => Some((user, password)),
+                    _ => None,
+                };
+                Some(EmailConfig {
+                    host,
+                    port,
+                    security,
+                    credentials,
+                    from: required_env("EMAIL_FROM")?,
+                    public_url: required_env("PUBLIC_URL")?
+                        .trim_end_matches('/')
+                        .to_string(),
+                })
+            }
+            Err(_) => None,
+        };
+        let private_webhooks = env::var("WEBHOOKS_ALLOW_PRIVATE").is_ok_and(|allow| allow == "1");
+        let trusted_proxies = match env::var("TRUSTED_PROXY") {
+            Ok(proxies) => proxies
+                .split(',')
+                .map(|proxy| {
+                    proxy
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("TRUSTED_PROXY {proxy} isn't an IP address"))
+                })
+                .collect::<Result<_>>()?,
+            Err(_) => Vec::new(),
+        };
         Ok(Self {
-            mongo_url: required_env("MONGO")
-                .or_else(|_| required_env("MONGO_URL"))
-                .context("missing MONGO or MONGO_URL")?,
-            prefix: required_env("PREFIX")
-                .or_else(|_| required_env("COLLECTION_PREFIX"))
-                .context("missing PREFIX or COLLECTION_PREFIX")?,
-            pem: required_env("PEM")
-                .or_else(|_| required_env("VAPID_PEM"))
-                .context("missing PEM or VAPID_PEM")?,
+            storage,
+            features,
+            notifier,
+            email,
+            private_webhooks,
+            trusted_proxies,
+            public_url: env::var("PUBLIC_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string()),
         })
     }
 }
 
+/// A comma-separated list like `ratings,chat`, or `none`
+fn parse_features(list: &str) -> Result<Features> {
+    let mut features = Features::default();
+    for feature in list.split(',').map(str::trim) {
+        match feature {
+            "none" => {}
+            "ratings" => features.ratings = true,
+            "tournaments" => features.tournaments = true,
+            "chat" => features.chat = true,
+            other => bail!("unknown feature {other}, expected ratings, tournaments, chat or none"),
+        }
+    }
+    Ok(features)
+}
+
 fn required_env(key: &str) -> Result<String> {
     env::var(key).with_context(|| format!("missing {key}"))
 }
@@ -0,0 +1,406 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    auth::{hash_password, hash_token, new_secret, reauthenticate, secret_matches},
+    config::EmailConfig,
+    limits::{Key, Limiter},
+    prelude::*,
+    storage::{GameStore, PlayerStore, SessionStore},
+};
+use crate::common::account::{EmailSettings, EmailStatus, EmailToken, MAX_EMAIL};
+
+mod smtp;
+#[cfg(test)]
+mod stub;
+
+/// How long the link to confirm an address works
+const VERIFY_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How long the link to reset a password works
+const RESET_LIFETIME: Duration = Duration::from_secs(60 * 60);
+/// Digests go out this long after midnight UTC
+const DIGEST_TIME: Duration = Duration::from_secs(8 * 60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Sends email through the configured SMTP server. Only there when the server has one.
+#[derive(Clone)]
+pub struct Mailer {
+    config: Arc<EmailConfig>,
+}
+
+impl Mailer {
+    pub fn new(config: EmailConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        smtp::send(&self.config, to, subject, body).await
+    }
+
+    /// A link to a page of the app
+    fn link(&self, path: &str) -> String {
+        format!("{}{path}", self.config.public_url)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs()
+}
+
+/// Only a rough check, since the verification email is the real test. Line breaks are the part
+/// that matters, as they would let an address add headers to the email.
+fn check_address(address: &str) -> Result<()> {
+    if address.len() > MAX_EMAIL {
+        bail!("Email addresses can be at most {MAX_EMAIL} characters")
+    }
+    let valid = address
+        .split_once('@')
+        .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'))
+        && !address
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | ','));
+    if !valid {
+        bail!("That doesn't look like an email address")
+    }
+    Ok(())
+}
+
+/// The token that goes in the link, which names the player so only their hash needs checking,
+/// and what gets stored.
+fn new_token(player: ObjectId, lifetime: Duration) -> (String, EmailToken) {
+    let secret = new_secret();
+    let stored = EmailToken {
+        hash: hash_token(&secret),
+        expires: now() + lifetime.as_secs(),
+    };
+    (format!("{}.{secret}", player.to_hex()), stored)
+}
+
+fn parse_token(token: &str) -> Result<(ObjectId, &str)> {
+    let (id, secret) = token
+        .split_once('.')
+        .ok_or_else(|| anyhow!("That link isn't valid"))?;
+    Ok((ObjectId::parse_str(id)?, secret))
+}
+
+fn token_matches(stored: &Option<EmailToken>, secret: &str) -> bool {
+    stored
+        .as_ref()
+        .is_some_and(|stored| stored.expires > now() && secret_matches(&stored.hash, secret))
+}
+
+pub async fn email_status(
+    player: &Player,
+    players: &dyn PlayerStore,
+    mailer: Option<&Mailer>,
+) -> Result<EmailStatus> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let email = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?
+        .email;
+    Ok(EmailStatus {
+        available: mailer.is_some(),
+        address: email.as_ref().map(|email| email.address.clone()),
+        verified: email.as_ref().is_some_and(|email| email.verified),
+        digest: email.as_ref().is_some_and(|email| email.digest),
+    })
+}
+
+/// Sets or removes the player's address. New addresses get a link to confirm they're theirs.
+/// Since an address can reset the password, changing it takes the password too.
+pub async fn set_email(
+    player: &Player,
+    address: String,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    mailer: Option<&Mailer>,
+) -> Result<()> {
+    let mut stored = reauthenticate(player, password, players, limiter).await?;
+    let address = address.trim().to_string();
+    if address.is_empty() {
+        stored.email = None;
+        return players.replace(stored).await;
+    }
+    let Some(mailer) = mailer else {
+        bail!("This server can't send email")
+    };
+    check_address(&address)?;
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let (token, verify) = new_token(id, VERIFY_LIFETIME);
+    stored.email = Some(EmailSettings {
+        address: address.clone(),
+        verified: false,
+        digest: false,
+        verify: Some(verify),
+        reset: None,
+    });
+    players.replace(stored).await?;
+    let link = mailer.link(&format!("/ui/verify/{token}"));
+    mailer
+        .send(
+            &address,
+            "Confirm your Duck Chess email",
+            &format!(
+                "Hi {},\n\nOpen this link to confirm this is your email address:\n\n{link}\n\nIf you didn't add this address to a Duck Chess account, you can ignore this email.\n",
+                player.name
+            ),
+        )
+        .await
+}
+
+pub async fn verify_email(token: &str, players: &dyn PlayerStore) -> Result<()> {
+    let (id, secret) = parse_token(token)?;
+    let mut stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("That link isn't valid"))?;
+    let Some(email) = &mut stored.email else {
+        bail!("That link isn't valid")
+    };
+    if !token_matches(&email.verify, secret) {
+        bail!("That link has expired or was already used")
+    }
+    email.verified = true;
+    email.verify = None;
+    players.replace(stored).await
+}
+
+pub async fn set_digest(player: &Player, digest: bool, players: &dyn PlayerStore) -> Result<()> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let mut stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?;
+    match &mut stored.email {
+        Some(email) if email.verified => email.digest = digest,
+        _ => bail!("Confirm your email address first"),
+    }
+    players.replace(stored).await
+}
+
+/// Emails a reset link if the account has a confirmed address. Returns before looking the
+/// account up, so neither the answer nor how long it takes says who has an address.
+pub async fn request_reset(
+    name: &str,
+    players: Arc<dyn PlayerStore>,
+    mailer: Option<&Mailer>,
+    limiter: &Limiter,
+) -> Result<()> {
+    let Some(mailer) = mailer else {
+        bail!("This server can't send email")
+    };
+    let key = [Key::Reset(name.to_string())];
+    limiter.check(&key)?;
+    limiter.failed(&key);
+    let (name, mailer) = (name.to_string(), mailer.clone());
+    tokio::spawn(async move {
+        if let Err(error) = send_reset(&name, &*players, &mailer).await {
+            log::error!("sending a password reset failed: {error:?}");
+        }
+    });
+    Ok(())
+}
+
+async fn send_reset(name: &str, players: &dyn PlayerStore, mailer: &Mailer) -> Result<()> {
+    let Some(mut stored) = players.find_by_name(name).await? else {
+        return Ok(());
+    };
+    let id = stored.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let Some(email) = &mut stored.email else {
+        return Ok(());
+    };
+    if !email.verified {
+        return Ok(());
+    }
+    let (token, reset) = new_token(id, RESET_LIFETIME);
+    email.reset = Some(reset);
+    let address = email.address.clone();
+    players.replace(stored).await?;
+    let link = mailer.link(&format!("/ui/reset/{token}"));
+    mailer
+        .send(
+            &address,
+            "Reset your Duck Chess password",
+            &format!(
+                "Hi {name},\n\nOpen this link within an hour to choose a new password:\n\n{link}\n\nIf you didn't ask for this, you can ignore this email and your password stays the same.\n"
+            ),
+        )
+        .await
+}
+
+/// Sets a new password from a reset link and logs out everywhere, in case someone else had the
+/// old one.
+pub async fn reset_password(
+    token: &str,
+    password: &str,
+    players: &dyn PlayerStore,
+    sessions: &dyn SessionStore,
+) -> Result<()> {
+    let (id, secret) = parse_token(token)?;
+    let mut stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("That link isn't valid"))?;
+    let Some(email) = &mut stored.email else {
+        bail!("That link isn't valid")
+    };
+    if !token_matches(&email.reset, secret) {
+        bail!("That link has expired or was already used")
+    }
+    email.reset = None;
+    stored.password = hash_password(password)?;
+    players.replace(stored).await?;
+    sessions.delete_player_sessions(id).await
+}
+
+/// Emails each player who asked for it a list of the games waiting on their move, once a day
+pub fn spawn_digests(mailer: Mailer, players: Arc<dyn PlayerStore>, games: Arc<dyn GameStore>) {
+    tokio::spawn(async move {
+        loop {
+            let into_day = now() % DAY.as_secs();
+            let wait = (DIGEST_TIME.as_secs() + DAY.as_secs() - into_day) % DAY.as_secs();
+            // Sleep at least a minute so a fast run doesn't send twice
+            tokio::time::sleep(Duration::from_secs(wait.max(60))).await;
+            if let Err(error) = send_digests(&mailer, &*players, &*games).await {
+                log::error!("sending digests failed: {error:?}");
+            }
+        }
+    });
+}
+
+async fn send_digests(
+    mailer: &Mailer,
+    players: &dyn PlayerStore,
+    games: &dyn GameStore,
+) -> Result<()> {
+    for stored in players.digest_players().await? {
+        let (Some(id), Some(email)) = (stored.id, &stored.email) else {
+            continue;
+        };
+        let waiting: Vec<String> = games
+            .player_games(id)
+            .await?
+            .into_iter()
+            .filter_map(|any_game| match &any_game.game {
+                GameOrRequest::Game(game) if game.is_player_turn(&stored.player) => Some(format!(
+                    "{}: {}",
+                    game.opponent(&stored.player).name,
+                    mailer.link(&format!("/ui/game/{}", any_game.id?.to_hex()))
+                )),
+                _ => None,
+            })
+            .collect();
+        if waiting.is_empty() {
+            continue;
+        }
+        let body = format!(
+            "Hi {},\n\nThese games are waiting on your move:\n\n{}\n\nYou can turn these emails off on your account page.\n",
+            stored.name,
+            waiting.join("\n")
+        );
+        // One bad address shouldn't stop everyone else's digest
+        if let Err(error) = mailer
+            .send(&email.address, "Your move in Duck Chess", &body)
+            .await
+        {
+            log::error!("sending digest to {} failed: {error:?}", stored.name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stub::Stub, *};
+    use crate::server::{
+        auth::new_user,
+        limits::ClientIp,
+        storage::{MemoryPlayers, MemorySessions},
+    };
+
+    const PASSWORD: &str = "passwordA1";
+
+    /// The token from a link to `path` in an email
+    fn token_in(message: &str, path: &str) -> String {
+        let (_, rest) = message
+            .split_once(&format!("https://duck.example{path}"))
+            .unwrap();
+        rest.split_whitespace().next().unwrap().to_string()
+    }
+
+    /// Signs up alice and confirms her address
+    async fn alice(players: &dyn PlayerStore, mailer: &Mailer, stub: &mut Stub) -> Player {
+        let player = new_user(
+            players,
+            &Limiter::default(),
+            &ClientIp("127.0.0.1".into()),
+            "alice".into(),
+            PASSWORD.into(),
+        )
+        .await
+        .unwrap();
+        set_email(
+            &player,
+            "alice@duck.example".into(),
+            PASSWORD,
+            players,
+            &Limiter::default(),
+            Some(mailer),
+        )
+        .await
+        .unwrap();
+        let message = stub.message().await.unwrap();
+        verify_email(&token_in(&message, "/ui/verify/"), players)
+            .await
+            .unwrap();
+        player
+    }
+
+    #[tokio::test]
+    async fn resets_a_password_by_email() {
+        let mut stub = Stub::start().await;
+        let mailer = Mailer::new(stub.config.clone());
+        let players: Arc<dyn PlayerStore> = Arc::new(MemoryPlayers::default());
+        let sessions = MemorySessions::default();
+        let player = alice(&*players, &mailer, &mut stub).await;
+
+        request_reset("alice", players.clone(), Some(&mailer), &Limiter::default())
+            .await
+            .unwrap();
+        let message = stub.message().await.unwrap();
+        assert!(message.contains("To: alice@duck.example"));
+        let token = token_in(&message, "/ui/reset/");
+        reset_password(&token, "passwordB2", &*players, &sessions)
+            .await
+            .unwrap();
+        assert!(
+            reauthenticate(&player, "passwordB2", &*players, &Limiter::default())
+                .await
+                .is_ok()
+        );
+        // Links only work once
+        assert!(
+            reset_password(&token, "passwordC3", &*players, &sessions)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_for_unknown_name_looks_the_same() {
+        let mut stub = Stub::start().await;
+        let mailer = Mailer::new(stub.config.clone());
+        let players: Arc<dyn PlayerStore> = Arc::new(MemoryPlayers::default());
+        request_reset("nobody", players, Some(&mailer), &Limiter::default())
+            .await
+            .unwrap();
+        assert!(stub.message().await.is_err());
+    }
+}
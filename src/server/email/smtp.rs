@@ -0,0 +1,145 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{ClientConfig, RootCertStore, crypto::ring, pki_types::ServerName},
+};
+
+use super::super::{
+    config::{EmailConfig, SmtpSecurity},
+    prelude::*,
+};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Just enough SMTP to hand a message to a relay, which takes care of delivering it
+struct Connection {
+    stream: BufReader<Box<dyn Stream>>,
+}
+
+impl Connection {
+    /// Reads a possibly multi-line reply and fails unless it has the expected code
+    async fn reply(&mut self, expected: u16) -> Result<String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                bail!("SMTP server hung up")
+            }
+            reply.push_str(&line);
+            // The last line of a reply has a space after the code instead of a dash
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        let code: u16 = reply
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        if code != expected {
+            bail!("SMTP server said {}", reply.trim_end())
+        }
+        Ok(reply)
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String> {
+        self.stream
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+        self.reply(expected).await
+    }
+}
+
+fn tls_connector() -> Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+async fn start_tls(stream: Box<dyn Stream>, host: &str) -> Result<Box<dyn Stream>> {
+    let name = ServerName::try_from(host.to_string())?;
+    Ok(Box::new(tls_connector()?.connect(name, stream).await?))
+}
+
+/// Lines starting with a dot get another one so they aren't read as the end of the message
+fn encode_body(body: &str) -> String {
+    body.lines()
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{line}\r\n")
+            } else {
+                format!("{line}\r\n")
+            }
+        })
+        .collect()
+}
+
+/// Gives up on a server that stops answering, rather than waiting on it forever
+pub async fn send(config: &EmailConfig, to: &str, subject: &str, body: &str) -> Result<()> {
+    tokio::time::timeout(SEND_TIMEOUT, send_message(config, to, subject, body))
+        .await
+        .map_err(|_| anyhow!("SMTP server took too long to answer"))?
+}
+
+async fn send_message(config: &EmailConfig, to: &str, subject: &str, body: &str) -> Result<()> {
+    let tcp: Box<dyn Stream> =
+        Box::new(TcpStream::connect((config.host.as_str(), config.port)).await?);
+    let stream = match config.security {
+        SmtpSecurity::Tls => start_tls(tcp, &config.host).await?,
+        SmtpSecurity::StartTls | SmtpSecurity::None => tcp,
+    };
+    let mut connection = Connection {
+        stream: BufReader::new(stream),
+    };
+    connection.reply(220).await?;
+    // Servers want to know who's connecting, and the sender's domain is the best name there is
+    let domain = config
+        .from
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+    let hello = format!("EHLO {domain}");
+    connection.command(&hello, 250).await?;
+    if config.security == SmtpSecurity::StartTls {
+        connection.command("STARTTLS", 220).await?;
+        let stream = start_tls(connection.stream.into_inner(), &config.host).await?;
+        connection = Connection {
+            stream: BufReader::new(stream),
+        };
+        connection.command(&hello, 250).await?;
+    }
+    if let Some((user, password)) = &config.credentials {
+        let login = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            format!("\0{user}\0{password}"),
+        );
+        connection
+            .command(&format!("AUTH PLAIN {login}"), 235)
+            .await?;
+    }
+    connection
+        .command(&format!("MAIL FROM:<{}>", config.from), 250)
+        .await?;
+    connection.command(&format!("RCPT TO:<{to}>"), 250).await?;
+    connection.command("DATA", 354).await?;
+    let message = format!(
+        "From: {}\r\nTo: {to}\r\nSubject: {subject}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}.",
+        config.from,
+        encode_body(body),
+    );
+    connection.command(&message, 250).await?;
+    connection.command("QUIT", 221).await?;
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+
+use super::super::{
+    config::{EmailConfig, SmtpSecurity},
+    prelude::*,
+};
+
+/// A pretend SMTP server on localhost for tests, which accepts every message and keeps its body
+pub struct Stub {
+    pub config: EmailConfig,
+    received: mpsc::UnboundedReceiver<String>,
+}
+
+impl Stub {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(BufReader::new(stream), sender.clone()));
+            }
+        });
+        Stub {
+            config: EmailConfig {
+                host: "127.0.0.1".into(),
+                port,
+                security: SmtpSecurity::None,
+                credentials: None,
+                from: "duck@duck.example".into(),
+                public_url: "https://duck.example".into(),
+            },
+            received,
+        }
+    }
+
+    /// The next message sent, or an error if none shows up soon
+    pub async fn message(&mut self) -> Result<String> {
+        tokio::time::timeout(std::time::Duration::from_secs(5), self.received.recv())
+            .await?
+            .ok_or_else(|| anyhow!("Stub stopped"))
+    }
+}
+
+async fn serve(
+    mut stream: BufReader<tokio::net::TcpStream>,
+    received: mpsc::UnboundedSender<String>,
+) -> Result<()> {
+    stream.write_all(b"220 stub\r\n").await?;
+    let mut line = String::new();
+    while stream.read_line(&mut line).await? > 0 {
+        let reply: &[u8] = match line.get(..4).unwrap_or_default() {
+            "EHLO" | "MAIL" | "RCPT" => b"250 ok\r\n",
+            "DATA" => {
+                stream.write_all(b"354 go ahead\r\n").await?;
+                let mut message = String::new();
+                loop {
+                    let mut data = String::new();
+                    if stream.read_line(&mut data).await? == 0 || data == ".\r\n" {
+                        break;
+                    }
+                    message.push_str(&data);
+                }
+                let _ = received.send(message);
+                b"250 queued\r\n"
+            }
+            "QUIT" => {
+                stream.write_all(b"221 bye\r\n").await?;
+                return Ok(());
+            }
+            _ => b"502 not here\r\n",
+        };
+        stream.write_all(reply).await?;
+        line.clear();
+    }
+    Ok(())
+}
@@ -1,30 +1,44 @@
-use crate::common::game::GameTypes;
-use futures::TryStreamExt;
-use mongodb::change_stream::{
-    ChangeStream,
-    event::{ChangeStreamEvent, OperationType},
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
-use web_push::{VapidSignatureBuilder, WebPushClient, WebPushMessageBuilder};
+
+use crate::common::{
+    game::{GameTypes, SomeGame},
+    tournament::Tournament,
+};
+use futures::StreamExt;
 
 use super::{
+    notation::some_turn_notation,
+    notifications::{Event, Notifier, Payload, send_notification},
     prelude::*,
-    state::{Notifier, SessionRecord},
+    storage::{Conflict, GameFeed, GameStore},
+    webhooks::{GameEvent, HookEvent, send_webhooks},
 };
 
-pub async fn get_player_games(
-    player: &Player,
-    games: &Collection<AnyGame>,
-) -> Result<Vec<AnyGame>> {
-    let filter = doc! {"$or": [{"game.maker._id": player.id}, {"game.joiner._id": player.id}]};
-    Ok(games.find(filter).await?.try_collect().await?)
+pub async fn get_player_games(player: &Player, games: &dyn GameStore) -> Result<Vec<AnyGame>> {
+    games.player_games(player.id.unwrap()).await
 }
 
-pub async fn get_open_games(games: &Collection<AnyGame>) -> Result<Vec<WithId<GameRequest>>> {
-    let open_games: Vec<AnyGame> = games
-        .find(doc! {"game.type": "Request"})
+pub async fn get_player_game(
+    game_id: ObjectId,
+    player: &Player,
+    games: &dyn GameStore,
+) -> Result<AnyGame> {
+    let with_id = games
+        .get(game_id)
         .await?
-        .try_collect()
-        .await?;
+        .ok_or_else(|| anyhow!("No valid game for id"))?;
+    if !with_id.game.in_game(player) {
+        bail!("No valid game")
+    }
+    Ok(with_id)
+}
+
+pub async fn get_open_games(games: &dyn GameStore) -> Result<Vec<WithId<GameRequest>>> {
+    let open_games = games.open_requests().await?;
     Ok(open_games
         .into_iter()
         .map(|game| match game.game {
@@ -34,35 +48,51 @@ pub async fn get_open_games(games: &Collection<AnyGame>) -> Result<Vec<WithId<Ga
         .collect())
 }
 
-pub async fn new_open_game(maker: Player, games: &Collection<AnyGame>) -> Result<ObjectId> {
+pub async fn get_public_games(player: &Player, games: &dyn GameStore) -> Result<Vec<AnyGame>> {
+    games.public_games(player.id.unwrap()).await
+}
+
+pub async fn new_open_game(
+    maker: Player,
+    game_type: GameTypes,
+    visibility: Visibility,
+    rated: bool,
+    time_control: TimeControl,
+    games: &dyn GameStore,
+) -> Result<ObjectId> {
     let open_game = AnyGame {
         id: None,
-        game: GameOrRequest::Request(GameRequest {
-            maker,
-            game_type: GameTypes::Square,
-        }),
+        game: GameOrRequest::Request(GameRequest { maker, game_type }),
+        visibility,
+        rated,
+        time_control,
+        tournament: None,
+        version: 0,
+        times: GameTimes {
+            created: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+            ..GameTimes::default()
+        },
     };
 
-    Ok(games
-        .insert_one(open_game)
-        .await?
-        .inserted_id
-        .as_object_id()
-        .unwrap())
+    games.insert(open_game).await
 }
 
 pub async fn join_open_game(
     game_id: ObjectId,
     joiner: Player,
-    games: &Collection<AnyGame>,
-    sessions: &Collection<SessionRecord>,
+    games: &dyn GameStore,
     notifier: &Notifier,
 ) -> Result<()> {
-    let filter = doc! {"_id": game_id};
-    let open_game = games.find_one(filter.clone()).await?;
+    let open_game = games.get(game_id).await?;
     if let Some(AnyGame {
         id,
         game: GameOrRequest::Request(request),
+        visibility,
+        rated,
+        time_control,
+        tournament,
+        version,
+        times,
     }) = open_game
     {
         let maker_color = if rand::random() {
@@ -70,22 +100,57 @@ pub async fn join_open_game(
         } else {
             Color::Black
         };
-        let maker_id = request.maker.id.unwrap();
-        let joiner_id = joiner.id.unwrap();
+        let maker = request.maker.clone();
         let game = request
             .game_type
-            .mk_game(request.maker, joiner, maker_color);
+            .mk_game(request.maker, joiner.clone(), maker_color);
+        let started = GameEvent::new(HookEvent::GameStarted, game_id, &game);
         games
-            .replace_one(
-                filter,
-                AnyGame {
-                    id,
-                    game: GameOrRequest::Game(game),
+            .replace(AnyGame {
+                id,
+                game: GameOrRequest::Game(game.clone()),
+                visibility,
+                rated,
+                time_control,
+                tournament,
+                version,
+                times: GameTimes {
+                    started: Some(SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs()),
+                    ..times
                 },
-            )
-            .await?;
-        send_notification(maker_id, "Duck Chess game started!", sessions, notifier).await?;
-        send_notification(joiner_id, "Duck Chess game started!", sessions, notifier).await?;
+            })
+            .await
+            // Only the first of two people joining at once gets the game
+            .map_err(|error| {
+                if error.is::<Conflict>() {
+                    anyhow!("Someone else joined this game first")
+                } else {
+                    error
+                }
+            })?;
+        send_notification(
+            maker.id.unwrap(),
+            Payload::new(
+                Event::GameStarted,
+                game_id,
+                &joiner,
+                None,
+                format!("{} joined your game", joiner.name),
+            ),
+            notifier,
+        );
+        send_notification(
+            joiner.id.unwrap(),
+            Payload::new(
+                Event::GameStarted,
+                game_id,
+                &maker,
+                None,
+                format!("Your game against {} started", maker.name),
+            ),
+            notifier,
+        );
+        send_webhooks(&game, started, &notifier.webhooks);
         Ok(())
     } else {
         bail!("Not a game!")
@@ -95,18 +160,28 @@ pub async fn join_open_game(
 pub async fn apply_turn(
     turn: WithId<SomeTurn>,
     player: Player,
-    sessions: &Collection<SessionRecord>,
     notifier: &Notifier,
-    games: &Collection<AnyGame>,
+    games: &dyn GameStore,
+    ratings: Option<&Collection<RatingRecord>>,
+    tournaments: Option<&Collection<Tournament>>,
 ) -> Result<()> {
-    let filter = doc! {"_id": turn.id};
     let with_id = games
-        .find_one(filter.clone())
+        .get(turn.id)
         .await?
         .ok_or_else(|| anyhow!("Not valid"))?;
 
+    if let GameOrRequest::Game(game) | GameOrRequest::Completed(game) = &with_id.game
+        && already_applied(game, &player, &turn)
+    {
+        return Ok(());
+    }
+
     if let GameOrRequest::Game(mut game) = with_id.game {
+        let last_move = some_turn_notation(&game.some_game, &turn);
         game.apply_turn(&player, *turn)?;
+        let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        let mut times = with_id.times;
+        times.turns.push(now);
 
         let other_player = if game.turn() == game.maker_color {
             game.maker.id.unwrap()
@@ -114,92 +189,437 @@ pub async fn apply_turn(
             game.joiner.id.unwrap()
         };
 
-        let message = if game.game_over().is_none() {
+        let event = if game.game_over().is_none() {
             games
-                .replace_one(
-                    filter,
-                    AnyGame {
-                        id: with_id.id,
-                        game: GameOrRequest::Game(game),
-                    },
-                )
+                .replace(AnyGame {
+                    id: with_id.id,
+                    game: GameOrRequest::Game(game.clone()),
+                    visibility: with_id.visibility,
+                    rated: with_id.rated,
+                    time_control: with_id.time_control,
+                    tournament: with_id.tournament,
+                    version: with_id.version,
+                    times,
+                })
                 .await?;
-            "It's your turn in a Duck Chess game!"
+            Event::YourTurn
         } else {
-            games
-                .replace_one(
-                    filter,
-                    AnyGame {
-                        id: with_id.id,
-                        game: GameOrRequest::Completed(game),
-                    },
-                )
-                .await?;
-            "A Duck Chess game has ended!"
+            game.winner = game.game_over();
+            times.ended = Some(now);
+            save_completed(
+                AnyGame {
+                    id: with_id.id,
+                    game: GameOrRequest::Completed(game.clone()),
+                    visibility: with_id.visibility,
+                    rated: with_id.rated,
+                    time_control: with_id.time_control,
+                    tournament: with_id.tournament,
+                    version: with_id.version,
+                    times,
+                },
+                notifier,
+                games,
+                ratings,
+                tournaments,
+            )
+            .await?;
+            Event::GameEnded
         };
 
-        send_notification(other_player, message, sessions, notifier).await?;
+        let played = match &last_move {
+            Some(notation) => format!("{} played {notation}", player.name),
+            None => format!("{} moved", player.name),
+        };
+        let body = if event == Event::YourTurn {
+            format!("{played}. It's your turn!")
+        } else {
+            format!("{played}. The game is over.")
+        };
+        let hook_event = if event == Event::YourTurn {
+            HookEvent::Turn
+        } else {
+            HookEvent::GameEnded
+        };
+        send_webhooks(
+            &game,
+            GameEvent {
+                last_move: last_move.clone(),
+                ..GameEvent::new(hook_event, turn.id, &game)
+            },
+            &notifier.webhooks,
+        );
+        send_notification(
+            other_player,
+            Payload::new(event, turn.id, &player, last_move, body),
+            notifier,
+        );
         Ok(())
     } else {
         bail!("Invalid game!")
     }
 }
 
-pub async fn create_change_stream(
+/// Ends the game with the player's opponent as the winner
+pub async fn resign(
     game_id: ObjectId,
     player: Player,
-    games: &Collection<AnyGame>,
-) -> Result<(AnyGame, ChangeStream<ChangeStreamEvent<AnyGame>>)> {
-    let filter = doc! {"_id": game_id};
+    notifier: &Notifier,
+    games: &dyn GameStore,
+    ratings: Option<&Collection<RatingRecord>>,
+    tournaments: Option<&Collection<Tournament>>,
+) -> Result<()> {
+    let with_id = get_player_game(game_id, &player, games).await?;
+    let GameOrRequest::Game(mut game) = with_id.game else {
+        bail!("This game isn't being played")
+    };
+    // Someone playing themselves resigns whichever side is to move
+    let loser = if game.is_player_turn(&player) {
+        game.turn()
+    } else {
+        game.turn().other()
+    };
+    game.winner = Some(loser.other());
+    let mut times = with_id.times;
+    times.ended = Some(SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs());
+    save_completed(
+        AnyGame {
+            game: GameOrRequest::Completed(game.clone()),
+            times,
+            ..with_id
+        },
+        notifier,
+        games,
+        ratings,
+        tournaments,
+    )
+    .await?;
+
+    send_webhooks(
+        &game,
+        GameEvent::new(HookEvent::GameEnded, game_id, &game),
+        &notifier.webhooks,
+    );
+    send_notification(
+        game.opponent(&player).id.unwrap(),
+        Payload::new(
+            Event::GameEnded,
+            game_id,
+            &player,
+            None,
+            format!("{} resigned. The game is over.", player.name),
+        ),
+        notifier,
+    );
+    Ok(())
+}
+
+/// Resigns every game the player is still in, so nobody is left waiting on them
+pub async fn resign_all(
+    player: &Player,
+    notifier: &Notifier,
+    games: &dyn GameStore,
+    ratings: Option<&Collection<RatingRecord>>,
+    tournaments: Option<&Collection<Tournament>>,
+) -> Result<()> {
+    for with_id in get_player_games(player, games).await? {
+        let (Some(id), GameOrRequest::Game(_)) = (with_id.id, &with_id.game) else {
+            continue;
+        };
+        loop {
+            match resign(id, player.clone(), notifier, games, ratings, tournaments).await {
+                // Their opponent moved in between, so try again with the new version
+                Err(error) if error.is::<Conflict>() => continue,
+                result => break result?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How often games are checked for a player who ran out of time
+const DEADLINE_SWEEP: Duration = Duration::from_secs(10 * 60);
+/// Players are reminded to move once this much of their time is left
+const DEADLINE_WARNING: u64 = 12 * 60 * 60;
+const DAY: u64 = 24 * 60 * 60;
+
+/// When the player to move loses, for games with a time limit
+fn deadline(with_id: &AnyGame) -> Option<u64> {
+    let TimeControl::DaysPerMove(days) = with_id.time_control else {
+        return None;
+    };
+    // The clock starts once someone joins, then again after every turn
+    let times = &with_id.times;
+    let last = times
+        .turns
+        .last()
+        .copied()
+        .or(times.started)
+        .unwrap_or_else(|| with_id.last_activity());
+    Some(last + u64::from(days) * DAY)
+}
+
+/// Checks games with a time limit every few minutes. Players close to running out of time get a
+/// reminder, and the ones who ran out lose.
+pub fn spawn_deadline_sweep(
+    notifier: Notifier,
+    games: Arc<dyn GameStore>,
+    ratings: Option<Collection<RatingRecord>>,
+    tournaments: Option<Collection<Tournament>>,
+) {
+    tokio::spawn(async move {
+        // Turns players were already reminded about, by game and number of turns played. This
+        // isn't saved, so a restart can send a reminder twice.
+        let mut warned = HashSet::new();
+        loop {
+            tokio::time::sleep(DEADLINE_SWEEP).await;
+            let checked = check_deadlines(
+                &mut warned,
+                &notifier,
+                &*games,
+                ratings.as_ref(),
+                tournaments.as_ref(),
+            );
+            if let Err(error) = checked.await {
+                log::error!("ending games past their deadline failed: {error:?}");
+            }
+        }
+    });
+}
+
+async fn check_deadlines(
+    warned: &mut HashSet<(ObjectId, usize)>,
+    notifier: &Notifier,
+    games: &dyn GameStore,
+    ratings: Option<&Collection<RatingRecord>>,
+    tournaments: Option<&Collection<Tournament>>,
+) -> Result<()> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let timed = games.timed_games().await?;
+    warned.retain(|(id, turns)| {
+        timed
+            .iter()
+            .any(|game| game.id == Some(*id) && game.times.turns.len() == *turns)
+    });
+    for with_id in timed {
+        let (Some(game_id), Some(deadline)) = (with_id.id, deadline(&with_id)) else {
+            continue;
+        };
+        let GameOrRequest::Game(mut game) = with_id.game.clone() else {
+            continue;
+        };
+        if now < deadline {
+            let to_move = if game.maker_color == game.turn() {
+                &game.maker
+            } else {
+                &game.joiner
+            };
+            if deadline - now <= DEADLINE_WARNING
+                && warned.insert((game_id, with_id.times.turns.len()))
+                && let Some(to) = to_move.id
+            {
+                let hours = (deadline - now).div_ceil(60 * 60);
+                send_notification(
+                    to,
+                    Payload::new(
+                        Event::Deadline,
+                        game_id,
+                        game.opponent(to_move),
+                        None,
+                        format!("You have {hours} hours left to move before you lose on time."),
+                    ),
+                    notifier,
+                );
+            }
+            continue;
+        }
+        let loser = game.turn();
+        game.winner = Some(loser.other());
+        let mut times = with_id.times.clone();
+        times.ended = Some(now);
+        let saved = save_completed(
+            AnyGame {
+                game: GameOrRequest::Completed(game.clone()),
+                times,
+                ..with_id
+            },
+            notifier,
+            games,
+            ratings,
+            tournaments,
+        )
+        .await;
+        match saved {
+            Ok(()) => {}
+            // They moved while this was running
+            Err(error) if error.is::<Conflict>() => continue,
+            Err(error) => return Err(error),
+        }
+
+        send_webhooks(
+            &game,
+            GameEvent::new(HookEvent::GameEnded, game_id, &game),
+            &notifier.webhooks,
+        );
+        let (winner, loser) = if game.maker_color == loser {
+            (&game.joiner, &game.maker)
+        } else {
+            (&game.maker, &game.joiner)
+        };
+        for (to, opponent, body) in [
+            (
+                loser,
+                winner,
+                "You ran out of time. The game is over.".to_string(),
+            ),
+            (
+                winner,
+                loser,
+                format!("{} ran out of time. The game is over.", loser.name),
+            ),
+        ] {
+            if let Some(to) = to.id {
+                send_notification(
+                    to,
+                    Payload::new(Event::GameEnded, game_id, opponent, None, body),
+                    notifier,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Saves a game that just ended, then counts it towards ratings and any tournament it's part of
+async fn save_completed(
+    completed: AnyGame,
+    notifier: &Notifier,
+    games: &dyn GameStore,
+    ratings: Option<&Collection<RatingRecord>>,
+    tournaments: Option<&Collection<Tournament>>,
+) -> Result<()> {
+    let id = completed.id.ok_or_else(|| anyhow!("Game has no id"))?;
+    let (rated, tournament) = (completed.rated, completed.tournament);
+    let GameOrRequest::Completed(game) = completed.game.clone() else {
+        bail!("Game isn't over")
+    };
+    games.replace(completed).await?;
+    // Ratings and tournaments are only around when MongoDB is. The game is saved by now, so
+    // failing here would only tell the player their winning move didn't go through.
+    if let (true, Some(ratings)) = (rated, ratings)
+        && let Err(error) = super::ratings::record_result(id, &game, ratings).await
+    {
+        log::error!("recording the rating change for {id} failed: {error:?}");
+    }
+    if let (Some(tournament), Some(tournaments)) = (tournament, tournaments)
+        && let Err(error) =
+            super::tournaments::record_result(tournament, id, &game, tournaments, games, notifier)
+                .await
+    {
+        log::error!("recording the result of {id} in tournament {tournament} failed: {error:?}");
+    }
+    Ok(())
+}
+
+/// A turn sent twice, say because the first response never made it back, shows up as the last
+/// turn made by whoever sent it. They can't move again until their opponent has, so this can't
+/// be mistaken for a new turn.
+fn already_applied(game: &Game, player: &Player, turn: &SomeTurn) -> bool {
+    let last_turn = match (&game.some_game, turn) {
+        (SomeGame::Square(game), SomeTurn::Square(turn)) => game.turns.last() == Some(turn),
+        (SomeGame::Hex(game), SomeTurn::Hex(turn)) => game.turns.last() == Some(turn),
+        _ => false,
+    };
+    last_turn && !game.is_player_turn(player)
+}
+
+pub async fn set_visibility(
+    game_id: ObjectId,
+    visibility: Visibility,
+    player: &Player,
+    games: &dyn GameStore,
+) -> Result<()> {
     let with_id = games
-        .find_one(filter)
+        .get(game_id)
         .await?
         .ok_or_else(|| anyhow!("No valid game for id"))?;
+    if let GameOrRequest::Request(request) = &with_id.game
+        && &request.maker != player
+    {
+        bail!("Only the maker can change who sees an open game")
+    }
+    if !with_id.game.in_game(player) {
+        bail!("Only players can change who watches a game")
+    }
+    games
+        .replace(AnyGame {
+            visibility,
+            ..with_id
+        })
+        .await?;
+    Ok(())
+}
+
+async fn watch_game(game_id: ObjectId, games: &dyn GameStore) -> Result<(AnyGame, GameFeed)> {
+    // Watching first means nothing saved between the two calls gets missed
+    let feed = games.watch(game_id).await?;
+    let with_id = games
+        .get(game_id)
+        .await?
+        .ok_or_else(|| anyhow!("No valid game for id"))?;
+    Ok((with_id, feed))
+}
+
+pub async fn create_change_stream(
+    game_id: ObjectId,
+    player: Player,
+    games: &dyn GameStore,
+) -> Result<(AnyGame, GameFeed)> {
+    let (with_id, change_stream) = watch_game(game_id, games).await?;
     if !with_id.game.in_game(&player) {
         bail!("No valid game")
     }
+    Ok((with_id, change_stream))
+}
 
-    let matcher = doc! {"$match": {"documentKey._id": game_id, "operationType": "replace"}};
-    let change_stream = games.watch().pipeline([matcher]).await?;
+pub async fn create_spectator_stream(
+    game_id: ObjectId,
+    player: &Player,
+    games: &dyn GameStore,
+) -> Result<(AnyGame, GameFeed)> {
+    let (with_id, change_stream) = watch_game(game_id, games).await?;
+    if !can_view(&with_id, player) {
+        bail!("This game isn't open to spectators")
+    }
     Ok((with_id, change_stream))
 }
 
 pub async fn next_game_update(
     player: &Player,
-    change_stream: &mut ChangeStream<ChangeStreamEvent<AnyGame>>,
+    change_stream: &mut GameFeed,
 ) -> Result<Option<AnyGame>> {
-    while let Some(change) = change_stream.try_next().await? {
-        if let OperationType::Replace = change.operation_type {
-            let game = change.full_document.unwrap();
-            if game.game.in_game(player) {
-                return Ok(Some(game));
-            }
-            return Ok(None);
-        }
-    }
-    Ok(None)
+    next_visible_update(change_stream, |game| game.game.in_game(player)).await
 }
 
-async fn send_notification(
-    player: ObjectId,
-    message: &str,
-    sessions: &Collection<SessionRecord>,
-    notifier: &Notifier,
-) -> Result<()> {
-    let mut subscriptions = sessions.find(doc! { "player._id": player }).await?;
-
-    while let Some(session) = subscriptions.try_next().await? {
-        if let Some(subscription) = session.subscription {
-            let mut sig_builder: VapidSignatureBuilder<'_> =
-                notifier.crypto.clone().add_sub_info(&subscription);
-            sig_builder.add_claim("sub", "mailto:emailjunk234@gmail.com");
-            let sig = sig_builder.build()?;
-            let mut builder = WebPushMessageBuilder::new(&subscription);
-            builder.set_payload(web_push::ContentEncoding::Aes128Gcm, message.as_bytes());
-            builder.set_vapid_signature(sig);
-            notifier.client.send(builder.build()?).await?;
+pub async fn next_spectator_update(
+    player: &Player,
+    change_stream: &mut GameFeed,
+) -> Result<Option<AnyGame>> {
+    next_visible_update(change_stream, |game| can_view(game, player)).await
+}
+
+fn can_view(game: &AnyGame, player: &Player) -> bool {
+    game.visibility != Visibility::Private || game.game.in_game(player)
+}
+
+async fn next_visible_update(
+    change_stream: &mut GameFeed,
+    visible: impl Fn(&AnyGame) -> bool,
+) -> Result<Option<AnyGame>> {
+    match change_stream.next().await {
+        Some(game) => {
+            let game = game?;
+            Ok(visible(&game).then_some(game))
         }
+        None => Ok(None),
     }
-
-    Ok(())
 }
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::TryStreamExt;
+use mongodb::bson::{Document, deserialize_from_document, serialize_to_bson};
+
+use super::{prelude::*, storage::PlayerStore};
+use crate::common::game::GameTypes;
+
+// Ratings only move when rated games finish, so a slightly stale board is fine
+const CACHE_FOR: Duration = Duration::from_secs(5 * 60);
+const ACTIVE_FOR: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const SHOWN: usize = 100;
+
+type Cached = (Instant, Arc<Vec<LeaderboardEntry>>);
+
+/// Caches every rated player in each variant, sorted by rating. Filters are applied to the cached
+/// list so changing them doesn't hit the database.
+#[derive(Clone, Default)]
+pub struct Leaderboards {
+    cache: Arc<Mutex<HashMap<GameTypes, Cached>>>,
+}
+
+impl Leaderboards {
+    pub async fn get(
+        &self,
+        game_type: GameTypes,
+        active_only: bool,
+        min_games: u32,
+        ratings: &Collection<RatingRecord>,
+        players: &dyn PlayerStore,
+    ) -> Result<Vec<LeaderboardEntry>> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&game_type)
+            .filter(|(fetched, _)| fetched.elapsed() < CACHE_FOR)
+            .map(|(_, entries)| entries.clone());
+        let entries = match cached {
+            Some(entries) => entries,
+            None => {
+                let entries = Arc::new(rank_players(game_type, ratings, players).await?);
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .insert(game_type, (Instant::now(), entries.clone()));
+                entries
+            }
+        };
+
+        let active_since = SystemTime::UNIX_EPOCH
+            .elapsed()
+            .unwrap()
+            .saturating_sub(ACTIVE_FOR);
+        Ok(entries
+            .iter()
+            .filter(|entry| !active_only || entry.last_played >= active_since.as_secs())
+            .filter(|entry| entry.games >= min_games)
+            .take(SHOWN)
+            .cloned()
+            .collect())
+    }
+}
+
+async fn rank_players(
+    game_type: GameTypes,
+    ratings: &Collection<RatingRecord>,
+    players: &dyn PlayerStore,
+) -> Result<Vec<LeaderboardEntry>> {
+    let pipeline = [
+        doc! {"$match": {"game_type": serialize_to_bson(&game_type)?}},
+        doc! {"$project": {
+            "_id": 0,
+            "player": 1,
+            "rating": "$current",
+            "games": {"$size": "$history"},
+            "last_played": {"$ifNull": [{"$max": "$history.time"}, 0]},
+        }},
+        doc! {"$sort": {"rating.rating": -1}},
+    ];
+    let documents: Vec<Document> = ratings.aggregate(pipeline).await?.try_collect().await?;
+
+    // Players can live somewhere other than Mongo, so they get filled in separately
+    let ids: Vec<ObjectId> = documents
+        .iter()
+        .filter_map(|document| document.get_object_id("player").ok())
+        .collect();
+    let players: HashMap<ObjectId, Player> = players
+        .find_by_ids(&ids)
+        .await?
+        .into_iter()
+        .filter_map(|player| Some((player.id?, player)))
+        .collect();
+
+    let mut entries = Vec::with_capacity(documents.len());
+    for mut document in documents {
+        let Some(player) = players.get(&document.get_object_id("player")?) else {
+            continue;
+        };
+        document.insert("player", serialize_to_bson(player)?);
+        entries.push(deserialize_from_document(document)?);
+    }
+    Ok(entries)
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+
+use super::{config::ServerConfig, prelude::*};
+
+/// Wait this long after the first failure past the free ones, doubling with each failure after.
+const FIRST_WAIT: Duration = Duration::from_secs(1);
+/// Longest lockout, so a forgotten password never locks someone out for good
+const MAX_WAIT: Duration = Duration::from_secs(15 * 60);
+/// Failures are forgotten once there haven't been any for this long
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// What attempts are counted against
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Key {
+    /// Failed logins from an address
+    Ip(String),
+    /// Failed logins to an account, from anywhere
+    Name(String),
+    /// Every signup from an address
+    Signup(String),
+    /// Every password reset email sent for an account
+    Reset(String),
+}
+
+impl Key {
+    fn is_empty(&self) -> bool {
+        match self {
+            Key::Ip(key) | Key::Name(key) | Key::Signup(key) | Key::Reset(key) => key.is_empty(),
+        }
+    }
+
+    /// Attempts allowed before backing off. Addresses get more since a whole household or office
+    /// can share one.
+    fn free_attempts(&self) -> u32 {
+        match self {
+            Key::Ip(_) => 20,
+            Key::Name(_) => 5,
+            Key::Signup(_) => 5,
+            Key::Reset(_) => 3,
+        }
+    }
+}
+
+/// Too many recent attempts, shown to the player
+#[derive(Debug)]
+pub struct TooManyAttempts {
+    pub wait: Duration,
+}
+
+impl std::fmt::Display for TooManyAttempts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many attempts, try again in {} seconds",
+            self.wait.as_secs().max(1)
+        )
+    }
+}
+
+impl std::error::Error for TooManyAttempts {}
+
+struct Attempts {
+    failures: u32,
+    last: Instant,
+}
+
+impl Attempts {
+    fn locked_until(&self, key: &Key) -> Option<Instant> {
+        let over = self.failures.checked_sub(key.free_attempts())?;
+        let wait = FIRST_WAIT
+            .saturating_mul(2u32.saturating_pow(over))
+            .min(MAX_WAIT);
+        Some(self.last + wait)
+    }
+}
+
+/// Recent login and signup attempts. Kept in memory since they only matter for a few minutes,
+/// so restarting the server clears them.
+#[derive(Clone, Default)]
+pub struct Limiter {
+    attempts: Arc<Mutex<HashMap<Key, Attempts>>>,
+}
+
+impl Limiter {
+    /// Fails with [`TooManyAttempts`] if any of the keys is locked out, or if one is empty
+    pub fn check(&self, keys: &[Key]) -> Result<()> {
+        // An empty key would lump everyone it came from together, so one person could lock out
+        // the rest
+        if keys.iter().any(Key::is_empty) {
+            bail!("Couldn't tell who this request came from")
+        }
+        let now = Instant::now();
+        let attempts = self.attempts.lock().unwrap();
+        let wait = keys
+            .iter()
+            .filter_map(|key| attempts.get(key)?.locked_until(key))
+            .filter_map(|until| until.checked_duration_since(now))
+            .max();
+        match wait {
+            Some(wait) => Err(TooManyAttempts { wait }.into()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn failed(&self, keys: &[Key]) {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, attempt| now - attempt.last < FORGET_AFTER);
+        for key in keys {
+            let attempt = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last: now,
+            });
+            attempt.failures += 1;
+            attempt.last = now;
+        }
+    }
+
+    pub fn succeeded(&self, key: &Key) {
+        self.attempts.lock().unwrap().remove(key);
+    }
+}
+
+/// Address the request came from. Behind a trusted proxy that's the last address in
+/// `X-Forwarded-For` that none of the trusted proxies added.
+pub struct ClientIp(pub String);
+
+impl<T: Send + Sync> FromRequestParts<T> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &T,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(connected)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            return Ok(ClientIp(String::new()));
+        };
+        let trusted = parts
+            .extensions
+            .get::<ServerConfig>()
+            .map(|config| config.trusted_proxies.as_slice())
+            .unwrap_or_default();
+        let mut ip = connected.ip().to_canonical();
+        if trusted.contains(&ip) {
+            // Each proxy adds the address it heard from to the end, so anything left of the last
+            // untrusted one could have been made up by the client
+            let forwarded = parts
+                .headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|header| header.to_str().ok())
+                .flat_map(|header| header.split(','))
+                .collect::<Vec<_>>();
+            for hop in forwarded.into_iter().rev() {
+                let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                    break;
+                };
+                ip = hop.to_canonical();
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            }
+        }
+        Ok(ClientIp(ip.to_string()))
+    }
+}
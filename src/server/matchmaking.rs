@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use futures::channel::oneshot;
+
+use super::{
+    games::{join_open_game, new_open_game},
+    notifications::Notifier,
+    prelude::*,
+    storage::GameStore,
+};
+use crate::common::seek::Seek;
+
+struct Waiting {
+    ticket: u64,
+    player: Player,
+    seek: Seek,
+    rating: f64,
+    found: oneshot::Sender<ObjectId>,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_ticket: u64,
+    waiting: Vec<Waiting>,
+}
+
+/// Players waiting in the play now queue. Like spectators, the queue only lives in memory since
+/// waiting is tied to an open connection.
+#[derive(Clone, Default)]
+pub struct Matchmaker {
+    queue: Arc<Mutex<Queue>>,
+}
+
+/// An opponent who was waiting in the queue. Send them the game once it's created.
+pub struct Opponent {
+    pub player: Player,
+    found: oneshot::Sender<ObjectId>,
+}
+
+impl Opponent {
+    pub fn start(self, game_id: ObjectId) {
+        let _ = self.found.send(game_id);
+    }
+}
+
+impl Matchmaker {
+    /// Takes the longest waiting compatible player out of the queue.
+    pub fn find(&self, player: &Player, seek: &Seek, rating: f64) -> Option<Opponent> {
+        let waiting = &mut self.queue.lock().unwrap().waiting;
+        let i = waiting.iter().position(|other| {
+            other.player.id != player.id
+                && !other.found.is_canceled()
+                && accepts(seek, rating, &other.seek, other.rating)
+        })?;
+        let other = waiting.remove(i);
+        Some(Opponent {
+            player: other.player,
+            found: other.found,
+        })
+    }
+
+    /// Adds a player to the queue until someone pairs with them or the guard is dropped.
+    pub fn wait(
+        &self,
+        player: Player,
+        seek: Seek,
+        rating: f64,
+    ) -> (QueueGuard, oneshot::Receiver<ObjectId>) {
+        let (found, receiver) = oneshot::channel();
+        let mut queue = self.queue.lock().unwrap();
+        queue.next_ticket += 1;
+        let ticket = queue.next_ticket;
+        // Searching again from another tab replaces the old search
+        queue.waiting.retain(|other| other.player.id != player.id);
+        queue.waiting.push(Waiting {
+            ticket,
+            player,
+            seek,
+            rating,
+            found,
+        });
+        let guard = QueueGuard {
+            ticket,
+            matchmaker: self.clone(),
+        };
+        (guard, receiver)
+    }
+}
+
+pub struct QueueGuard {
+    ticket: u64,
+    matchmaker: Matchmaker,
+}
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        let mut queue = self.matchmaker.queue.lock().unwrap();
+        queue.waiting.retain(|other| other.ticket != self.ticket);
+    }
+}
+
+/// Both players have to be happy with the pairing, so each one's range has to cover the other.
+fn accepts(seek: &Seek, rating: f64, other: &Seek, other_rating: f64) -> bool {
+    let difference = (rating - other_rating).abs();
+    let within = |range: Option<u32>| range.is_none_or(|range| difference <= range as f64);
+    seek.game_type == other.game_type
+        && seek.time_control == other.time_control
+        && seek.rated == other.rated
+        && within(seek.range)
+        && within(other.range)
+}
+
+/// Starts a game between a player and the opponent the queue found for them. The game goes
+/// through the same open game flow as joining by hand, so both players hear about it the same way.
+pub async fn start_match(
+    opponent: Opponent,
+    joiner: Player,
+    seek: Seek,
+    games: &dyn GameStore,
+    notifier: &Notifier,
+) -> Result<ObjectId> {
+    let game_id = new_open_game(
+        opponent.player.clone(),
+        seek.game_type,
+        Visibility::default(),
+        seek.rated,
+        seek.time_control,
+        games,
+    )
+    .await?;
+    join_open_game(game_id, joiner, games, notifier).await?;
+    opponent.start(game_id);
+    Ok(game_id)
+}
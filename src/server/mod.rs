@@ -1,8 +1,23 @@
 pub mod auth;
+pub mod bots;
+pub mod chat;
 pub mod config;
+pub mod email;
 pub mod games;
+pub mod leaderboard;
+pub mod limits;
+pub mod matchmaking;
 pub mod mongo;
+pub mod notation;
+pub mod notifications;
+pub mod passkeys;
 pub mod prelude;
+pub mod profiles;
+pub mod ratings;
+pub mod spectators;
 pub mod state;
+pub mod storage;
+pub mod tournaments;
+pub mod webhooks;
 
 pub use state::build_state;
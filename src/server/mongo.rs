@@ -1,4 +1,10 @@
+use futures::TryStreamExt;
+use mongodb::bson::serialize_to_bson;
+
+use super::chat::{ChatReport, Chats};
 use super::prelude::*;
+use crate::common::chat::{ChatMessage, ChatSettings};
+use crate::common::tournament::Tournament;
 
 pub async fn connect(config: String) -> Result<Database> {
     let mut client_options = ClientOptions::parse(config).await?;
@@ -23,14 +29,41 @@ pub async fn setup_players_database(db: &Database, prefix: &str) -> Result<Colle
 pub async fn setup_games_database(db: &Database, prefix: &str) -> Result<Collection<AnyGame>> {
     let games: Collection<AnyGame> = db.collection(&format!("{prefix}_AllGames"));
     games
-        .create_index(IndexModel::builder().keys(doc! { "game.joiner._id": 1u32 }).build())
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "game.joiner._id": 1u32 })
+                .build(),
+        )
         .await?;
     games
-        .create_index(IndexModel::builder().keys(doc! { "game.maker._id": 1u32 }).build())
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "game.maker._id": 1u32 })
+                .build(),
+        )
         .await?;
+    backfill_winners(&games).await?;
     Ok(games)
 }
 
+// Games that ended before winners were stored only have the board to go on
+async fn backfill_winners(games: &Collection<AnyGame>) -> Result<()> {
+    let mut legacy = games
+        .find(doc! {"game.type": "Completed", "game.winner": {"$exists": false}})
+        .await?;
+    while let Some(any_game) = legacy.try_next().await? {
+        if let GameOrRequest::Completed(game) = any_game.game {
+            games
+                .update_one(
+                    doc! {"_id": any_game.id},
+                    doc! {"$set": {"game.winner": serialize_to_bson(&game.game_over())?}},
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 pub async fn setup_session_database(
     db: &Database,
     prefix: &str,
@@ -38,7 +71,60 @@ pub async fn setup_session_database(
     let sessions: Collection<super::state::SessionRecord> =
         db.collection(&format!("{prefix}_Sessions"));
     sessions
-        .create_index(IndexModel::builder().keys(doc! { "player._id": 1u32 }).build())
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "player._id": 1u32 })
+                .build(),
+        )
         .await?;
     Ok(sessions)
 }
+
+pub async fn setup_ratings_database(
+    db: &Database,
+    prefix: &str,
+) -> Result<Collection<RatingRecord>> {
+    let ratings: Collection<RatingRecord> = db.collection(&format!("{prefix}_Ratings"));
+    ratings
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "player": 1u32, "game_type": 1u32 })
+                .options(Some(IndexOptions::builder().unique(true).build()))
+                .build(),
+        )
+        .await?;
+    Ok(ratings)
+}
+
+pub async fn setup_chat_database(db: &Database, prefix: &str) -> Result<Chats> {
+    let chats: Collection<ChatMessage> = db.collection(&format!("{prefix}_Chat"));
+    chats
+        .create_index(IndexModel::builder().keys(doc! { "game": 1u32 }).build())
+        .await?;
+    let settings: Collection<ChatSettings> = db.collection(&format!("{prefix}_ChatSettings"));
+    settings
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "player": 1u32 })
+                .options(Some(IndexOptions::builder().unique(true).build()))
+                .build(),
+        )
+        .await?;
+    let reports: Collection<ChatReport> = db.collection(&format!("{prefix}_ChatReports"));
+    Ok(Chats {
+        messages: chats,
+        settings,
+        reports,
+    })
+}
+
+pub async fn setup_tournaments_database(
+    db: &Database,
+    prefix: &str,
+) -> Result<Collection<Tournament>> {
+    let tournaments: Collection<Tournament> = db.collection(&format!("{prefix}_Tournaments"));
+    tournaments
+        .create_index(IndexModel::builder().keys(doc! { "status": 1u32 }).build())
+        .await?;
+    Ok(tournaments)
+}
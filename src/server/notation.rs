@@ -0,0 +1,295 @@
+use crate::common::{
+    game::{GameRaw, SomeGame},
+    hexboard::{Coord, Hexboard},
+};
+
+use super::prelude::*;
+
+/// Files of the hex board, which skip j as in Gliński's notation
+const HEX_FILES: &[u8] = b"abcdefghikl";
+const HEX_RADIUS: i32 = 5;
+
+/// Boards whose squares have names like `e4`
+pub trait SquareNames: ChessBoard {
+    fn square_name(loc: Self::Loc) -> String;
+
+    /// The square with the name, if the name could belong to one. Whether it's actually on the
+    /// board is up to the caller.
+    fn parse_square(name: &str) -> Option<Self::Loc>;
+}
+
+/// Splits a name into its file letter and rank number
+fn file_and_rank(name: &str) -> Option<(u8, i32)> {
+    let (&file, rank) = name.as_bytes().split_first()?;
+    let rank = std::str::from_utf8(rank).ok()?;
+    if !rank.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((file, rank.parse().ok()?))
+}
+
+impl SquareNames for Board {
+    fn square_name(loc: Loc) -> String {
+        format!("{}{}", (b'a' + loc.right as u8) as char, 8 - loc.down)
+    }
+
+    fn parse_square(name: &str) -> Option<Loc> {
+        let (file, rank) = file_and_rank(name)?;
+        Some(Loc {
+            right: usize::from(file.checked_sub(b'a')?),
+            down: usize::try_from(8 - rank).ok()?,
+        })
+    }
+}
+
+/// Files run along q and rank 1 is the end of each file on White's side
+impl SquareNames for Hexboard {
+    fn square_name(loc: Coord) -> String {
+        let file = HEX_FILES[(loc.q + HEX_RADIUS) as usize] as char;
+        let rank = HEX_RADIUS.min(HEX_RADIUS - loc.q) - loc.r + 1;
+        format!("{file}{rank}")
+    }
+
+    fn parse_square(name: &str) -> Option<Coord> {
+        let (file, rank) = file_and_rank(name)?;
+        let q = HEX_FILES.iter().position(|known| *known == file)? as i32 - HEX_RADIUS;
+        Some(Coord {
+            q,
+            r: HEX_RADIUS.min(HEX_RADIUS - q) - rank + 1,
+        })
+    }
+}
+
+/// A turn in coordinate notation, like `e2e4@e5`: where the piece started, where it ended up, a
+/// letter for what a pawn promoted to, and where the duck went. Needs the game from before the
+/// turn was played.
+pub fn turn_notation<Board: SquareNames>(game: &GameRaw<Board>, turn: &TurnRaw<Board>) -> String {
+    let to = match turn.action {
+        SingleAction::Move(rel, _) | SingleAction::EnPassant(rel) => turn.from + rel,
+        // Castling is written as the king's move
+        SingleAction::Castle(_) => {
+            let color = game.turn();
+            let mut after = game.clone();
+            after.apply_from(turn.from, turn.action);
+            after
+                .board
+                .iter()
+                .find(|(_, square)| square.is_king(color))
+                .map_or(turn.from, |(loc, _)| loc)
+        }
+    };
+    let promotion = match (game.get(turn.from), turn.action) {
+        (
+            Some(Square::Piece(_, Piece::Pawn { .. }, _)),
+            SingleAction::Move(
+                _,
+                piece @ (Piece::Queen | Piece::Rook { .. } | Piece::Bishop | Piece::Knight),
+            ),
+        ) => piece.short_name().to_ascii_lowercase().to_string(),
+        _ => String::new(),
+    };
+    format!(
+        "{}{}{promotion}@{}",
+        Board::square_name(turn.from),
+        Board::square_name(to),
+        Board::square_name(turn.duck_to)
+    )
+}
+
+pub fn some_turn_notation(game: &SomeGame, turn: &SomeTurn) -> Option<String> {
+    match (game, turn) {
+        (SomeGame::Square(game), SomeTurn::Square(turn)) => Some(turn_notation(game, turn)),
+        (SomeGame::Hex(game), SomeTurn::Hex(turn)) => Some(turn_notation(game, turn)),
+        _ => None,
+    }
+}
+
+/// Every turn played so far in coordinate notation, worked out by replaying the game from the
+/// start
+pub fn moves<Board: SquareNames>(game: &GameRaw<Board>) -> Vec<String> {
+    let mut replay = GameRaw::<Board>::empty_board();
+    let mut moves = Vec::new();
+    for turn in &game.turns {
+        moves.push(turn_notation(&replay, turn));
+        if replay.apply_turn(*turn).is_err() {
+            break;
+        }
+    }
+    moves
+}
+
+pub fn some_moves(game: &SomeGame) -> Vec<String> {
+    match game {
+        SomeGame::Square(game) => moves(game),
+        SomeGame::Hex(game) => moves(game),
+    }
+}
+
+/// The square with the name, as long as it's on the game's board
+fn square<Board: SquareNames>(game: &GameRaw<Board>, name: &str) -> Result<Board::Loc> {
+    Board::parse_square(name)
+        .filter(|loc| game.get(*loc).is_some())
+        .ok_or_else(|| anyhow!("There's no square {name}"))
+}
+
+/// Splits the square name at the start of the text, like the `e2` of `e2e4`, from the rest
+fn split_square(text: &str) -> Option<(&str, &str)> {
+    if !text.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return None;
+    }
+    let end = text[1..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(text.len(), |end| end + 1);
+    (end > 1).then(|| text.split_at(end))
+}
+
+/// Reads a turn in the notation [`turn_notation`] writes
+pub fn parse_turn<Board: SquareNames>(game: &GameRaw<Board>, text: &str) -> Result<TurnRaw<Board>> {
+    let text = text.trim().to_ascii_lowercase();
+    let (piece, duck) = text
+        .split_once('@')
+        .ok_or_else(|| anyhow!("Turns need an @ and where the duck goes, like e2e4@e5"))?;
+    let (from, rest) =
+        split_square(piece).ok_or_else(|| anyhow!("{text} doesn't start with a square"))?;
+    let (to, promotion) =
+        split_square(rest).ok_or_else(|| anyhow!("{text} doesn't say where the piece goes"))?;
+    let (from, to, duck_to) = (square(game, from)?, square(game, to)?, square(game, duck)?);
+    let action = match (game.valid_locations_from(from).remove(&to), promotion) {
+        (Some(ActionRaw::Just(action)), "") => action,
+        (Some(ActionRaw::Promotion(rel, pieces)), promotion) => pieces
+            .into_iter()
+            .find(|piece| piece.short_name().to_ascii_lowercase().to_string() == promotion)
+            .map(|piece| SingleAction::Move(rel, piece))
+            .ok_or_else(|| anyhow!("{text} needs a letter for what the pawn promotes to"))?,
+        _ => bail!("{text} isn't a legal turn"),
+    };
+    Ok(TurnRaw {
+        from,
+        action,
+        duck_to,
+    })
+}
+
+pub fn parse_some_turn(game: &SomeGame, text: &str) -> Result<SomeTurn> {
+    Ok(match game {
+        SomeGame::Square(game) => SomeTurn::Square(parse_turn(game, text)?),
+        SomeGame::Hex(game) => SomeTurn::Hex(parse_turn(game, text)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the turns in order, checking each is written the way it was read
+    fn play<Board: SquareNames>(game: &mut GameRaw<Board>, turns: &[&str]) {
+        for text in turns {
+            let turn = parse_turn(game, text).unwrap();
+            assert_eq!(turn_notation(game, &turn), *text);
+            game.apply_turn(turn).unwrap();
+        }
+    }
+
+    /// Every turn the side to move has, with the duck on the first empty square it can use
+    fn legal_turns<Board: SquareNames>(game: &GameRaw<Board>) -> Vec<TurnRaw<Board>> {
+        let color = game.turn();
+        let mut turns = Vec::new();
+        for (from, square) in game.board.iter() {
+            if !matches!(square, Square::Piece(owner, _, _) if owner == color) {
+                continue;
+            }
+            for (to, action) in game.valid_locations_from(from) {
+                let actions = match action {
+                    ActionRaw::Just(action) => vec![action],
+                    ActionRaw::Promotion(rel, pieces) => pieces
+                        .into_iter()
+                        .map(|piece| SingleAction::Move(rel, piece))
+                        .collect(),
+                };
+                let duck_to = game
+                    .board
+                    .iter()
+                    .map(|(loc, _)| loc)
+                    .find(|loc| game.valid_duck(*loc) && *loc != to)
+                    .unwrap();
+                turns.extend(actions.into_iter().map(|action| TurnRaw {
+                    from,
+                    action,
+                    duck_to,
+                }));
+            }
+        }
+        turns
+    }
+
+    fn round_trips<Board: SquareNames>(game: &GameRaw<Board>) {
+        let turns = legal_turns(game);
+        assert!(!turns.is_empty());
+        for turn in turns {
+            let text = turn_notation(game, &turn);
+            assert_eq!(parse_turn(game, &text).unwrap(), turn, "{text}");
+        }
+    }
+
+    #[test]
+    fn opening_turns_round_trip() {
+        round_trips(&GameRaw::<Board>::empty_board());
+        round_trips(&GameRaw::<Hexboard>::empty_board());
+    }
+
+    #[test]
+    fn castling_is_the_kings_move() {
+        let mut game = GameRaw::<Board>::empty_board();
+        play(
+            &mut game,
+            &[
+                "e2e4@a3", "e7e5@a6", "g1f3@a3", "b8c6@a6", "f1c4@a3", "g8f6@a6", "e1g1@a3",
+            ],
+        );
+        let square = |name| game.get(Board::parse_square(name).unwrap()).unwrap();
+        assert!(square("g1").is_king(Color::White));
+        assert!(matches!(
+            square("f1"),
+            Square::Piece(Color::White, Piece::Rook { .. }, _)
+        ));
+        assert_eq!(moves(&game).last().unwrap(), "e1g1@a3");
+    }
+
+    #[test]
+    fn promotions_need_a_letter() {
+        let mut game = GameRaw::<Board>::empty_board();
+        play(
+            &mut game,
+            &[
+                "a2a4@d3", "b7b5@d6", "a4b5@d3", "h7h6@d6", "b5b6@d3", "h6h5@d6", "b6a7@d3",
+                "h5h4@d6",
+            ],
+        );
+        assert!(parse_turn(&game, "a7b8@d3").is_err());
+        assert!(parse_turn(&game, "a7b8k@d3").is_err());
+        play(&mut game, &["a7b8n@d3"]);
+        assert!(matches!(
+            game.get(Board::parse_square("b8").unwrap()),
+            Some(Square::Piece(Color::White, Piece::Knight, _))
+        ));
+    }
+
+    #[test]
+    fn hex_squares_have_glinski_names() {
+        assert_eq!(Hexboard::square_name(Coord::new(0, 0)), "f6");
+        for (loc, _) in Hexboard::default().iter() {
+            let name = Hexboard::square_name(loc);
+            assert_eq!(Hexboard::parse_square(&name), Some(loc), "{name}");
+        }
+    }
+
+    #[test]
+    fn bad_turns_are_turned_down() {
+        let game = GameRaw::<Board>::empty_board();
+        for text in [
+            "e2e4", "e2@e5", "e2e5@e6", "z9e4@e5", "e2e4@e9", "e2e4q@e5", "e7e5@e4",
+        ] {
+            assert!(parse_turn(&game, text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use web_push::PartialVapidSignatureBuilder;
+
+use super::{
+    config::NotifierConfig,
+    prelude::*,
+    storage::{PlayerStore, SessionStore},
+    webhooks::Webhooks,
+};
+use crate::common::account::{NotificationSettings, QuietHours};
+
+mod webhook;
+mod webpush;
+
+pub use webhook::{Webhook, http_client, pinned_client, post_with_retries};
+pub use webpush::WebPush;
+
+/// Times a notification is tried before giving up on it
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubling after each one
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+/// Neither HTTP client gives up on its own
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// What a notification is about, so the app can word and group it
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    GameStarted,
+    YourTurn,
+    GameEnded,
+    Chat,
+    NextRound,
+    /// Time to move is running out
+    Deadline,
+}
+
+impl Event {
+    fn title(self) -> &'static str {
+        match self {
+            Event::GameStarted => "Game started",
+            Event::YourTurn => "Your turn",
+            Event::GameEnded => "Game over",
+            Event::Chat => "New message",
+            Event::NextRound => "Next round",
+            Event::Deadline => "Time is running out",
+        }
+    }
+}
+
+/// What the service worker gets. It opens the game when clicked, and a newer notification about a
+/// game replaces any older one still showing.
+#[derive(Clone, Debug, Serialize)]
+pub struct Payload {
+    pub event: Event,
+    /// Hex id of the game
+    pub game: String,
+    pub opponent: String,
+    /// The turn that was just played, in coordinate notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_move: Option<String>,
+    pub title: String,
+    pub body: String,
+}
+
+impl Payload {
+    pub fn new(
+        event: Event,
+        game: ObjectId,
+        opponent: &Player,
+        last_move: Option<String>,
+        body: String,
+    ) -> Self {
+        Self {
+            event,
+            game: game.to_hex(),
+            opponent: opponent.name.clone(),
+            last_move,
+            title: format!("Duck Chess: {}", event.title()),
+            body,
+        }
+    }
+}
+
+/// How notifications reach players. Only called for notifications the player wants.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn deliver(&self, player: ObjectId, payload: &Payload) -> Result<()>;
+}
+
+/// Keeps every notification instead of sending it, so tests can check what would have gone out
+#[derive(Clone, Default)]
+pub struct Recording {
+    sent: Arc<Mutex<HashMap<ObjectId, Vec<Payload>>>>,
+}
+
+impl Recording {
+    #[cfg(test)]
+    pub fn sent_to(&self, player: ObjectId) -> Vec<Payload> {
+        self.sent
+            .lock()
+            .unwrap()
+            .get(&player)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Transport for Recording {
+    async fn deliver(&self, player: ObjectId, payload: &Payload) -> Result<()> {
+        self.sent
+            .lock()
+            .unwrap()
+            .entry(player)
+            .or_default()
+            .push(payload.clone());
+        Ok(())
+    }
+}
+
+enum Failure {
+    /// The receiving end says the address is gone for good
+    Gone,
+    /// Might work if tried again later
+    Retry(Option<Duration>, anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+/// Tries again after transient failures, waiting longer each time
+async fn with_retries<F: Future<Output = std::result::Result<(), Failure>>>(
+    mut send: impl FnMut() -> F,
+) -> std::result::Result<(), Failure> {
+    let mut wait = FIRST_RETRY;
+    let mut attempt = 1;
+    loop {
+        match send().await {
+            Err(Failure::Retry(retry_after, _)) if attempt < MAX_ATTEMPTS => {
+                tokio::time::sleep(retry_after.unwrap_or(wait)).await;
+                wait *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+struct Notification {
+    player: ObjectId,
+    payload: Payload,
+}
+
+/// Hands notifications to a background task, so a slow or broken transport never holds up or
+/// fails whatever the notification is about.
+#[derive(Clone)]
+pub struct Notifier {
+    /// Signs web push messages. Other transports don't need a key.
+    pub crypto: Option<PartialVapidSignatureBuilder>,
+    queue: mpsc::UnboundedSender<Notification>,
+    /// Players' own webhooks hear about the same game events
+    pub webhooks: Webhooks,
+}
+
+impl Notifier {
+    pub fn start(
+        crypto: Option<PartialVapidSignatureBuilder>,
+        transport: Arc<dyn Transport>,
+        players: Arc<dyn PlayerStore>,
+        webhooks: Webhooks,
+    ) -> Self {
+        let (queue, mut received) = mpsc::unbounded_channel::<Notification>();
+        tokio::spawn(async move {
+            while let Some(notification) = received.recv().await {
+                // Retries wait a while, so each notification gets its own task
+                tokio::spawn(deliver(notification, transport.clone(), players.clone()));
+            }
+        });
+        Self {
+            crypto,
+            queue,
+            webhooks,
+        }
+    }
+}
+
+/// The transport the config asks for. Web push needs the key made from the config's PEM.
+pub fn transport(
+    config: &NotifierConfig,
+    crypto: Option<&PartialVapidSignatureBuilder>,
+    sessions: Arc<dyn SessionStore>,
+) -> Result<Arc<dyn Transport>> {
+    Ok(match config {
+        NotifierConfig::WebPush { subject, .. } => {
+            let crypto = crypto.ok_or_else(|| anyhow!("Web push needs a VAPID key"))?;
+            Arc::new(WebPush::new(crypto.clone(), subject.clone(), sessions)?)
+        }
+        NotifierConfig::Webhook { url } => Arc::new(Webhook::new(url.clone())?),
+        NotifierConfig::Recording => Arc::new(Recording::default()),
+    })
+}
+
+async fn deliver(
+    notification: Notification,
+    transport: Arc<dyn Transport>,
+    players: Arc<dyn PlayerStore>,
+) {
+    let settings = match players.get(notification.player).await {
+        Ok(stored) => stored
+            .and_then(|stored| stored.notifications)
+            .unwrap_or_default(),
+        Err(error) => {
+            log::error!("looking up notification settings failed: {error:?}");
+            return;
+        }
+    };
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    if !wanted(&settings, notification.payload.event, now) {
+        return;
+    }
+    if let Err(error) = transport
+        .deliver(notification.player, &notification.payload)
+        .await
+    {
+        log::error!("delivering notification failed: {error:?}");
+    }
+}
+
+/// The key browsers need to subscribe to web push, for servers that send it
+pub fn public_key(notifier: &Notifier) -> Result<String> {
+    let Some(crypto) = &notifier.crypto else {
+        bail!("This server doesn't send push notifications")
+    };
+    Ok(base64_url::encode(&crypto.get_public_key()))
+}
+
+/// Queues a notification to the player, if they want to hear about it right now
+pub fn send_notification(player: ObjectId, payload: Payload, notifier: &Notifier) {
+    if notifier
+        .queue
+        .send(Notification { player, payload })
+        .is_err()
+    {
+        log::error!("notification queue is closed");
+    }
+}
+
+pub async fn get_settings(
+    player: &Player,
+    players: &dyn PlayerStore,
+) -> Result<NotificationSettings> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    Ok(players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?
+        .notifications
+        .unwrap_or_default())
+}
+
+pub async fn set_settings(
+    player: &Player,
+    settings: NotificationSettings,
+    players: &dyn PlayerStore,
+) -> Result<()> {
+    if let Some(quiet) = &settings.quiet_hours {
+        let day = MINUTES_PER_DAY as u16;
+        if quiet.start >= day || quiet.end >= day {
+            bail!("Quiet hours have to be within a day")
+        }
+        if quiet.utc_offset.abs() > 14 * 60 {
+            bail!("That isn't a real timezone")
+        }
+    }
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let mut stored = players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))?;
+    stored.notifications = Some(settings);
+    players.replace(stored).await
+}
+
+fn is_quiet(quiet: &QuietHours, now: u64) -> bool {
+    let minute = (now as i64 / 60 + quiet.utc_offset as i64).rem_euclid(MINUTES_PER_DAY) as u16;
+    if quiet.start <= quiet.end {
+        (quiet.start..quiet.end).contains(&minute)
+    } else {
+        minute >= quiet.start || minute < quiet.end
+    }
+}
+
+fn wanted(settings: &NotificationSettings, event: Event, now: u64) -> bool {
+    let on = match event {
+        Event::GameStarted | Event::NextRound => settings.game_start,
+        Event::YourTurn => settings.turn,
+        Event::GameEnded => settings.game_end,
+        Event::Chat => settings.chat,
+        Event::Deadline => settings.deadlines,
+    };
+    on && !settings
+        .quiet_hours
+        .as_ref()
+        .is_some_and(|quiet| is_quiet(quiet, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::game::GameTypes,
+        server::{
+            games::{join_open_game, new_open_game},
+            storage::{MemoryGames, MemoryPlayers},
+        },
+    };
+
+    struct Setup {
+        recording: Recording,
+        notifier: Notifier,
+        players: Arc<dyn PlayerStore>,
+        games: MemoryGames,
+    }
+
+    fn setup() -> Setup {
+        let recording = Recording::default();
+        let players: Arc<dyn PlayerStore> = Arc::new(MemoryPlayers::default());
+        let webhooks = Webhooks::start(players.clone(), false).unwrap();
+        let notifier =
+            Notifier::start(None, Arc::new(recording.clone()), players.clone(), webhooks);
+        Setup {
+            recording,
+            notifier,
+            players,
+            games: MemoryGames::default(),
+        }
+    }
+
+    async fn player(name: &str, players: &dyn PlayerStore) -> Player {
+        let mut player = Player {
+            id: None,
+            name: name.into(),
+        };
+        let stored = PasswordPlayer {
+            player: player.clone(),
+            ..PasswordPlayer::default()
+        };
+        player.id = Some(players.insert(stored).await.unwrap());
+        player
+    }
+
+    /// Waits a little for the background task to deliver notifications
+    async fn sent_to(recording: &Recording, player: &Player, count: usize) -> Vec<Payload> {
+        for _ in 0..50 {
+            let sent = recording.sent_to(player.id.unwrap());
+            if sent.len() >= count {
+                return sent;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        recording.sent_to(player.id.unwrap())
+    }
+
+    async fn start_game(setup: &Setup, maker: &Player, joiner: &Player) -> ObjectId {
+        let id = new_open_game(
+            maker.clone(),
+            GameTypes::Square,
+            Visibility::Private,
+            false,
+            TimeControl::Unlimited,
+            &setup.games,
+        )
+        .await
+        .unwrap();
+        join_open_game(id, joiner.clone(), &setup.games, &setup.notifier)
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn maker_hears_when_someone_joins() {
+        let setup = setup();
+        let alice = player("alice", &*setup.players).await;
+        let bob = player("bob", &*setup.players).await;
+        let id = start_game(&setup, &alice, &bob).await;
+
+        let sent = sent_to(&setup.recording, &alice, 1).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].event, Event::GameStarted);
+        assert_eq!(sent[0].game, id.to_hex());
+        assert_eq!(sent[0].opponent, "bob");
+    }
+
+    #[test]
+    fn turned_off_events_are_not_wanted() {
+        let settings = NotificationSettings {
+            game_start: false,
+            ..NotificationSettings::default()
+        };
+        assert!(!wanted(&settings, Event::GameStarted, 0));
+        assert!(!wanted(&settings, Event::NextRound, 0));
+        assert!(wanted(&settings, Event::Chat, 0));
+        assert!(wanted(&settings, Event::YourTurn, 0));
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        let quiet = QuietHours {
+            start: 22 * 60,
+            end: 7 * 60,
+            utc_offset: 60,
+        };
+        // 23:30 and 06:00 in UTC+1
+        assert!(is_quiet(&quiet, 22 * 60 * 60 + 30 * 60));
+        assert!(is_quiet(&quiet, 5 * 60 * 60));
+        assert!(!is_quiet(&quiet, 12 * 60 * 60));
+    }
+}
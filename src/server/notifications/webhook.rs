@@ -0,0 +1,125 @@
+use std::{net::IpAddr, time::Duration};
+
+use async_trait::async_trait;
+use isahc::{
+    AsyncReadResponseExt, HttpClient, Request,
+    config::{Configurable, ResolveMap},
+    http::header,
+};
+
+use super::{Failure, Payload, SEND_TIMEOUT, Transport, with_retries};
+use crate::server::prelude::*;
+
+#[derive(Serialize)]
+struct Delivery<'a> {
+    /// Hex id of the player the notification is for
+    player: String,
+    #[serde(flatten)]
+    payload: &'a Payload,
+}
+
+/// POSTs every notification as JSON to one URL, for relaying them somewhere push can't reach
+pub struct Webhook {
+    client: HttpClient,
+    url: String,
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Result<Self> {
+        Ok(Self {
+            client: http_client()?,
+            url,
+        })
+    }
+}
+
+pub fn http_client() -> Result<HttpClient> {
+    Ok(HttpClient::builder().timeout(SEND_TIMEOUT).build()?)
+}
+
+/// A client that only ever connects to `ip` for the host and port, whatever DNS says by then
+pub fn pinned_client(host: &str, port: u16, ip: IpAddr) -> Result<HttpClient> {
+    Ok(HttpClient::builder()
+        .timeout(SEND_TIMEOUT)
+        .dns_resolve(ResolveMap::new().add(host, port, ip))
+        .build()?)
+}
+
+/// Sends a JSON body, telling apart failures worth retrying from the rest
+async fn post(
+    client: &HttpClient,
+    url: &str,
+    body: &[u8],
+    headers: &[(&str, String)],
+) -> std::result::Result<(), Failure> {
+    let mut request = Request::post(url).header(header::CONTENT_TYPE, "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request
+        .body(body.to_vec())
+        .map_err(|error| Failure::Fatal(error.into()))?;
+    let mut response = match client.send_async(request).await {
+        Ok(response) => response,
+        Err(error) => return Err(Failure::Retry(None, error.into())),
+    };
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .map(Duration::from_secs);
+    // Only a little of the reply is kept, for the log
+    let reply: String = response
+        .text()
+        .await
+        .unwrap_or_default()
+        .chars()
+        .take(200)
+        .collect();
+    let error = anyhow!("webhook answered {status}: {reply}");
+    if status.is_server_error() || status.as_u16() == 429 {
+        Err(Failure::Retry(retry_after, error))
+    } else if status.as_u16() == 410 {
+        Err(Failure::Gone)
+    } else {
+        Err(Failure::Fatal(error))
+    }
+}
+
+/// POSTs a JSON body, trying again after transient failures. Also says how many tries it took.
+pub async fn post_with_retries(
+    client: &HttpClient,
+    url: &str,
+    body: &[u8],
+    headers: &[(&str, String)],
+) -> (u32, Result<()>) {
+    let mut attempts = 0;
+    let result = with_retries(|| {
+        attempts += 1;
+        post(client, url, body, headers)
+    })
+    .await;
+    let result = match result {
+        Ok(()) => Ok(()),
+        Err(Failure::Gone) => Err(anyhow!("webhook {url} is gone")),
+        Err(Failure::Retry(_, error) | Failure::Fatal(error)) => Err(error),
+    };
+    (attempts, result)
+}
+
+#[async_trait]
+impl Transport for Webhook {
+    async fn deliver(&self, player: ObjectId, payload: &Payload) -> Result<()> {
+        let body = serde_json::to_vec(&Delivery {
+            player: player.to_hex(),
+            payload,
+        })?;
+        post_with_retries(&self.client, &self.url, &body, &[])
+            .await
+            .1
+    }
+}
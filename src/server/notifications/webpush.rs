@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use web_push::{
+    IsahcWebPushClient, PartialVapidSignatureBuilder, SubscriptionInfo, VapidSignatureBuilder,
+    WebPushClient, WebPushError, WebPushMessageBuilder,
+};
+
+use super::{Failure, Payload, SEND_TIMEOUT, Transport, with_retries};
+use crate::server::{prelude::*, storage::SessionStore};
+
+/// Browser push notifications to every device the player turned them on for
+pub struct WebPush {
+    client: IsahcWebPushClient,
+    crypto: PartialVapidSignatureBuilder,
+    /// Who push services can contact about these notifications, a mailto: or https: URL
+    subject: String,
+    sessions: Arc<dyn SessionStore>,
+}
+
+impl WebPush {
+    pub fn new(
+        crypto: PartialVapidSignatureBuilder,
+        subject: String,
+        sessions: Arc<dyn SessionStore>,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: IsahcWebPushClient::new()?,
+            crypto,
+            subject,
+            sessions,
+        })
+    }
+
+    async fn send(
+        &self,
+        subscription: &SubscriptionInfo,
+        payload: &[u8],
+    ) -> std::result::Result<(), Failure> {
+        let mut sig_builder: VapidSignatureBuilder<'_> =
+            self.crypto.clone().add_sub_info(subscription);
+        sig_builder.add_claim("sub", self.subject.as_str());
+        let sig = sig_builder
+            .build()
+            .map_err(|error| Failure::Fatal(error.into()))?;
+        let mut builder = WebPushMessageBuilder::new(subscription);
+        builder.set_payload(web_push::ContentEncoding::Aes128Gcm, payload);
+        builder.set_vapid_signature(sig);
+        let message = builder
+            .build()
+            .map_err(|error| Failure::Fatal(error.into()))?;
+        match tokio::time::timeout(SEND_TIMEOUT, self.client.send(message)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(WebPushError::EndpointNotFound(_) | WebPushError::EndpointNotValid(_))) => {
+                Err(Failure::Gone)
+            }
+            Ok(Err(WebPushError::ServerError { retry_after, info })) => Err(Failure::Retry(
+                retry_after,
+                anyhow!("push service error: {info}"),
+            )),
+            Ok(Err(error @ (WebPushError::Io(_) | WebPushError::Unspecified))) => {
+                Err(Failure::Retry(None, error.into()))
+            }
+            Ok(Err(error)) => Err(Failure::Fatal(error.into())),
+            Err(_) => Err(Failure::Retry(None, anyhow!("push service timed out"))),
+        }
+    }
+
+    /// Forgets a subscription the push service no longer knows, unless the device has
+    /// subscribed again since
+    async fn prune(&self, id: ObjectId, endpoint: &str) -> Result<()> {
+        let Some(mut session) = self.sessions.get(id).await? else {
+            return Ok(());
+        };
+        if session
+            .subscription
+            .as_ref()
+            .is_some_and(|subscription| subscription.endpoint == endpoint)
+        {
+            session.subscription = None;
+            self.sessions.replace(session).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for WebPush {
+    async fn deliver(&self, player: ObjectId, payload: &Payload) -> Result<()> {
+        let payload = serde_json::to_vec(payload)?;
+        for session in self.sessions.player_sessions(player).await? {
+            let (Some(id), Some(subscription)) = (session.id, &session.subscription) else {
+                continue;
+            };
+            // One broken device shouldn't keep the others from hearing about it
+            match with_retries(|| self.send(subscription, &payload)).await {
+                Ok(()) => {}
+                Err(Failure::Gone) => {
+                    if let Err(error) = self.prune(id, &subscription.endpoint).await {
+                        log::error!("removing expired subscription failed: {error:?}");
+                    }
+                }
+                Err(Failure::Retry(_, error) | Failure::Fatal(error)) => {
+                    log::error!("push notification failed: {error:?}")
+                }
+            }
+        }
+        Ok(())
+    }
+}
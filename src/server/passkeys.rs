@@ -0,0 +1,572 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{Uri, request::Parts},
+};
+use ciborium::Value;
+use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+use sha2::{Digest, Sha256};
+
+use super::{
+    auth::reauthenticate, config::ServerConfig, limits::Limiter, prelude::*, storage::PlayerStore,
+};
+use crate::common::account::{
+    Passkey, PasskeyAssertion, PasskeyChallenge, PasskeyInfo, PasskeyRegistration,
+};
+
+/// How long the browser has to answer a challenge
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const CREDENTIAL_INCLUDED: u8 = 0x40;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Purpose {
+    Register,
+    Login,
+}
+
+struct Pending {
+    player: ObjectId,
+    purpose: Purpose,
+    issued: Instant,
+}
+
+/// Challenges handed out and not yet answered. Each one can only be answered once.
+#[derive(Clone, Default)]
+pub struct Challenges {
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+impl Challenges {
+    fn issue(&self, player: ObjectId, purpose: Purpose) -> Vec<u8> {
+        let challenge = rand::random::<[u8; 32]>().to_vec();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, pending| pending.issued.elapsed() < CHALLENGE_LIFETIME);
+        pending.insert(
+            base64_url::encode(&challenge),
+            Pending {
+                player,
+                purpose,
+                issued: Instant::now(),
+            },
+        );
+        challenge
+    }
+
+    /// The player the challenge was issued to
+    fn take(&self, challenge: &str, purpose: Purpose) -> Result<ObjectId> {
+        match self.pending.lock().unwrap().remove(challenge) {
+            Some(pending)
+                if pending.purpose == purpose && pending.issued.elapsed() < CHALLENGE_LIFETIME =>
+            {
+                Ok(pending.player)
+            }
+            _ => bail!("The passkey request expired, please try again"),
+        }
+    }
+}
+
+/// Which site passkeys are made for, from `PUBLIC_URL`. Headers can't be used since a
+/// passkey made for whatever host a request named would only be checked against itself.
+pub struct RelyingParty {
+    id: String,
+    origin: String,
+}
+
+impl RelyingParty {
+    fn from_url(url: &str) -> Result<Self> {
+        let uri: Uri = url.parse().map_err(|_| anyhow!("PUBLIC_URL isn't a URL"))?;
+        let (Some(scheme), Some(host)) = (uri.scheme_str(), uri.host()) else {
+            bail!("PUBLIC_URL needs a scheme and host")
+        };
+        let origin = match uri.port_u16() {
+            Some(port) => format!("{scheme}://{host}:{port}"),
+            None => format!("{scheme}://{host}"),
+        };
+        Ok(RelyingParty {
+            id: host.to_string(),
+            origin,
+        })
+    }
+}
+
+impl<T: Send + Sync> FromRequestParts<T> for RelyingParty {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &T,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let url = parts
+            .extensions
+            .get::<ServerConfig>()
+            .and_then(|config| config.public_url.as_deref())
+            .ok_or_else(|| anyhow!("Passkeys are turned off on this server"))?;
+        Ok(RelyingParty::from_url(url)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Checks what the browser says it signed, and returns the challenge for looking up who asked
+fn check_client_data(client_data: &[u8], kind: &str, rp: &RelyingParty) -> Result<String> {
+    let client_data: ClientData = serde_json::from_slice(client_data)?;
+    if client_data.kind != kind {
+        bail!("Wrong kind of passkey response")
+    }
+    if client_data.origin != rp.origin {
+        bail!("Passkey response is for another site")
+    }
+    Ok(client_data.challenge)
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions, if any
+    rest: &'a [u8],
+}
+
+fn parse_authenticator_data<'a>(
+    data: &'a [u8],
+    rp: &RelyingParty,
+) -> Result<AuthenticatorData<'a>> {
+    if data.len() < 37 {
+        bail!("Authenticator data is too short")
+    }
+    if data[..32] != Sha256::digest(rp.id.as_bytes())[..] {
+        bail!("Passkey is for another site")
+    }
+    let flags = data[32];
+    if flags & USER_PRESENT == 0 {
+        bail!("Passkey was used without anyone there")
+    }
+    // Presence is only a tap, so without this anyone holding the device could use it
+    if flags & USER_VERIFIED == 0 {
+        bail!("Passkey was used without unlocking it")
+    }
+    Ok(AuthenticatorData {
+        flags,
+        sign_count: u32::from_be_bytes(data[33..37].try_into()?),
+        rest: &data[37..],
+    })
+}
+
+fn map_get(map: &[(Value, Value)], key: impl Into<Value>) -> Option<&Value> {
+    let key = key.into();
+    map.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+/// Turns a COSE EC2 key into the SEC1 form that p256 reads. Only ES256 is accepted, which every
+/// authenticator supports.
+fn cose_to_sec1(key: &Value) -> Result<Vec<u8>> {
+    let key = key.as_map().ok_or_else(|| anyhow!("Key isn't a map"))?;
+    let kty = map_get(key, 1).and_then(Value::as_integer);
+    let alg = map_get(key, 3).and_then(Value::as_integer);
+    let crv = map_get(key, -1).and_then(Value::as_integer);
+    if kty != Some(2.into()) || alg != Some((-7).into()) || crv != Some(1.into()) {
+        bail!("Only P-256 passkeys are supported")
+    }
+    let x = map_get(key, -2).and_then(Value::as_bytes);
+    let y = map_get(key, -3).and_then(Value::as_bytes);
+    let (Some(x), Some(y)) = (x, y) else {
+        bail!("Key is missing coordinates")
+    };
+    let sec1 = [&[0x04][..], x, y].concat();
+    VerifyingKey::from_sec1_bytes(&sec1)?;
+    Ok(sec1)
+}
+
+/// The credential id and public key in an attestation object. The browser is asked for no
+/// attestation, so the attestation statement itself is never checked.
+fn parse_attestation(attestation_object: &[u8], rp: &RelyingParty) -> Result<(Vec<u8>, Vec<u8>)> {
+    let attestation: Value = ciborium::from_reader(attestation_object)?;
+    let attestation = attestation
+        .as_map()
+        .ok_or_else(|| anyhow!("Attestation isn't a map"))?;
+    let data = map_get(attestation, "authData")
+        .and_then(Value::as_bytes)
+        .ok_or_else(|| anyhow!("Attestation has no authenticator data"))?;
+    let data = parse_authenticator_data(data, rp)?;
+    if data.flags & CREDENTIAL_INCLUDED == 0 || data.rest.len() < 18 {
+        bail!("Attestation has no credential")
+    }
+    // Skip the AAGUID, which only matters with attestation
+    let length = u16::from_be_bytes(data.rest[16..18].try_into()?) as usize;
+    let rest = &data.rest[18..];
+    if rest.len() < length {
+        bail!("Credential id is cut off")
+    }
+    let (id, key) = rest.split_at(length);
+    let key: Value = ciborium::from_reader(key)?;
+    Ok((id.to_vec(), cose_to_sec1(&key)?))
+}
+
+async fn stored_player(id: ObjectId, players: &dyn PlayerStore) -> Result<PasswordPlayer> {
+    players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))
+}
+
+/// Needs the password, since a passkey is another way into the account
+pub async fn start_registration(
+    player: &Player,
+    password: &str,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    challenges: &Challenges,
+    rp: &RelyingParty,
+) -> Result<PasskeyChallenge> {
+    let stored = reauthenticate(player, password, players, limiter).await?;
+    let id = stored.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    Ok(PasskeyChallenge {
+        challenge: challenges.issue(id, Purpose::Register),
+        rp_id: rp.id.clone(),
+        user_id: id.bytes().to_vec(),
+        user_name: stored.name.clone(),
+        credentials: stored
+            .passkeys
+            .iter()
+            .map(|passkey| base64_url::decode(&passkey.id))
+            .collect::<std::result::Result<_, _>>()?,
+    })
+}
+
+pub async fn finish_registration(
+    player: &Player,
+    password: &str,
+    registration: PasskeyRegistration,
+    players: &dyn PlayerStore,
+    limiter: &Limiter,
+    challenges: &Challenges,
+    rp: &RelyingParty,
+) -> Result<()> {
+    let mut stored = reauthenticate(player, password, players, limiter).await?;
+    let challenge = check_client_data(&registration.client_data, "webauthn.create", rp)?;
+    let id = challenges.take(&challenge, Purpose::Register)?;
+    if Some(id) != player.id {
+        bail!("The passkey request was for someone else")
+    }
+    let (credential, public_key) = parse_attestation(&registration.attestation_object, rp)?;
+    let credential = base64_url::encode(&credential);
+    if stored
+        .passkeys
+        .iter()
+        .any(|passkey| passkey.id == credential)
+    {
+        bail!("That passkey is already on your account")
+    }
+    stored.passkeys.push(Passkey {
+        id: credential,
+        public_key: base64_url::encode(&public_key),
+        sign_count: 0,
+        name: registration.name,
+        created: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+    });
+    players.replace(stored).await
+}
+
+pub async fn start_login(
+    name: &str,
+    players: &dyn PlayerStore,
+    challenges: &Challenges,
+    rp: &RelyingParty,
+) -> Result<PasskeyChallenge> {
+    let stored = players
+        .find_by_name(name)
+        .await?
+        .filter(|player| !player.passkeys.is_empty())
+        .ok_or_else(|| anyhow!("There are no passkeys for that username"))?;
+    let id = stored.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    Ok(PasskeyChallenge {
+        challenge: challenges.issue(id, Purpose::Login),
+        rp_id: rp.id.clone(),
+        user_id: id.bytes().to_vec(),
+        user_name: stored.name.clone(),
+        credentials: stored
+            .passkeys
+            .iter()
+            .map(|passkey| base64_url::decode(&passkey.id))
+            .collect::<std::result::Result<_, _>>()?,
+    })
+}
+
+/// Checks a signed challenge. A passkey stands in for both the password and any second factor.
+pub async fn finish_login(
+    assertion: PasskeyAssertion,
+    players: &dyn PlayerStore,
+    challenges: &Challenges,
+    rp: &RelyingParty,
+) -> Result<Player> {
+    let challenge = check_client_data(&assertion.client_data, "webauthn.get", rp)?;
+    let id = challenges.take(&challenge, Purpose::Login)?;
+    let mut stored = stored_player(id, players).await?;
+    if stored.name != assertion.name {
+        bail!("The passkey request was for someone else")
+    }
+    let credential = base64_url::encode(&assertion.credential);
+    let Some(passkey) = stored
+        .passkeys
+        .iter_mut()
+        .find(|passkey| passkey.id == credential)
+    else {
+        bail!("That passkey isn't on this account")
+    };
+    let data = parse_authenticator_data(&assertion.authenticator_data, rp)?;
+    let key = VerifyingKey::from_sec1_bytes(&base64_url::decode(&passkey.public_key)?)?;
+    let signed = [
+        &assertion.authenticator_data[..],
+        &Sha256::digest(&assertion.client_data)[..],
+    ]
+    .concat();
+    key.verify(&signed, &Signature::from_der(&assertion.signature)?)?;
+    // Authenticators that don't keep a count always send zero
+    if data.sign_count != 0 || passkey.sign_count != 0 {
+        if data.sign_count <= passkey.sign_count {
+            bail!("This passkey may have been copied")
+        }
+        passkey.sign_count = data.sign_count;
+        players.replace(stored.clone()).await?;
+    }
+    Ok(stored.player)
+}
+
+pub async fn list_passkeys(player: &Player, players: &dyn PlayerStore) -> Result<Vec<PasskeyInfo>> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    Ok(stored_player(id, players)
+        .await?
+        .passkeys
+        .into_iter()
+        .map(|passkey| PasskeyInfo {
+            id: passkey.id,
+            name: passkey.name,
+            created: passkey.created,
+        })
+        .collect())
+}
+
+pub async fn remove_passkey(
+    player: &Player,
+    credential: &str,
+    players: &dyn PlayerStore,
+) -> Result<()> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    let mut stored = stored_player(id, players).await?;
+    let before = stored.passkeys.len();
+    stored.passkeys.retain(|passkey| passkey.id != credential);
+    if stored.passkeys.len() == before {
+        bail!("No passkey with that id")
+    }
+    players.replace(stored).await
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::{SigningKey, signature::Signer};
+
+    use super::*;
+    use crate::server::{
+        auth::new_user,
+        limits::{ClientIp, Limiter},
+        storage::MemoryPlayers,
+    };
+
+    const PASSWORD: &str = "passwordA1";
+
+    /// Does what a browser and authenticator would, with one P-256 key kept in memory
+    struct Authenticator {
+        key: SigningKey,
+        credential: Vec<u8>,
+        sign_count: u32,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            Authenticator {
+                key: SigningKey::from_slice(&[7; 32]).unwrap(),
+                credential: b"software authenticator".to_vec(),
+                sign_count: 0,
+            }
+        }
+
+        fn authenticator_data(&self, rp: &RelyingParty, flags: u8, attested: &[u8]) -> Vec<u8> {
+            [
+                &Sha256::digest(rp.id.as_bytes())[..],
+                &[flags],
+                &self.sign_count.to_be_bytes(),
+                attested,
+            ]
+            .concat()
+        }
+
+        fn create(&self, challenge: &PasskeyChallenge, rp: &RelyingParty) -> PasskeyRegistration {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let key = Value::Map(vec![
+                (1.into(), 2.into()),
+                (3.into(), (-7).into()),
+                ((-1).into(), 1.into()),
+                ((-2).into(), Value::Bytes(point.x().unwrap().to_vec())),
+                ((-3).into(), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            let mut encoded_key = Vec::new();
+            ciborium::into_writer(&key, &mut encoded_key).unwrap();
+            let attested = [
+                &[0; 16][..],
+                &(self.credential.len() as u16).to_be_bytes(),
+                &self.credential,
+                &encoded_key,
+            ]
+            .concat();
+            let flags = USER_PRESENT | USER_VERIFIED | CREDENTIAL_INCLUDED;
+            let attestation = Value::Map(vec![
+                ("fmt".into(), "none".into()),
+                ("attStmt".into(), Value::Map(Vec::new())),
+                (
+                    "authData".into(),
+                    Value::Bytes(self.authenticator_data(rp, flags, &attested)),
+                ),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+            PasskeyRegistration {
+                name: "Test key".into(),
+                client_data: client_data("webauthn.create", challenge, rp),
+                attestation_object,
+            }
+        }
+
+        fn get(
+            &mut self,
+            name: &str,
+            challenge: &PasskeyChallenge,
+            rp: &RelyingParty,
+            flags: u8,
+        ) -> PasskeyAssertion {
+            self.sign_count += 1;
+            let authenticator_data = self.authenticator_data(rp, flags, &[]);
+            let client_data = client_data("webauthn.get", challenge, rp);
+            let signed = [&authenticator_data[..], &Sha256::digest(&client_data)[..]].concat();
+            let signature: Signature = self.key.sign(&signed);
+            PasskeyAssertion {
+                name: name.into(),
+                credential: self.credential.clone(),
+                client_data,
+                authenticator_data,
+                signature: signature.to_der().as_bytes().to_vec(),
+            }
+        }
+    }
+
+    fn client_data(kind: &str, challenge: &PasskeyChallenge, rp: &RelyingParty) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": kind,
+            "challenge": base64_url::encode(&challenge.challenge),
+            "origin": rp.origin,
+        }))
+        .unwrap()
+    }
+
+    async fn registered() -> (MemoryPlayers, Challenges, RelyingParty, Authenticator) {
+        let players = MemoryPlayers::default();
+        let challenges = Challenges::default();
+        let rp = RelyingParty::from_url("https://duck.example").unwrap();
+        let authenticator = Authenticator::new();
+        let player = new_user(
+            &players,
+            &Limiter::default(),
+            &ClientIp("127.0.0.1".into()),
+            "alice".into(),
+            PASSWORD.into(),
+        )
+        .await
+        .unwrap();
+        let challenge = start_registration(
+            &player,
+            PASSWORD,
+            &players,
+            &Limiter::default(),
+            &challenges,
+            &rp,
+        )
+        .await
+        .unwrap();
+        let registration = authenticator.create(&challenge, &rp);
+        finish_registration(
+            &player,
+            PASSWORD,
+            registration,
+            &players,
+            &Limiter::default(),
+            &challenges,
+            &rp,
+        )
+        .await
+        .unwrap();
+        (players, challenges, rp, authenticator)
+    }
+
+    #[tokio::test]
+    async fn registers_and_logs_in() {
+        let (players, challenges, rp, mut authenticator) = registered().await;
+        let challenge = start_login("alice", &players, &challenges, &rp)
+            .await
+            .unwrap();
+        let assertion = authenticator.get("alice", &challenge, &rp, USER_PRESENT | USER_VERIFIED);
+        let player = finish_login(assertion.clone(), &players, &challenges, &rp)
+            .await
+            .unwrap();
+        assert_eq!(player.name, "alice");
+        // Challenges only work once
+        assert!(
+            finish_login(assertion, &players, &challenges, &rp)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn login_needs_user_verification() {
+        let (players, challenges, rp, mut authenticator) = registered().await;
+        let challenge = start_login("alice", &players, &challenges, &rp)
+            .await
+            .unwrap();
+        let assertion = authenticator.get("alice", &challenge, &rp, USER_PRESENT);
+        assert!(
+            finish_login(assertion, &players, &challenges, &rp)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn registration_needs_the_password() {
+        let (players, challenges, rp, _) = registered().await;
+        let player = players.find_by_name("alice").await.unwrap().unwrap().player;
+        assert!(
+            start_registration(
+                &player,
+                "wrongPassword1",
+                &players,
+                &Limiter::default(),
+                &challenges,
+                &rp
+            )
+            .await
+            .is_err()
+        );
+    }
+}
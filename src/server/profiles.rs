@@ -0,0 +1,133 @@
+use super::{
+    prelude::*,
+    storage::{GameStore, PlayerStore},
+};
+use crate::common::profile::{GamePage, PlayerProfile};
+
+const PAGE_SIZE: u64 = 20;
+
+pub async fn find_player(name: &str, players: &dyn PlayerStore) -> Result<Player> {
+    players
+        .find_by_name(name)
+        .await?
+        .map(|player| player.player)
+        .ok_or_else(|| anyhow!("No player named {name}"))
+}
+
+/// Totals up every completed game the player was in, both per variant and color and per opponent.
+/// Like the game list, everyone else only gets totals from the public ones.
+pub async fn get_profile(
+    player: Player,
+    viewer: &Player,
+    games: &dyn GameStore,
+) -> Result<PlayerProfile> {
+    let public_only = viewer.id != player.id;
+    games.profile(player, public_only).await
+}
+
+/// Lists the player's completed games. Everyone else only gets to see the public ones.
+pub async fn get_completed_games(
+    player: ObjectId,
+    page: u64,
+    viewer: &Player,
+    games: &dyn GameStore,
+) -> Result<GamePage> {
+    let public_only = viewer.id != Some(player);
+    // Grabbing one extra game is the cheapest way to know whether there's another page
+    let skip = page
+        .checked_mul(PAGE_SIZE)
+        .ok_or_else(|| anyhow!("There aren't that many pages"))?;
+    let mut completed = games
+        .completed_games(player, public_only, skip, PAGE_SIZE + 1)
+        .await?;
+    let more = completed.len() as u64 > PAGE_SIZE;
+    completed.truncate(PAGE_SIZE as usize);
+    Ok(GamePage {
+        games: completed,
+        more,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        common::game::GameTypes,
+        server::{
+            games::{join_open_game, new_open_game, resign},
+            notifications::{Notifier, Recording},
+            storage::{MemoryGames, MemoryPlayers, sqlite},
+            webhooks::Webhooks,
+        },
+    };
+
+    fn player(name: &str) -> Player {
+        Player {
+            id: Some(ObjectId::new()),
+            name: name.into(),
+        }
+    }
+
+    /// Plays a game between them that alice resigns
+    async fn play(
+        alice: &Player,
+        bob: &Player,
+        visibility: Visibility,
+        games: &dyn GameStore,
+        notifier: &Notifier,
+    ) {
+        let id = new_open_game(
+            alice.clone(),
+            GameTypes::Square,
+            visibility,
+            false,
+            TimeControl::Unlimited,
+            games,
+        )
+        .await
+        .unwrap();
+        join_open_game(id, bob.clone(), games, notifier)
+            .await
+            .unwrap();
+        resign(id, alice.clone(), notifier, games, None, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn others_only_see_public_games() {
+        let players: Arc<dyn PlayerStore> = Arc::new(MemoryPlayers::default());
+        let webhooks = Webhooks::start(players.clone(), false).unwrap();
+        let notifier = Notifier::start(None, Arc::new(Recording::default()), players, webhooks);
+        let (alice, bob, carol) = (player("alice"), player("bob"), player("carol"));
+        let (sqlite_games, _, _) = sqlite::open(":memory:").unwrap();
+        let stores: [&dyn GameStore; 2] = [&MemoryGames::default(), &sqlite_games];
+        for games in stores {
+            play(&alice, &bob, Visibility::Public, games, &notifier).await;
+            play(&alice, &carol, Visibility::Private, games, &notifier).await;
+
+            let own = get_profile(alice.clone(), &alice, games).await.unwrap();
+            assert_eq!(own.head_to_head.len(), 2);
+            let seen = get_profile(alice.clone(), &bob, games).await.unwrap();
+            assert_eq!(seen.head_to_head.len(), 1);
+            assert_eq!(seen.head_to_head[0].opponent.name, "bob");
+            let page = get_completed_games(alice.id.unwrap(), 0, &bob, games)
+                .await
+                .unwrap();
+            assert_eq!(page.games.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn huge_pages_are_an_error() {
+        let alice = player("alice");
+        let games = MemoryGames::default();
+        assert!(
+            get_completed_games(alice.id.unwrap(), u64::MAX, &alice, &games)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{f64::consts::PI, time::SystemTime};
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::serialize_to_bson,
+    error::{ErrorKind, WriteFailure},
+};
+
+use super::prelude::*;
+use crate::common::{game::GameTypes, rating::RatingChange};
+
+// Glicko-2 works on its own scale, this converts to and from the usual 1500-centered one
+const SCALE: f64 = 173.7178;
+// Constrains how quickly volatility can change. Glickman suggests something between 0.3 and 1.2.
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000001;
+// MongoDB's code for a write that breaks a unique index
+const DUPLICATE_KEY: i32 = 11000;
+
+pub async fn get_ratings(
+    player: ObjectId,
+    ratings: &Collection<RatingRecord>,
+) -> Result<Vec<RatingRecord>> {
+    Ok(ratings
+        .find(doc! {"player": player})
+        .await?
+        .try_collect()
+        .await?)
+}
+
+pub async fn delete_ratings(player: ObjectId, ratings: &Collection<RatingRecord>) -> Result<()> {
+    ratings.delete_many(doc! {"player": player}).await?;
+    Ok(())
+}
+
+pub async fn get_rating(
+    player: ObjectId,
+    game_type: GameTypes,
+    ratings: &Collection<RatingRecord>,
+) -> Result<Rating> {
+    let record = ratings
+        .find_one(doc! {"player": player, "game_type": serialize_to_bson(&game_type)?})
+        .await?;
+    Ok(record.map_or_else(Rating::default, |record| record.current))
+}
+
+/// Updates both players' ratings for a finished game. Every game is treated as its own rating
+/// period since correspondence games finish at very different times.
+pub async fn record_result(
+    game_id: ObjectId,
+    game: &Game,
+    ratings: &Collection<RatingRecord>,
+) -> Result<()> {
+    let (Some(winner), Some(maker), Some(joiner)) =
+        (game.game_over(), game.maker.id, game.joiner.id)
+    else {
+        return Ok(());
+    };
+    if maker == joiner {
+        return Ok(());
+    }
+
+    let game_type = game.game_type();
+    let maker_before = get_rating(maker, game_type, ratings).await?;
+    let joiner_before = get_rating(joiner, game_type, ratings).await?;
+    let maker_score = if winner == game.maker_color { 1.0 } else { 0.0 };
+
+    for (player, opponent, score) in [
+        (maker, joiner_before, maker_score),
+        (joiner, maker_before, 1.0 - maker_score),
+    ] {
+        // Another game finishing at the same time can change the rating after it's read, so the
+        // write only goes through over the rating it started from. Otherwise the upsert trips
+        // the unique index and it starts over.
+        loop {
+            let before = get_rating(player, game_type, ratings).await?;
+            let after = glicko2(before, &[(opponent, score)]);
+            let change = RatingChange {
+                game: game_id,
+                before,
+                after,
+                time: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+            };
+            let saved = ratings
+                .update_one(
+                    doc! {
+                        "player": player,
+                        "game_type": serialize_to_bson(&game_type)?,
+                        "current": serialize_to_bson(&before)?,
+                    },
+                    doc! {
+                        "$set": {"current": serialize_to_bson(&after)?},
+                        "$push": {"history": serialize_to_bson(&change)?},
+                    },
+                )
+                .upsert(true)
+                .await;
+            match saved {
+                Ok(_) => break,
+                Err(error) if is_duplicate(&error) => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_duplicate(error: &mongodb::error::Error) -> bool {
+    matches!(
+        &*error.kind,
+        ErrorKind::Write(WriteFailure::WriteError(error)) if error.code == DUPLICATE_KEY
+    )
+}
+
+/// Runs one Glicko-2 rating period for `player` given each opponent's rating and the score
+/// against them (1 for a win, 0.5 for a draw, 0 for a loss).
+pub fn glicko2(player: Rating, results: &[(Rating, f64)]) -> Rating {
+    let mu = (player.rating - 1500.0) / SCALE;
+    let phi = player.deviation / SCALE;
+    if results.is_empty() {
+        let phi = (phi.powi(2) + player.volatility.powi(2)).sqrt();
+        return Rating {
+            deviation: (phi * SCALE).min(350.0),
+            ..player
+        };
+    }
+
+    let opponents: Vec<(f64, f64, f64)> = results
+        .iter()
+        .map(|(opponent, score)| {
+            let g = g((opponent.deviation) / SCALE);
+            let e = 1.0 / (1.0 + (-g * (mu - (opponent.rating - 1500.0) / SCALE)).exp());
+            (g, e, *score)
+        })
+        .collect();
+    let v = 1.0
+        / opponents
+            .iter()
+            .map(|(g, e, _)| g.powi(2) * e * (1.0 - e))
+            .sum::<f64>();
+    let improvement: f64 = opponents.iter().map(|(g, e, s)| g * (s - e)).sum();
+    let delta = v * improvement;
+
+    let volatility = new_volatility(player.volatility, phi, v, delta);
+    let phi_star = (phi.powi(2) + volatility.powi(2)).sqrt();
+    let new_phi = 1.0 / (1.0 / phi_star.powi(2) + 1.0 / v).sqrt();
+    let new_mu = mu + new_phi.powi(2) * improvement;
+
+    Rating {
+        rating: new_mu * SCALE + 1500.0,
+        deviation: (new_phi * SCALE).min(350.0),
+        volatility,
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi.powi(2) / PI.powi(2)).sqrt()
+}
+
+// Step 5 of the Glicko-2 paper, which finds the new volatility with the Illinois algorithm
+fn new_volatility(sigma: f64, phi: f64, v: f64, delta: f64) -> f64 {
+    let a = sigma.powi(2).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta.powi(2) - phi.powi(2) - v - ex) / (2.0 * (phi.powi(2) + v + ex).powi(2))
+            - (x - a) / TAU.powi(2)
+    };
+
+    let mut big_a = a;
+    let mut big_b = if delta.powi(2) > phi.powi(2) + v {
+        (delta.powi(2) - phi.powi(2) - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+
+    let mut f_a = f(big_a);
+    let mut f_b = f(big_b);
+    while (big_b - big_a).abs() > EPSILON {
+        let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+        let f_c = f(big_c);
+        if f_c * f_b <= 0.0 {
+            big_a = big_b;
+            f_a = f_b;
+        } else {
+            f_a /= 2.0;
+        }
+        big_b = big_c;
+        f_b = f_c;
+    }
+
+    (big_a / 2.0).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            volatility: 0.06,
+        }
+    }
+
+    /// The worked example from Glickman's "Example of the Glicko-2 system"
+    #[test]
+    fn matches_glickmans_example() {
+        let after = glicko2(
+            rating(1500.0, 200.0),
+            &[
+                (rating(1400.0, 30.0), 1.0),
+                (rating(1550.0, 100.0), 0.0),
+                (rating(1700.0, 300.0), 0.0),
+            ],
+        );
+        assert!((after.rating - 1464.06).abs() < 0.01, "{after:?}");
+        assert!((after.deviation - 151.52).abs() < 0.01, "{after:?}");
+        assert!((after.volatility - 0.05999).abs() < 0.00001, "{after:?}");
+    }
+
+    #[test]
+    fn idle_players_grow_less_certain() {
+        let after = glicko2(rating(1500.0, 200.0), &[]);
+        assert_eq!(after.rating, 1500.0);
+        assert!(after.deviation > 200.0);
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::watch;
+
+use super::prelude::*;
+
+/// Counts the spectators currently streaming each game. Counts only live in memory since they're
+/// tied to open connections on this server.
+#[derive(Clone, Default)]
+pub struct Spectators {
+    counts: Arc<Mutex<HashMap<ObjectId, watch::Sender<usize>>>>,
+}
+
+impl Spectators {
+    /// Follows the game's count until the returned watch is dropped
+    pub fn watch(&self, game_id: ObjectId) -> CountWatch {
+        let mut counts = self.counts.lock().unwrap();
+        let receiver = counts
+            .entry(game_id)
+            .or_insert_with(|| watch::channel(0).0)
+            .subscribe();
+        CountWatch {
+            receiver,
+            game_id,
+            spectators: self.clone(),
+        }
+    }
+
+    /// Registers a new spectator until the returned guard is dropped.
+    pub fn join(&self, game_id: ObjectId) -> SpectatorGuard {
+        let mut counts = self.counts.lock().unwrap();
+        counts
+            .entry(game_id)
+            .or_insert_with(|| watch::channel(0).0)
+            .send_modify(|count| *count += 1);
+        SpectatorGuard {
+            game_id,
+            spectators: self.clone(),
+        }
+    }
+
+    fn leave(&self, game_id: ObjectId) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(sender) = counts.get(&game_id) {
+            sender.send_modify(|count| *count -= 1);
+        }
+        remove_unused(&mut counts, game_id, 0);
+    }
+
+    fn stop_watching(&self, game_id: ObjectId) {
+        let mut counts = self.counts.lock().unwrap();
+        // The receiver being dropped is still around until this returns
+        remove_unused(&mut counts, game_id, 1);
+    }
+}
+
+/// Forgets the game once nobody spectates it and nobody but the given number of receivers
+/// follows its count
+fn remove_unused(
+    counts: &mut HashMap<ObjectId, watch::Sender<usize>>,
+    game_id: ObjectId,
+    leaving: usize,
+) {
+    if let Some(sender) = counts.get(&game_id)
+        && *sender.borrow() == 0
+        && sender.receiver_count() <= leaving
+    {
+        counts.remove(&game_id);
+    }
+}
+
+/// A game's spectator count, for one of its players
+pub struct CountWatch {
+    pub receiver: watch::Receiver<usize>,
+    game_id: ObjectId,
+    spectators: Spectators,
+}
+
+impl Drop for CountWatch {
+    fn drop(&mut self) {
+        self.spectators.stop_watching(self.game_id);
+    }
+}
+
+pub struct SpectatorGuard {
+    game_id: ObjectId,
+    spectators: Spectators,
+}
+
+impl Drop for SpectatorGuard {
+    fn drop(&mut self) {
+        self.spectators.leave(self.game_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(spectators: &Spectators) -> usize {
+        spectators.counts.lock().unwrap().len()
+    }
+
+    #[test]
+    fn games_are_forgotten_once_nobody_follows_them() {
+        let spectators = Spectators::default();
+        let game = ObjectId::new();
+
+        drop(spectators.watch(game));
+        assert_eq!(tracked(&spectators), 0);
+
+        let watch = spectators.watch(game);
+        let guard = spectators.join(game);
+        assert_eq!(*watch.receiver.borrow(), 1);
+        drop(guard);
+        assert_eq!(*watch.receiver.borrow(), 0);
+        assert_eq!(tracked(&spectators), 1);
+        drop(watch);
+        assert_eq!(tracked(&spectators), 0);
+    }
+}
@@ -1,8 +1,34 @@
-use axum::{Extension, Router, extract::FromRequestParts, http::request::Parts};
+use axum::{
+    Extension, Router,
+    extract::FromRequestParts,
+    http::{HeaderName, HeaderValue, request::Parts},
+};
 use tower_cookies::Cookies;
-use web_push::{IsahcWebPushClient, PartialVapidSignatureBuilder, VapidSignatureBuilder};
+use tower_http::set_header::SetResponseHeaderLayer;
+use web_push::VapidSignatureBuilder;
 
-use super::{config::ServerConfig, mongo, prelude::*};
+use std::sync::Arc;
+
+use super::{
+    auth::{check_origin, find_session},
+    bots,
+    config::{NotifierConfig, ServerConfig, Storage},
+    email::{Mailer, spawn_digests},
+    games::spawn_deadline_sweep,
+    leaderboard::Leaderboards,
+    limits::Limiter,
+    matchmaking::Matchmaker,
+    mongo,
+    notifications::{Notifier, transport},
+    passkeys::Challenges,
+    prelude::*,
+    spectators::Spectators,
+    storage::{
+        GameStore, MemoryGames, MemoryPlayers, MemorySessions, MongoGames, MongoPlayers,
+        MongoSessions, PlayerStore, SessionStore, Sessions, sqlite,
+    },
+    webhooks::Webhooks,
+};
 
 pub type DB<T> = Extension<Collection<T>>;
 
@@ -14,7 +40,15 @@ pub struct SessionRecord {
     #[serde(rename = "_id")]
     #[serde(default)]
     pub id: Option<ObjectId>,
+    /// When the session was created
     pub time: u64,
+    /// Sessions from before this was recorded count as last used when they were created
+    #[serde(default)]
+    pub last_used: u64,
+    /// Hash of the secret half of the session cookie. Sessions from before cookies held a secret
+    /// don't have one and can't be logged into.
+    #[serde(default)]
+    pub token: Option<String>,
     pub subscription: Option<web_push::SubscriptionInfo>,
     pub player: Player,
 }
@@ -41,39 +75,95 @@ impl<T: Send + Sync + 'static> axum::extract::OptionalFromRequestParts<T> for Se
     ) -> std::result::Result<Option<Self>, Self::Rejection> {
         let cookies = Cookies::from_request_parts(parts, state).await.unwrap();
         let sessions =
-            <Extension<Collection<_>> as axum::extract::FromRequestParts<T>>::from_request_parts(
-                parts, state,
-            )
-            .await?;
-        Ok(if let Some(cookie) = cookies.get(TOKEN_COOKIE) {
-            let id: ObjectId = serde_json::from_str(cookie.value())?;
-            sessions.find_one(doc! { "_id": id }).await?
-        } else {
-            None
-        })
+            <Sessions as axum::extract::FromRequestParts<T>>::from_request_parts(parts, state)
+                .await?;
+        let Some(cookie) = cookies.get(TOKEN_COOKIE) else {
+            return Ok(None);
+        };
+        Ok(find_session(cookie.value(), &cookies, &**sessions).await?)
     }
 }
 
-#[derive(Clone)]
-pub struct Notifier {
-    pub client: IsahcWebPushClient,
-    pub crypto: PartialVapidSignatureBuilder,
-}
-
 pub async fn build_state(router: Router) -> Result<Router> {
     let config = ServerConfig::from_env()?;
-    let db = mongo::connect(config.mongo_url.clone()).await?;
-    let players = mongo::setup_players_database(&db, &config.prefix).await?;
-    let games = mongo::setup_games_database(&db, &config.prefix).await?;
-    let sessions = mongo::setup_session_database(&db, &config.prefix).await?;
-    let notifier = Notifier {
-        client: IsahcWebPushClient::new()?,
-        crypto: VapidSignatureBuilder::from_pem_no_sub(config.pem.as_bytes())?,
+    let mut router = router.merge(bots::routes());
+    let (mut ratings, mut tournaments) = (None, None);
+    let (players, games, sessions): (
+        Arc<dyn PlayerStore>,
+        Arc<dyn GameStore>,
+        Arc<dyn SessionStore>,
+    ) = match &config.storage {
+        Storage::Mongo { url, prefix } => {
+            let db = mongo::connect(url.clone()).await?;
+            let players = mongo::setup_players_database(&db, prefix).await?;
+            let games = mongo::setup_games_database(&db, prefix).await?;
+            let sessions = mongo::setup_session_database(&db, prefix).await?;
+            let features = config.features;
+            if features.chat {
+                let chats = mongo::setup_chat_database(&db, prefix).await?;
+                router = router.layer(Extension(chats));
+            }
+            if features.ratings {
+                let rating_records = mongo::setup_ratings_database(&db, prefix).await?;
+                router = router.layer(Extension(rating_records.clone()));
+                ratings = Some(rating_records);
+            }
+            if features.tournaments {
+                let tournament_records = mongo::setup_tournaments_database(&db, prefix).await?;
+                router = router.layer(Extension(tournament_records.clone()));
+                tournaments = Some(tournament_records);
+            }
+            (
+                Arc::new(MongoPlayers {
+                    players: players.clone_with_type(),
+                }),
+                Arc::new(MongoGames { games }),
+                Arc::new(MongoSessions { sessions }),
+            )
+        }
+        Storage::Sqlite { path } => {
+            let (games, players, sessions) = sqlite::open(path)?;
+            (Arc::new(players), Arc::new(games), Arc::new(sessions))
+        }
+        Storage::Memory => (
+            Arc::new(MemoryPlayers::default()),
+            Arc::new(MemoryGames::default()),
+            Arc::new(MemorySessions::default()),
+        ),
+    };
+
+    let crypto = match &config.notifier {
+        NotifierConfig::WebPush { pem, .. } => {
+            Some(VapidSignatureBuilder::from_pem_no_sub(pem.as_bytes())?)
+        }
+        _ => None,
     };
+    let transport = transport(&config.notifier, crypto.as_ref(), sessions.clone())?;
+    let webhooks = Webhooks::start(players.clone(), config.private_webhooks)?;
+    let notifier = Notifier::start(crypto, transport, players.clone(), webhooks);
+    spawn_deadline_sweep(notifier.clone(), games.clone(), ratings, tournaments);
+
+    if let Some(email) = config.email.clone() {
+        let mailer = Mailer::new(email);
+        spawn_digests(mailer.clone(), players.clone(), games.clone());
+        router = router.layer(Extension(mailer));
+    }
 
     Ok(router
         .layer(Extension(players))
         .layer(Extension(games))
         .layer(Extension(sessions))
-        .layer(Extension(notifier)))
+        .layer(Extension(notifier))
+        .layer(Extension(Spectators::default()))
+        .layer(Extension(Leaderboards::default()))
+        .layer(Extension(Matchmaker::default()))
+        .layer(Extension(Limiter::default()))
+        .layer(Extension(Challenges::default()))
+        .layer(Extension(config))
+        .layer(axum::middleware::from_fn(check_origin))
+        // Lets the service worker served out of /assets control the whole app for offline use
+        .layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("service-worker-allowed"),
+            HeaderValue::from_static("/"),
+        )))
 }
@@ -0,0 +1,298 @@
+mod memory;
+mod mongo;
+pub mod sqlite;
+
+use std::{future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use axum::Extension;
+use dioxus::prelude::ServerFnError;
+use futures::{StreamExt, stream::BoxStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::{limits::TooManyAttempts, prelude::*, state::SessionRecord};
+use crate::common::profile::{HeadToHead, PlayerProfile, Record, VariantRecord};
+
+pub use memory::{MemoryGames, MemoryPlayers, MemorySessions};
+pub use mongo::{MongoGames, MongoPlayers, MongoSessions};
+
+pub type Store<T> = Extension<Arc<T>>;
+pub type Games = Store<dyn GameStore>;
+pub type Players = Store<dyn PlayerStore>;
+pub type Sessions = Store<dyn SessionStore>;
+
+/// Every new version of one game, in the order they were saved
+pub type GameFeed = BoxStream<'static, Result<AnyGame>>;
+
+#[async_trait]
+pub trait GameStore: Send + Sync {
+    async fn get(&self, id: ObjectId) -> Result<Option<AnyGame>>;
+
+    async fn insert(&self, game: AnyGame) -> Result<ObjectId>;
+
+    /// Overwrites the stored game with the same id and tells everyone watching it. This only
+    /// happens if the stored version still matches the game's version, which then goes up by one.
+    /// Otherwise it fails with a [`Conflict`].
+    async fn replace(&self, game: AnyGame) -> Result<()>;
+
+    /// Every game the player made or joined, in any state
+    async fn player_games(&self, player: ObjectId) -> Result<Vec<AnyGame>>;
+
+    /// Requests that are still waiting for someone to join
+    async fn open_requests(&self) -> Result<Vec<AnyGame>>;
+
+    /// Games in progress with a limit on how long each move can take
+    async fn timed_games(&self) -> Result<Vec<AnyGame>>;
+
+    /// Public games in progress that the player isn't part of
+    async fn public_games(&self, player: ObjectId) -> Result<Vec<AnyGame>>;
+
+    /// The player's completed games newest first, skipping any they played against themselves
+    async fn completed_games(
+        &self,
+        player: ObjectId,
+        public_only: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<AnyGame>>;
+
+    /// Totals up every completed game the player was in, both per variant and color and per
+    /// opponent. `public_only` leaves out games only their players can see.
+    async fn profile(&self, player: Player, public_only: bool) -> Result<PlayerProfile>;
+
+    /// Starts following a game. Changes made after this returns are always seen.
+    async fn watch(&self, id: ObjectId) -> Result<GameFeed>;
+
+    /// Follows every game as it's created or saved. Unlike [`watch`](Self::watch) this skips
+    /// versions when it falls behind, so it's for noticing games rather than following one.
+    async fn watch_all(&self) -> Result<GameFeed>;
+
+    /// Updates every copy of the player kept in games, like after they change their name
+    async fn rename_player(&self, player: &Player) -> Result<()>;
+
+    /// Drops the requests the player made that nobody has joined yet
+    async fn delete_requests(&self, player: ObjectId) -> Result<()>;
+}
+
+#[async_trait]
+pub trait PlayerStore: Send + Sync {
+    async fn find_by_name(&self, name: &str) -> Result<Option<PasswordPlayer>>;
+
+    async fn find_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<Player>>;
+
+    async fn get(&self, id: ObjectId) -> Result<Option<PasswordPlayer>>;
+
+    /// Fails if the name is already taken
+    async fn insert(&self, player: PasswordPlayer) -> Result<ObjectId>;
+
+    /// Fails if the name is already taken by someone else. Like games, this only saves over the
+    /// version that was read and fails with a [`Conflict`] otherwise.
+    async fn replace(&self, player: PasswordPlayer) -> Result<()>;
+
+    async fn delete(&self, id: ObjectId) -> Result<()>;
+
+    /// Players with a confirmed email address who want the daily digest
+    async fn digest_players(&self) -> Result<Vec<PasswordPlayer>>;
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn get(&self, id: ObjectId) -> Result<Option<SessionRecord>>;
+
+    async fn insert(&self, session: SessionRecord) -> Result<ObjectId>;
+
+    async fn replace(&self, session: SessionRecord) -> Result<()>;
+
+    async fn player_sessions(&self, player: ObjectId) -> Result<Vec<SessionRecord>>;
+
+    async fn delete(&self, id: ObjectId) -> Result<()>;
+
+    async fn delete_player_sessions(&self, player: ObjectId) -> Result<()>;
+
+    /// Updates the copy of the player kept in each of their sessions
+    async fn rename_player(&self, player: &Player) -> Result<()>;
+}
+
+/// Someone else saved the game or player after it was read. Reading it again and retrying might
+/// work.
+#[derive(Debug)]
+pub struct Conflict;
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "This changed while it was being saved, please try again")
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Turns an error into one for the client, using 409 for conflicts so it knows to retry and 429
+/// when it has to wait.
+pub fn server_error(error: anyhow::Error) -> ServerFnError {
+    let code = if error.is::<Conflict>() {
+        409
+    } else if error.is::<TooManyAttempts>() {
+        429
+    } else {
+        500
+    };
+    ServerFnError::ServerError {
+        message: error.to_string(),
+        code,
+        details: None,
+    }
+}
+
+/// An in-process change feed for backends that can't watch for changes on their own. Only works
+/// when every write goes through this server.
+struct Changes {
+    sender: broadcast::Sender<AnyGame>,
+}
+
+impl Default for Changes {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(256).0,
+        }
+    }
+}
+
+impl Changes {
+    /// Callers should still hold whatever lock guarded the write so watchers see versions in the
+    /// order they were saved.
+    fn publish(&self, game: AnyGame) {
+        let _ = self.sender.send(game);
+    }
+
+    fn watch<F, Fut>(&self, id: ObjectId, latest: F) -> GameFeed
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<AnyGame>>> + Send,
+    {
+        let mut changes = self.sender.subscribe();
+        async_stream::stream! {
+            loop {
+                match changes.recv().await {
+                    Ok(game) if game.id == Some(id) => yield Ok(game),
+                    Ok(_) => {}
+                    // Every version is the whole game, so catching up only needs the newest one
+                    Err(RecvError::Lagged(_)) => match latest().await {
+                        Ok(Some(game)) => yield Ok(game),
+                        Ok(None) => {}
+                        Err(err) => yield Err(err),
+                    },
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn watch_all(&self) -> GameFeed {
+        let mut changes = self.sender.subscribe();
+        async_stream::stream! {
+            loop {
+                match changes.recv().await {
+                    Ok(game) => yield Ok(game),
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("game feed fell behind and skipped {skipped} saves")
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Updates the copies of the player in a game, returning whether there were any
+fn rename_in_game(game: &mut AnyGame, player: &Player) -> bool {
+    let copies = match &mut game.game {
+        GameOrRequest::Request(request) => vec![&mut request.maker],
+        GameOrRequest::Game(game) | GameOrRequest::Completed(game) => {
+            vec![&mut game.maker, &mut game.joiner]
+        }
+    };
+    let mut renamed = false;
+    for copy in copies {
+        if copy.id == player.id {
+            copy.name = player.name.clone();
+            renamed = true;
+        }
+    }
+    renamed
+}
+
+/// Whether a completed game counts towards the player's record
+fn counts_for(game: &AnyGame, player: ObjectId) -> Option<&Game> {
+    match &game.game {
+        GameOrRequest::Completed(game)
+            if game.maker.id != game.joiner.id
+                && (game.maker.id == Some(player) || game.joiner.id == Some(player)) =>
+        {
+            Some(game)
+        }
+        _ => None,
+    }
+}
+
+/// Builds a profile out of completed games for backends that can't aggregate on their own.
+fn tally(player: Player, games: &[&Game]) -> PlayerProfile {
+    let mut records: Vec<VariantRecord> = Vec::new();
+    let mut head_to_head: Vec<HeadToHead> = Vec::new();
+    for game in games {
+        let color = if game.maker.id == player.id {
+            game.maker_color
+        } else {
+            game.maker_color.other()
+        };
+        let opponent = game.opponent(&player);
+        let game_type = game.game_type();
+
+        let variant = match records
+            .iter()
+            .position(|record| record.game_type == game_type && record.color == color)
+        {
+            Some(i) => &mut records[i].record,
+            None => {
+                records.push(VariantRecord {
+                    game_type,
+                    color,
+                    record: Record::default(),
+                });
+                &mut records.last_mut().unwrap().record
+            }
+        };
+        count_result(variant, game.winner, color);
+
+        let versus = match head_to_head
+            .iter()
+            .position(|versus| versus.opponent.id == opponent.id)
+        {
+            Some(i) => &mut head_to_head[i].record,
+            None => {
+                head_to_head.push(HeadToHead {
+                    opponent: opponent.clone(),
+                    record: Record::default(),
+                });
+                &mut head_to_head.last_mut().unwrap().record
+            }
+        };
+        count_result(versus, game.winner, color);
+    }
+    head_to_head.sort_by(|a, b| a.opponent.name.cmp(&b.opponent.name));
+
+    PlayerProfile {
+        player,
+        records,
+        head_to_head,
+    }
+}
+
+fn count_result(record: &mut Record, winner: Option<Color>, color: Color) {
+    match winner {
+        None => record.draws += 1,
+        Some(winner) if winner == color => record.wins += 1,
+        Some(_) => record.losses += 1,
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+use super::{
+    Changes, Conflict, GameFeed, GameStore, PlayerStore, SessionStore, counts_for, rename_in_game,
+    tally,
+};
+use crate::common::profile::PlayerProfile;
+use crate::server::{prelude::*, state::SessionRecord};
+
+/// Keeps games in memory. ObjectIds sort by creation time, so a BTreeMap keeps them in order.
+#[derive(Default)]
+pub struct MemoryGames {
+    games: Arc<Mutex<BTreeMap<ObjectId, AnyGame>>>,
+    changes: Changes,
+}
+
+impl MemoryGames {
+    fn filtered(&self, keep: impl Fn(&AnyGame) -> bool) -> Vec<AnyGame> {
+        let games = self.games.lock().unwrap();
+        games.values().filter(|game| keep(game)).cloned().collect()
+    }
+}
+
+#[async_trait]
+impl GameStore for MemoryGames {
+    async fn get(&self, id: ObjectId) -> Result<Option<AnyGame>> {
+        Ok(self.games.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn insert(&self, mut game: AnyGame) -> Result<ObjectId> {
+        let id = ObjectId::new();
+        game.id = Some(id);
+        let mut games = self.games.lock().unwrap();
+        games.insert(id, game.clone());
+        self.changes.publish(game);
+        Ok(id)
+    }
+
+    async fn replace(&self, mut game: AnyGame) -> Result<()> {
+        let id = game.id.ok_or_else(|| anyhow!("Game has no id"))?;
+        let mut games = self.games.lock().unwrap();
+        let Some(stored) = games
+            .get_mut(&id)
+            .filter(|stored| stored.version == game.version)
+        else {
+            bail!(Conflict)
+        };
+        game.version += 1;
+        *stored = game.clone();
+        self.changes.publish(game);
+        Ok(())
+    }
+
+    async fn player_games(&self, player: ObjectId) -> Result<Vec<AnyGame>> {
+        Ok(self.filtered(|game| match &game.game {
+            GameOrRequest::Request(request) => request.maker.id == Some(player),
+            GameOrRequest::Game(game) | GameOrRequest::Completed(game) => {
+                game.maker.id == Some(player) || game.joiner.id == Some(player)
+            }
+        }))
+    }
+
+    async fn open_requests(&self) -> Result<Vec<AnyGame>> {
+        Ok(self.filtered(|game| matches!(game.game, GameOrRequest::Request(_))))
+    }
+
+    async fn timed_games(&self) -> Result<Vec<AnyGame>> {
+        Ok(self.filtered(|any_game| {
+            matches!(any_game.game, GameOrRequest::Game(_))
+                && any_game.time_control != TimeControl::Unlimited
+        }))
+    }
+
+    async fn public_games(&self, player: ObjectId) -> Result<Vec<AnyGame>> {
+        Ok(self.filtered(|any_game| match &any_game.game {
+            GameOrRequest::Game(game) => {
+                any_game.visibility == Visibility::Public
+                    && game.maker.id != Some(player)
+                    && game.joiner.id != Some(player)
+            }
+            _ => false,
+        }))
+    }
+
+    async fn completed_games(
+        &self,
+        player: ObjectId,
+        public_only: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<AnyGame>> {
+        let games = self.games.lock().unwrap();
+        Ok(games
+            .values()
+            .rev()
+            .filter(|game| counts_for(game, player).is_some())
+            .filter(|game| !public_only || game.visibility == Visibility::Public)
+            .skip(skip as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn profile(&self, player: Player, public_only: bool) -> Result<PlayerProfile> {
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        let games = self.games.lock().unwrap();
+        let completed: Vec<&Game> = games
+            .values()
+            .filter(|game| !public_only || game.visibility == Visibility::Public)
+            .filter_map(|game| counts_for(game, id))
+            .collect();
+        Ok(tally(player, &completed))
+    }
+
+    async fn watch(&self, id: ObjectId) -> Result<GameFeed> {
+        let games = self.games.clone();
+        Ok(self.changes.watch(id, move || {
+            let latest = games.lock().unwrap().get(&id).cloned();
+            async move { Ok(latest) }
+        }))
+    }
+
+    async fn watch_all(&self) -> Result<GameFeed> {
+        Ok(self.changes.watch_all())
+    }
+
+    async fn rename_player(&self, player: &Player) -> Result<()> {
+        let mut games = self.games.lock().unwrap();
+        for game in games.values_mut() {
+            if rename_in_game(game, player) {
+                game.version += 1;
+                self.changes.publish(game.clone());
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_requests(&self, player: ObjectId) -> Result<()> {
+        let mut games = self.games.lock().unwrap();
+        games.retain(|_, game| {
+            !matches!(&game.game, GameOrRequest::Request(request) if request.maker.id == Some(player))
+        });
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryPlayers {
+    players: Mutex<Vec<PasswordPlayer>>,
+}
+
+#[async_trait]
+impl PlayerStore for MemoryPlayers {
+    async fn find_by_name(&self, name: &str) -> Result<Option<PasswordPlayer>> {
+        let players = self.players.lock().unwrap();
+        Ok(players.iter().find(|player| player.name == name).cloned())
+    }
+
+    async fn find_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<Player>> {
+        let players = self.players.lock().unwrap();
+        Ok(players
+            .iter()
+            .filter(|player| player.id.is_some_and(|id| ids.contains(&id)))
+            .map(|player| player.player.clone())
+            .collect())
+    }
+
+    async fn get(&self, id: ObjectId) -> Result<Option<PasswordPlayer>> {
+        let players = self.players.lock().unwrap();
+        Ok(players.iter().find(|player| player.id == Some(id)).cloned())
+    }
+
+    async fn insert(&self, mut player: PasswordPlayer) -> Result<ObjectId> {
+        let mut players = self.players.lock().unwrap();
+        if players.iter().any(|other| other.name == player.name) {
+            bail!("Name already taken")
+        }
+        let id = ObjectId::new();
+        player.id = Some(id);
+        players.push(player);
+        Ok(id)
+    }
+
+    async fn replace(&self, mut player: PasswordPlayer) -> Result<()> {
+        let mut players = self.players.lock().unwrap();
+        if players
+            .iter()
+            .any(|other| other.name == player.name && other.id != player.id)
+        {
+            bail!("Name already taken")
+        }
+        let Some(stored) = players
+            .iter_mut()
+            .find(|other| other.id == player.id && other.version == player.version)
+        else {
+            bail!(Conflict)
+        };
+        player.version += 1;
+        *stored = player;
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<()> {
+        let mut players = self.players.lock().unwrap();
+        players.retain(|player| player.id != Some(id));
+        Ok(())
+    }
+
+    async fn digest_players(&self) -> Result<Vec<PasswordPlayer>> {
+        let players = self.players.lock().unwrap();
+        Ok(players
+            .iter()
+            .filter(|player| {
+                player
+                    .email
+                    .as_ref()
+                    .is_some_and(|email| email.verified && email.digest)
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+#[derive(Default)]
+pub struct MemorySessions {
+    sessions: Mutex<HashMap<ObjectId, SessionRecord>>,
+}
+
+#[async_trait]
+impl SessionStore for MemorySessions {
+    async fn get(&self, id: ObjectId) -> Result<Option<SessionRecord>> {
+        Ok(self.sessions.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn insert(&self, mut session: SessionRecord) -> Result<ObjectId> {
+        let id = ObjectId::new();
+        session.id = Some(id);
+        self.sessions.lock().unwrap().insert(id, session);
+        Ok(id)
+    }
+
+    async fn replace(&self, session: SessionRecord) -> Result<()> {
+        let id = session.id.ok_or_else(|| anyhow!("Session has no id"))?;
+        if let Some(stored) = self.sessions.lock().unwrap().get_mut(&id) {
+            *stored = session;
+        }
+        Ok(())
+    }
+
+    async fn player_sessions(&self, player: ObjectId) -> Result<Vec<SessionRecord>> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .values()
+            .filter(|session| session.player.id == Some(player))
+            .cloned()
+            .collect())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<()> {
+        self.sessions.lock().unwrap().remove(&id);
+        Ok(())
+    }
+
+    async fn delete_player_sessions(&self, player: ObjectId) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.player.id != Some(player));
+        Ok(())
+    }
+
+    async fn rename_player(&self, player: &Player) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        for session in sessions.values_mut() {
+            if session.player.id == player.id {
+                session.player = player.clone();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{Document, deserialize_from_document},
+    change_stream::event::OperationType,
+    options::FullDocumentType,
+};
+
+use super::{Conflict, GameFeed, GameStore, PlayerStore, SessionStore};
+use crate::common::profile::{HeadToHead, PlayerProfile, VariantRecord};
+use crate::server::{prelude::*, state::SessionRecord};
+
+pub struct MongoGames {
+    pub games: Collection<AnyGame>,
+}
+
+pub struct MongoPlayers {
+    pub players: Collection<PasswordPlayer>,
+}
+
+pub struct MongoSessions {
+    pub sessions: Collection<SessionRecord>,
+}
+
+// Games someone played against themselves don't say much about how they play
+fn completed_filter(player: ObjectId) -> Document {
+    doc! {
+        "game.type": "Completed",
+        "$or": [{"game.maker._id": player}, {"game.joiner._id": player}],
+        "$expr": {"$ne": ["$game.maker._id", "$game.joiner._id"]},
+    }
+}
+
+fn count(result: &str) -> Document {
+    doc! {"$sum": {"$cond": [{"$eq": ["$result", result]}, 1, 0]}}
+}
+
+#[async_trait]
+impl GameStore for MongoGames {
+    async fn get(&self, id: ObjectId) -> Result<Option<AnyGame>> {
+        Ok(self.games.find_one(doc! {"_id": id}).await?)
+    }
+
+    async fn insert(&self, game: AnyGame) -> Result<ObjectId> {
+        Ok(self
+            .games
+            .insert_one(game)
+            .await?
+            .inserted_id
+            .as_object_id()
+            .unwrap())
+    }
+
+    async fn replace(&self, mut game: AnyGame) -> Result<()> {
+        let id = game.id.ok_or_else(|| anyhow!("Game has no id"))?;
+        let expected = game.version as i64;
+        // Games saved before versions existed don't have the field at all
+        let version = if expected == 0 {
+            doc! {"$in": [0i64, null]}
+        } else {
+            doc! {"$eq": expected}
+        };
+        game.version += 1;
+        let result = self
+            .games
+            .replace_one(doc! {"_id": id, "version": version}, game)
+            .await?;
+        if result.matched_count == 0 {
+            bail!(Conflict)
+        }
+        Ok(())
+    }
+
+    async fn player_games(&self, player: ObjectId) -> Result<Vec<AnyGame>> {
+        let filter = doc! {"$or": [{"game.maker._id": player}, {"game.joiner._id": player}]};
+        Ok(self.games.find(filter).await?.try_collect().await?)
+    }
+
+    async fn open_requests(&self) -> Result<Vec<AnyGame>> {
+        Ok(self
+            .games
+            .find(doc! {"game.type": "Request"})
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn timed_games(&self) -> Result<Vec<AnyGame>> {
+        let filter = doc! {
+            "game.type": "Game",
+            "time_control": {"$exists": true, "$ne": "Unlimited"},
+        };
+        Ok(self.games.find(filter).await?.try_collect().await?)
+    }
+
+    async fn public_games(&self, player: ObjectId) -> Result<Vec<AnyGame>> {
+        let filter = doc! {
+            "visibility": "Public",
+            "game.type": "Game",
+            "game.maker._id": {"$ne": player},
+            "game.joiner._id": {"$ne": player},
+        };
+        Ok(self.games.find(filter).await?.try_collect().await?)
+    }
+
+    async fn completed_games(
+        &self,
+        player: ObjectId,
+        public_only: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<AnyGame>> {
+        let mut filter = completed_filter(player);
+        if public_only {
+            filter.insert("visibility", "Public");
+        }
+        Ok(self
+            .games
+            .find(filter)
+            .sort(doc! {"_id": -1})
+            .skip(skip)
+            .limit(limit as i64)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn profile(&self, player: Player, public_only: bool) -> Result<PlayerProfile> {
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        let mut filter = completed_filter(id);
+        if public_only {
+            filter.insert("visibility", "Public");
+        }
+        let pipeline = [
+            doc! {"$match": filter},
+            doc! {"$addFields": {
+                "is_maker": {"$eq": ["$game.maker._id", id]},
+                "game_type": {"$cond": [{"$eq": [{"$type": "$game.some_game.Square"}, "missing"]}, "Hex", "Square"]},
+            }},
+            doc! {"$addFields": {
+                "color": {"$cond": [
+                    "$is_maker",
+                    "$game.maker_color",
+                    {"$cond": [{"$eq": ["$game.maker_color", "White"]}, "Black", "White"]},
+                ]},
+                "opponent": {"$cond": ["$is_maker", "$game.joiner", "$game.maker"]},
+            }},
+            doc! {"$addFields": {
+                "result": {"$switch": {
+                    "branches": [
+                        {"case": {"$eq": [{"$ifNull": ["$game.winner", null]}, null]}, "then": "draw"},
+                        {"case": {"$eq": ["$game.winner", "$color"]}, "then": "win"},
+                    ],
+                    "default": "loss",
+                }},
+            }},
+            doc! {"$facet": {
+                "records": [
+                    {"$group": {
+                        "_id": {"game_type": "$game_type", "color": "$color"},
+                        "wins": count("win"),
+                        "draws": count("draw"),
+                        "losses": count("loss"),
+                    }},
+                    {"$project": {
+                        "_id": 0,
+                        "game_type": "$_id.game_type",
+                        "color": "$_id.color",
+                        "wins": 1,
+                        "draws": 1,
+                        "losses": 1,
+                    }},
+                ],
+                "head_to_head": [
+                    {"$group": {
+                        "_id": "$opponent._id",
+                        "opponent": {"$last": "$opponent"},
+                        "wins": count("win"),
+                        "draws": count("draw"),
+                        "losses": count("loss"),
+                    }},
+                    {"$sort": {"opponent.name": 1}},
+                    {"$project": {"_id": 0}},
+                ],
+            }},
+        ];
+
+        let mut results = self.games.aggregate(pipeline).await?;
+        let facets = results
+            .try_next()
+            .await?
+            .ok_or_else(|| anyhow!("Profile aggregation returned nothing"))?;
+        let records = facets
+            .get_array("records")?
+            .iter()
+            .filter_map(|record| record.as_document())
+            .map(|record| deserialize_from_document::<VariantRecord>(record.clone()))
+            .collect::<Result<_, _>>()?;
+        let head_to_head = facets
+            .get_array("head_to_head")?
+            .iter()
+            .filter_map(|record| record.as_document())
+            .map(|record| deserialize_from_document::<HeadToHead>(record.clone()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(PlayerProfile {
+            player,
+            records,
+            head_to_head,
+        })
+    }
+
+    async fn watch(&self, id: ObjectId) -> Result<GameFeed> {
+        // Renames update games in place, so updates are looked up in full too.
+        let matcher = doc! {
+            "$match": {"documentKey._id": id, "operationType": {"$in": ["replace", "update"]}}
+        };
+        let change_stream = self
+            .games
+            .watch()
+            .pipeline([matcher])
+            .full_document(FullDocumentType::UpdateLookup)
+            .await?;
+        Ok(change_stream
+            .try_filter_map(|change| async move {
+                Ok(match change.operation_type {
+                    OperationType::Replace | OperationType::Update => change.full_document,
+                    _ => None,
+                })
+            })
+            .map_err(anyhow::Error::from)
+            .boxed())
+    }
+
+    async fn watch_all(&self) -> Result<GameFeed> {
+        let matcher = doc! {"$match": {"operationType": {"$in": ["insert", "replace", "update"]}}};
+        let change_stream = self
+            .games
+            .watch()
+            .pipeline([matcher])
+            .full_document(FullDocumentType::UpdateLookup)
+            .await?;
+        Ok(change_stream
+            .try_filter_map(|change| async move {
+                Ok(match change.operation_type {
+                    OperationType::Insert | OperationType::Replace | OperationType::Update => {
+                        change.full_document
+                    }
+                    _ => None,
+                })
+            })
+            .map_err(anyhow::Error::from)
+            .boxed())
+    }
+
+    async fn rename_player(&self, player: &Player) -> Result<()> {
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        for side in ["maker", "joiner"] {
+            self.games
+                .update_many(
+                    doc! {format!("game.{side}._id"): id},
+                    doc! {
+                        "$set": {format!("game.{side}.name"): &player.name},
+                        "$inc": {"version": 1},
+                    },
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_requests(&self, player: ObjectId) -> Result<()> {
+        self.games
+            .delete_many(doc! {"game.type": "Request", "game.maker._id": player})
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PlayerStore for MongoPlayers {
+    async fn find_by_name(&self, name: &str) -> Result<Option<PasswordPlayer>> {
+        Ok(self.players.find_one(doc! {"name": name}).await?)
+    }
+
+    async fn find_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<Player>> {
+        Ok(self
+            .players
+            .clone_with_type::<Player>()
+            .find(doc! {"_id": {"$in": ids}})
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn get(&self, id: ObjectId) -> Result<Option<PasswordPlayer>> {
+        Ok(self.players.find_one(doc! {"_id": id}).await?)
+    }
+
+    async fn insert(&self, player: PasswordPlayer) -> Result<ObjectId> {
+        // The unique index on name is what actually stops two signups racing for one name
+        if self.find_by_name(&player.name).await?.is_some() {
+            bail!("Name already taken")
+        }
+        Ok(self
+            .players
+            .insert_one(player)
+            .await?
+            .inserted_id
+            .as_object_id()
+            .unwrap())
+    }
+
+    async fn replace(&self, mut player: PasswordPlayer) -> Result<()> {
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        if let Some(other) = self.find_by_name(&player.name).await?
+            && other.id != player.id
+        {
+            bail!("Name already taken")
+        }
+        let expected = player.version as i64;
+        // Players saved before versions existed don't have the field at all
+        let version = if expected == 0 {
+            doc! {"$in": [0i64, null]}
+        } else {
+            doc! {"$eq": expected}
+        };
+        player.version += 1;
+        let result = self
+            .players
+            .replace_one(doc! {"_id": id, "version": version}, player)
+            .await?;
+        if result.matched_count == 0 {
+            bail!(Conflict)
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<()> {
+        self.players.delete_one(doc! {"_id": id}).await?;
+        Ok(())
+    }
+
+    async fn digest_players(&self) -> Result<Vec<PasswordPlayer>> {
+        Ok(self
+            .players
+            .find(doc! {"email.verified": true, "email.digest": true})
+            .await?
+            .try_collect()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl SessionStore for MongoSessions {
+    async fn get(&self, id: ObjectId) -> Result<Option<SessionRecord>> {
+        Ok(self.sessions.find_one(doc! {"_id": id}).await?)
+    }
+
+    async fn insert(&self, session: SessionRecord) -> Result<ObjectId> {
+        Ok(self
+            .sessions
+            .insert_one(session)
+            .await?
+            .inserted_id
+            .as_object_id()
+            .unwrap())
+    }
+
+    async fn replace(&self, session: SessionRecord) -> Result<()> {
+        let id = session.id.ok_or_else(|| anyhow!("Session has no id"))?;
+        self.sessions.replace_one(doc! {"_id": id}, session).await?;
+        Ok(())
+    }
+
+    async fn player_sessions(&self, player: ObjectId) -> Result<Vec<SessionRecord>> {
+        Ok(self
+            .sessions
+            .find(doc! {"player._id": player})
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<()> {
+        self.sessions.delete_one(doc! {"_id": id}).await?;
+        Ok(())
+    }
+
+    async fn delete_player_sessions(&self, player: ObjectId) -> Result<()> {
+        self.sessions
+            .delete_many(doc! {"player._id": player})
+            .await?;
+        Ok(())
+    }
+
+    async fn rename_player(&self, player: &Player) -> Result<()> {
+        self.sessions
+            .update_many(
+                doc! {"player._id": player.id},
+                doc! {"$set": {"player.name": &player.name}},
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, params, params_from_iter};
+use serde::de::DeserializeOwned;
+
+use super::{
+    Changes, Conflict, GameFeed, GameStore, PlayerStore, SessionStore, counts_for, rename_in_game,
+    tally,
+};
+use crate::common::profile::PlayerProfile;
+use crate::server::{prelude::*, state::SessionRecord};
+
+type Db = Arc<Mutex<Connection>>;
+
+/// Documents are stored as JSON with the fields we search on copied into their own columns.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS games (
+        id TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        visibility TEXT NOT NULL,
+        maker TEXT,
+        joiner TEXT,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS games_maker ON games (maker);
+    CREATE INDEX IF NOT EXISTS games_joiner ON games (joiner);
+    CREATE INDEX IF NOT EXISTS games_kind ON games (kind);
+    CREATE TABLE IF NOT EXISTS players (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        player TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sessions_player ON sessions (player);
+";
+
+/// Opens (or creates) the database file and hands back a store for each collection. They all
+/// share one connection since SQLite only allows one writer at a time anyway.
+pub fn open(path: &str) -> Result<(SqliteGames, SqlitePlayers, SqliteSessions)> {
+    let connection = Connection::open(path)?;
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.execute_batch(SCHEMA)?;
+    let db: Db = Arc::new(Mutex::new(connection));
+    Ok((
+        SqliteGames {
+            db: db.clone(),
+            changes: Arc::default(),
+        },
+        SqlitePlayers { db: db.clone() },
+        SqliteSessions { db },
+    ))
+}
+
+/// Runs a query off the async runtime since rusqlite blocks
+async fn call<T: Send + 'static>(
+    db: &Db,
+    query: impl FnOnce(&Connection) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || query(&db.lock().unwrap())).await?
+}
+
+fn parse<T: DeserializeOwned>(data: String) -> Result<T> {
+    Ok(serde_json::from_str(&data)?)
+}
+
+fn query_all<T: DeserializeOwned>(
+    connection: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<T>> {
+    let mut statement = connection.prepare(sql)?;
+    let rows = statement.query_map(params, |row| row.get::<_, String>(0))?;
+    rows.map(|data| parse(data?)).collect()
+}
+
+fn query_one<T: DeserializeOwned>(
+    connection: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Option<T>> {
+    connection
+        .query_row(sql, params, |row| row.get::<_, String>(0))
+        .optional()?
+        .map(parse)
+        .transpose()
+}
+
+pub struct SqliteGames {
+    db: Db,
+    changes: Arc<Changes>,
+}
+
+/// The kind, visibility, maker and joiner columns for a game
+fn game_columns(game: &AnyGame) -> (&'static str, String, Option<String>, Option<String>) {
+    let (kind, maker, joiner) = match &game.game {
+        GameOrRequest::Request(request) => ("Request", request.maker.id, None),
+        GameOrRequest::Game(game) => ("Game", game.maker.id, game.joiner.id),
+        GameOrRequest::Completed(game) => ("Completed", game.maker.id, game.joiner.id),
+    };
+    (
+        kind,
+        format!("{:?}", game.visibility),
+        maker.map(|id| id.to_hex()),
+        joiner.map(|id| id.to_hex()),
+    )
+}
+
+#[async_trait]
+impl GameStore for SqliteGames {
+    async fn get(&self, id: ObjectId) -> Result<Option<AnyGame>> {
+        call(&self.db, move |db| {
+            query_one(db, "SELECT data FROM games WHERE id = ?1", [id.to_hex()])
+        })
+        .await
+    }
+
+    async fn insert(&self, mut game: AnyGame) -> Result<ObjectId> {
+        let id = ObjectId::new();
+        game.id = Some(id);
+        let changes = self.changes.clone();
+        call(&self.db, move |db| {
+            let (kind, visibility, maker, joiner) = game_columns(&game);
+            db.execute(
+                "INSERT INTO games (id, kind, visibility, maker, joiner, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![id.to_hex(), kind, visibility, maker, joiner, serde_json::to_string(&game)?],
+            )?;
+            changes.publish(game);
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn replace(&self, mut game: AnyGame) -> Result<()> {
+        let id = game.id.ok_or_else(|| anyhow!("Game has no id"))?;
+        let changes = self.changes.clone();
+        call(&self.db, move |db| {
+            let expected = game.version as i64;
+            game.version += 1;
+            let (kind, visibility, maker, joiner) = game_columns(&game);
+            let updated = db.execute(
+                "UPDATE games SET kind = ?2, visibility = ?3, maker = ?4, joiner = ?5, data = ?6
+                 WHERE id = ?1 AND COALESCE(json_extract(data, '$.version'), 0) = ?7",
+                params![
+                    id.to_hex(),
+                    kind,
+                    visibility,
+                    maker,
+                    joiner,
+                    serde_json::to_string(&game)?,
+                    expected
+                ],
+            )?;
+            if updated == 0 {
+                bail!(Conflict)
+            }
+            // Still holding the connection keeps watchers seeing versions in the order they were saved
+            changes.publish(game);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn player_games(&self, player: ObjectId) -> Result<Vec<AnyGame>> {
+        call(&self.db, move |db| {
+            query_all(
+                db,
+                "SELECT data FROM games WHERE maker = ?1 OR joiner = ?1",
+                [player.to_hex()],
+            )
+        })
+        .await
+    }
+
+    async fn open_requests(&self) -> Result<Vec<AnyGame>> {
+        call(&self.db, move |db| {
+            query_all(db, "SELECT data FROM games WHERE kind = 'Request'", [])
+        })
+        .await
+    }
+
+    async fn timed_games(&self) -> Result<Vec<AnyGame>> {
+        let games: Vec<AnyGame> = call(&self.db, |db| {
+            query_all(db, "SELECT data FROM games WHERE kind = 'Game'", [])
+        })
+        .await?;
+        Ok(games
+            .into_iter()
+            .filter(|game| game.time_control != TimeControl::Unlimited)
+            .collect())
+    }
+
+    async fn public_games(&self, player: ObjectId) -> Result<Vec<AnyGame>> {
+        call(&self.db, move |db| {
+            query_all(
+                db,
+                "SELECT data FROM games WHERE kind = 'Game' AND visibility = 'Public' AND maker != ?1 AND joiner != ?1",
+                [player.to_hex()],
+            )
+        })
+        .await
+    }
+
+    async fn completed_games(
+        &self,
+        player: ObjectId,
+        public_only: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<AnyGame>> {
+        call(&self.db, move |db| {
+            // Hex ObjectIds sort by creation time just like the raw bytes do
+            query_all(
+                db,
+                "SELECT data FROM games
+                 WHERE kind = 'Completed' AND (maker = ?1 OR joiner = ?1) AND maker != joiner
+                   AND (?2 = 0 OR visibility = 'Public')
+                 ORDER BY id DESC LIMIT ?3 OFFSET ?4",
+                params![player.to_hex(), public_only, limit as i64, skip as i64],
+            )
+        })
+        .await
+    }
+
+    async fn profile(&self, player: Player, public_only: bool) -> Result<PlayerProfile> {
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        let games: Vec<AnyGame> = call(&self.db, move |db| {
+            query_all(
+                db,
+                "SELECT data FROM games WHERE kind = 'Completed' AND (maker = ?1 OR joiner = ?1)
+                   AND (?2 = 0 OR visibility = 'Public')",
+                params![id.to_hex(), public_only],
+            )
+        })
+        .await?;
+        let completed: Vec<&Game> = games
+            .iter()
+            .filter_map(|game| counts_for(game, id))
+            .collect();
+        Ok(tally(player, &completed))
+    }
+
+    async fn watch(&self, id: ObjectId) -> Result<GameFeed> {
+        let db = self.db.clone();
+        Ok(self.changes.watch(id, move || {
+            let db = db.clone();
+            async move {
+                call(&db, move |db| {
+                    query_one(db, "SELECT data FROM games WHERE id = ?1", [id.to_hex()])
+                })
+                .await
+            }
+        }))
+    }
+
+    async fn watch_all(&self) -> Result<GameFeed> {
+        Ok(self.changes.watch_all())
+    }
+
+    async fn rename_player(&self, player: &Player) -> Result<()> {
+        let player = player.clone();
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        let changes = self.changes.clone();
+        call(&self.db, move |db| {
+            let games: Vec<AnyGame> = query_all(
+                db,
+                "SELECT data FROM games WHERE maker = ?1 OR joiner = ?1",
+                [id.to_hex()],
+            )?;
+            for mut game in games {
+                if rename_in_game(&mut game, &player) {
+                    game.version += 1;
+                    db.execute(
+                        "UPDATE games SET data = ?2 WHERE id = ?1",
+                        params![game.id.unwrap().to_hex(), serde_json::to_string(&game)?],
+                    )?;
+                    changes.publish(game);
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_requests(&self, player: ObjectId) -> Result<()> {
+        call(&self.db, move |db| {
+            db.execute(
+                "DELETE FROM games WHERE kind = 'Request' AND maker = ?1",
+                [player.to_hex()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+pub struct SqlitePlayers {
+    db: Db,
+}
+
+#[async_trait]
+impl PlayerStore for SqlitePlayers {
+    async fn find_by_name(&self, name: &str) -> Result<Option<PasswordPlayer>> {
+        let name = name.to_string();
+        call(&self.db, move |db| {
+            query_one(db, "SELECT data FROM players WHERE name = ?1", [name])
+        })
+        .await
+    }
+
+    async fn find_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<Player>> {
+        let ids: Vec<String> = ids.iter().map(|id| id.to_hex()).collect();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        call(&self.db, move |db| {
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            let players: Vec<PasswordPlayer> = query_all(
+                db,
+                &format!("SELECT data FROM players WHERE id IN ({placeholders})"),
+                params_from_iter(ids),
+            )?;
+            Ok(players.into_iter().map(|player| player.player).collect())
+        })
+        .await
+    }
+
+    async fn get(&self, id: ObjectId) -> Result<Option<PasswordPlayer>> {
+        call(&self.db, move |db| {
+            query_one(db, "SELECT data FROM players WHERE id = ?1", [id.to_hex()])
+        })
+        .await
+    }
+
+    async fn insert(&self, mut player: PasswordPlayer) -> Result<ObjectId> {
+        let id = ObjectId::new();
+        player.id = Some(id);
+        call(&self.db, move |db| {
+            name_taken(db.execute(
+                "INSERT INTO players (id, name, data) VALUES (?1, ?2, ?3)",
+                params![id.to_hex(), player.name, serde_json::to_string(&player)?],
+            ))?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn replace(&self, mut player: PasswordPlayer) -> Result<()> {
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        call(&self.db, move |db| {
+            let expected = player.version as i64;
+            player.version += 1;
+            let updated = name_taken(db.execute(
+                "UPDATE players SET name = ?2, data = ?3
+                 WHERE id = ?1 AND COALESCE(json_extract(data, '$.version'), 0) = ?4",
+                params![
+                    id.to_hex(),
+                    player.name,
+                    serde_json::to_string(&player)?,
+                    expected
+                ],
+            ))?;
+            if updated == 0 {
+                bail!(Conflict)
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<()> {
+        call(&self.db, move |db| {
+            db.execute("DELETE FROM players WHERE id = ?1", [id.to_hex()])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn digest_players(&self) -> Result<Vec<PasswordPlayer>> {
+        call(&self.db, |db| {
+            query_all(
+                db,
+                "SELECT data FROM players WHERE json_extract(data, '$.email.verified') = 1 AND json_extract(data, '$.email.digest') = 1",
+                [],
+            )
+        })
+        .await
+    }
+}
+
+/// Turns the unique index on names rejecting a write into a friendlier error
+fn name_taken(result: rusqlite::Result<usize>) -> Result<usize> {
+    match result {
+        Err(rusqlite::Error::SqliteFailure(error, _))
+            if error.code == ErrorCode::ConstraintViolation =>
+        {
+            bail!("Name already taken")
+        }
+        result => Ok(result?),
+    }
+}
+
+pub struct SqliteSessions {
+    db: Db,
+}
+
+#[async_trait]
+impl SessionStore for SqliteSessions {
+    async fn get(&self, id: ObjectId) -> Result<Option<SessionRecord>> {
+        call(&self.db, move |db| {
+            query_one(db, "SELECT data FROM sessions WHERE id = ?1", [id.to_hex()])
+        })
+        .await
+    }
+
+    async fn insert(&self, mut session: SessionRecord) -> Result<ObjectId> {
+        let id = ObjectId::new();
+        session.id = Some(id);
+        let player = session
+            .player
+            .id
+            .ok_or_else(|| anyhow!("Player has no id"))?;
+        call(&self.db, move |db| {
+            db.execute(
+                "INSERT INTO sessions (id, player, data) VALUES (?1, ?2, ?3)",
+                params![
+                    id.to_hex(),
+                    player.to_hex(),
+                    serde_json::to_string(&session)?
+                ],
+            )?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn replace(&self, session: SessionRecord) -> Result<()> {
+        let id = session.id.ok_or_else(|| anyhow!("Session has no id"))?;
+        call(&self.db, move |db| {
+            db.execute(
+                "UPDATE sessions SET data = ?2 WHERE id = ?1",
+                params![id.to_hex(), serde_json::to_string(&session)?],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn player_sessions(&self, player: ObjectId) -> Result<Vec<SessionRecord>> {
+        call(&self.db, move |db| {
+            query_all(
+                db,
+                "SELECT data FROM sessions WHERE player = ?1",
+                [player.to_hex()],
+            )
+        })
+        .await
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<()> {
+        call(&self.db, move |db| {
+            db.execute("DELETE FROM sessions WHERE id = ?1", [id.to_hex()])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_player_sessions(&self, player: ObjectId) -> Result<()> {
+        call(&self.db, move |db| {
+            db.execute("DELETE FROM sessions WHERE player = ?1", [player.to_hex()])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn rename_player(&self, player: &Player) -> Result<()> {
+        let player = player.clone();
+        let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+        call(&self.db, move |db| {
+            let sessions: Vec<SessionRecord> = query_all(
+                db,
+                "SELECT data FROM sessions WHERE player = ?1",
+                [id.to_hex()],
+            )?;
+            for session in sessions {
+                let session = SessionRecord {
+                    player: player.clone(),
+                    ..session
+                };
+                db.execute(
+                    "UPDATE sessions SET data = ?2 WHERE id = ?1",
+                    params![
+                        session.id.unwrap().to_hex(),
+                        serde_json::to_string(&session)?
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
+
+use futures::TryStreamExt;
+use mongodb::bson::serialize_to_bson;
+
+use super::{
+    notifications::{Event, Notifier, Payload, send_notification},
+    prelude::*,
+    storage::GameStore,
+};
+use crate::common::{
+    game::GameTypes,
+    tournament::{Pairing, Round, Tournament, TournamentFormat, TournamentStatus},
+};
+
+pub async fn get_tournaments(tournaments: &Collection<Tournament>) -> Result<Vec<Tournament>> {
+    Ok(tournaments
+        .find(doc! {})
+        .sort(doc! {"_id": -1})
+        .await?
+        .try_collect()
+        .await?)
+}
+
+pub async fn get_tournament(
+    tournament_id: ObjectId,
+    tournaments: &Collection<Tournament>,
+) -> Result<Tournament> {
+    tournaments
+        .find_one(doc! {"_id": tournament_id})
+        .await?
+        .ok_or_else(|| anyhow!("No tournament for id"))
+}
+
+pub async fn new_tournament(
+    organizer: Player,
+    name: String,
+    game_type: GameTypes,
+    time_control: TimeControl,
+    format: TournamentFormat,
+    rated: bool,
+    tournaments: &Collection<Tournament>,
+) -> Result<ObjectId> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        bail!("Tournaments need a name")
+    }
+    if let TournamentFormat::Swiss { rounds: 0 } = format {
+        bail!("Swiss tournaments need at least one round")
+    }
+    let tournament = Tournament {
+        id: None,
+        name,
+        organizer,
+        game_type,
+        time_control,
+        format,
+        rated,
+        status: TournamentStatus::Registering,
+        players: Vec::new(),
+        rounds: Vec::new(),
+        current_round: 0,
+    };
+    Ok(tournaments
+        .insert_one(tournament)
+        .await?
+        .inserted_id
+        .as_object_id()
+        .unwrap())
+}
+
+/// Adds or removes a player. Nobody can join or leave once pairings have been made.
+pub async fn set_registered(
+    tournament_id: ObjectId,
+    player: Player,
+    registered: bool,
+    tournaments: &Collection<Tournament>,
+) -> Result<()> {
+    let player = serialize_to_bson(&player)?;
+    let update = if registered {
+        doc! {"$addToSet": {"players": player}}
+    } else {
+        doc! {"$pull": {"players": player}}
+    };
+    let result = tournaments
+        .update_one(doc! {"_id": tournament_id, "status": "Registering"}, update)
+        .await?;
+    if result.matched_count == 0 {
+        bail!("Registration for this tournament is closed")
+    }
+    Ok(())
+}
+
+pub async fn start_tournament(
+    tournament_id: ObjectId,
+    player: &Player,
+    tournaments: &Collection<Tournament>,
+    games: &dyn GameStore,
+    notifier: &Notifier,
+) -> Result<()> {
+    let mut tournament = get_tournament(tournament_id, tournaments).await?;
+    if &tournament.organizer != player {
+        bail!("Only the organizer can start a tournament")
+    }
+    if tournament.status != TournamentStatus::Registering {
+        bail!("This tournament already started")
+    }
+    if tournament.players.len() < 2 {
+        bail!("Tournaments need at least two players")
+    }
+
+    tournament.status = TournamentStatus::Running;
+    tournament.rounds = match tournament.format {
+        TournamentFormat::RoundRobin => round_robin(&tournament.players),
+        TournamentFormat::Swiss { .. } => vec![swiss_round(&tournament)],
+    };
+    // Only the first request to start gets to create games
+    let result = tournaments
+        .update_one(
+            doc! {"_id": tournament_id, "status": "Registering"},
+            doc! {"$set": {
+                "status": serialize_to_bson(&tournament.status)?,
+                "rounds": serialize_to_bson(&tournament.rounds)?,
+            }},
+        )
+        .await?;
+    if result.modified_count == 0 {
+        bail!("This tournament already started")
+    }
+    start_round(&mut tournament, 0, tournaments, games, notifier).await
+}
+
+/// Records the result of a tournament game and starts the next round once every game in the
+/// current one is over.
+pub async fn record_result(
+    tournament_id: ObjectId,
+    game_id: ObjectId,
+    game: &Game,
+    tournaments: &Collection<Tournament>,
+    games: &dyn GameStore,
+    notifier: &Notifier,
+) -> Result<()> {
+    let winner = game.game_over();
+    tournaments
+        .update_one(
+            doc! {"_id": tournament_id},
+            doc! {"$set": {
+                "rounds.$[].pairings.$[pairing].finished": true,
+                "rounds.$[].pairings.$[pairing].winner": serialize_to_bson(&winner)?,
+            }},
+        )
+        .array_filters([doc! {"pairing.game": game_id}])
+        .await?;
+
+    let mut tournament = get_tournament(tournament_id, tournaments).await?;
+    let round = tournament.current_round;
+    let pairings = &tournament.rounds[round as usize].pairings;
+    if !pairings.iter().all(|pairing| pairing.finished) {
+        return Ok(());
+    }
+
+    let next = round + 1;
+    if next >= tournament.total_rounds() {
+        tournaments
+            .update_one(
+                doc! {"_id": tournament_id},
+                doc! {"$set": {"status": serialize_to_bson(&TournamentStatus::Finished)?}},
+            )
+            .await?;
+        return Ok(());
+    }
+
+    // Two games can finish at the same time, so only whoever moves the round forward first
+    // gets to pair the next one
+    let claimed = tournaments
+        .update_one(
+            doc! {"_id": tournament_id, "current_round": round},
+            doc! {"$set": {"current_round": next}},
+        )
+        .await?;
+    if claimed.modified_count == 0 {
+        return Ok(());
+    }
+    tournament.current_round = next;
+    if let TournamentFormat::Swiss { .. } = tournament.format {
+        let pairings = swiss_round(&tournament);
+        tournament.rounds.push(pairings);
+    }
+    start_round(&mut tournament, next, tournaments, games, notifier).await
+}
+
+/// Creates a game for every pairing in the round and lets the players know it's ready.
+async fn start_round(
+    tournament: &mut Tournament,
+    round: u32,
+    tournaments: &Collection<Tournament>,
+    games: &dyn GameStore,
+    notifier: &Notifier,
+) -> Result<()> {
+    let tournament_id = tournament.id.unwrap();
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    for pairing in &mut tournament.rounds[round as usize].pairings {
+        let Some(black) = &pairing.black else {
+            continue;
+        };
+        let game = tournament
+            .game_type
+            .mk_game(pairing.white.clone(), black.clone(), Color::White);
+        let game_id = games
+            .insert(AnyGame {
+                id: None,
+                game: GameOrRequest::Game(game),
+                visibility: Visibility::Public,
+                rated: tournament.rated,
+                time_control: tournament.time_control,
+                tournament: Some(tournament_id),
+                version: 0,
+                times: GameTimes {
+                    created: now,
+                    started: Some(now),
+                    ..GameTimes::default()
+                },
+            })
+            .await?;
+        pairing.game = Some(game_id);
+    }
+    tournaments
+        .update_one(
+            doc! {"_id": tournament_id},
+            doc! {"$set": {
+                format!("rounds.{round}"): serialize_to_bson(&tournament.rounds[round as usize])?,
+            }},
+        )
+        .await?;
+
+    let message = format!("Your next game in {} is ready!", tournament.name);
+    for pairing in &tournament.rounds[round as usize].pairings {
+        let (Some(black), Some(game)) = (&pairing.black, pairing.game) else {
+            continue;
+        };
+        for (player, opponent) in [(&pairing.white, black), (black, &pairing.white)] {
+            send_notification(
+                player.id.unwrap(),
+                Payload::new(Event::NextRound, game, opponent, None, message.clone()),
+                notifier,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Updates every copy of the player kept in tournaments, including past pairings
+pub async fn rename_player(player: &Player, tournaments: &Collection<Tournament>) -> Result<()> {
+    let id = player.id;
+    let name = &player.name;
+    tournaments
+        .update_many(
+            doc! {"organizer._id": id},
+            doc! {"$set": {"organizer.name": name}},
+        )
+        .await?;
+    tournaments
+        .update_many(
+            doc! {"players._id": id},
+            doc! {"$set": {"players.$[player].name": name}},
+        )
+        .array_filters([doc! {"player._id": id}])
+        .await?;
+    for side in ["white", "black"] {
+        tournaments
+            .update_many(
+                doc! {format!("rounds.pairings.{side}._id"): id},
+                doc! {"$set": {format!("rounds.$[].pairings.$[pairing].{side}.name"): name}},
+            )
+            .array_filters([doc! {format!("pairing.{side}._id"): id}])
+            .await?;
+    }
+    Ok(())
+}
+
+/// Sitting out a round counts as a win
+fn bye(player: Player) -> Pairing {
+    Pairing {
+        white: player,
+        black: None,
+        game: None,
+        finished: true,
+        winner: Some(Color::White),
+    }
+}
+
+/// Schedules every round up front using the circle method. With an odd number of players,
+/// whoever would face the empty seat gets a bye that round.
+fn round_robin(players: &[Player]) -> Vec<Round> {
+    let mut seats: Vec<Option<Player>> = players.iter().cloned().map(Some).collect();
+    if seats.len() % 2 == 1 {
+        seats.push(None);
+    }
+    let n = seats.len();
+
+    (0..n - 1)
+        .map(|round| {
+            let pairings = (0..n / 2)
+                .filter_map(|i| {
+                    let (mut first, mut second) = (&seats[i], &seats[n - 1 - i]);
+                    // Alternate colors so the fixed seat doesn't always play white
+                    if (i == 0 && round % 2 == 1) || (i > 0 && i % 2 == 1) {
+                        (first, second) = (second, first);
+                    }
+                    match (first, second) {
+                        (Some(white), Some(black)) => Some(Pairing {
+                            white: white.clone(),
+                            black: Some(black.clone()),
+                            game: None,
+                            finished: false,
+                            winner: None,
+                        }),
+                        (Some(player), None) | (None, Some(player)) => Some(bye(player.clone())),
+                        (None, None) => None,
+                    }
+                })
+                .collect();
+            seats[1..].rotate_right(1);
+            Round { pairings }
+        })
+        .collect()
+}
+
+/// Pairs players with similar scores who haven't met yet. This is a simple search down the
+/// standings rather than the full Dutch system, but it avoids rematches and second byes whenever
+/// it can.
+fn swiss_round(tournament: &Tournament) -> Round {
+    let scores = tournament.scores();
+    let mut played: HashSet<(ObjectId, ObjectId)> = HashSet::new();
+    let mut whites: HashMap<ObjectId, i32> = HashMap::new();
+    let mut byes: HashSet<ObjectId> = HashSet::new();
+    for pairing in tournament.rounds.iter().flat_map(|round| &round.pairings) {
+        let white = pairing.white.id.unwrap();
+        match &pairing.black {
+            Some(black) => {
+                let black = black.id.unwrap();
+                played.insert((white, black));
+                played.insert((black, white));
+                *whites.entry(white).or_default() += 1;
+                *whites.entry(black).or_default() -= 1;
+            }
+            None => {
+                byes.insert(white);
+            }
+        }
+    }
+
+    let mut ranked = tournament.players.clone();
+    ranked.sort_by(|a, b| scores[&b.id.unwrap()].total_cmp(&scores[&a.id.unwrap()]));
+
+    // The lowest ranked player who hasn't sat out yet, and still leaves everyone else a new
+    // opponent, gets the bye. Sitting out twice beats a rematch.
+    let bye_candidates: Vec<Option<usize>> = if ranked.len() % 2 == 1 {
+        let (fresh, again): (Vec<usize>, Vec<usize>) = (0..ranked.len())
+            .rev()
+            .partition(|i| !byes.contains(&ranked[*i].id.unwrap()));
+        fresh.into_iter().chain(again).map(Some).collect()
+    } else {
+        vec![None]
+    };
+    let found = bye_candidates.into_iter().find_map(|bye| {
+        let mut rest = ranked.clone();
+        let bye = bye.map(|i| rest.remove(i));
+        pair_off(&rest, &played).map(|pairs| (bye, pairs))
+    });
+    // Late in a small tournament a rematch can't always be avoided
+    let (bye, pairs) = found.unwrap_or_else(|| {
+        let bye = (ranked.len() % 2 == 1).then(|| ranked.pop().unwrap());
+        let pairs = ranked
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        (bye, pairs)
+    });
+
+    let mut pairings: Vec<Pairing> = pairs
+        .into_iter()
+        .map(|(first, second)| {
+            let (first_id, second_id) = (first.id.unwrap(), second.id.unwrap());
+            // Whoever has had white less often gets it this time
+            let (white, black) = if whites.get(&first_id).copied().unwrap_or_default()
+                <= whites.get(&second_id).copied().unwrap_or_default()
+            {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            Pairing {
+                white,
+                black: Some(black),
+                game: None,
+                finished: false,
+                winner: None,
+            }
+        })
+        .collect();
+    pairings.extend(bye.map(self::bye));
+    Round { pairings }
+}
+
+/// Pairs each player, from the top of the standings down, with the highest ranked opponent they
+/// haven't met that still lets everyone below be paired. Nothing if there's no way around a
+/// rematch.
+fn pair_off(
+    ranked: &[Player],
+    played: &HashSet<(ObjectId, ObjectId)>,
+) -> Option<Vec<(Player, Player)>> {
+    let Some((first, rest)) = ranked.split_first() else {
+        return Some(Vec::new());
+    };
+    let first_id = first.id.unwrap();
+    rest.iter().enumerate().find_map(|(i, opponent)| {
+        if played.contains(&(first_id, opponent.id.unwrap())) {
+            return None;
+        }
+        let mut others = rest.to_vec();
+        others.remove(i);
+        let mut pairs = pair_off(&others, played)?;
+        pairs.insert(0, (first.clone(), opponent.clone()));
+        Some(pairs)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(count: usize) -> Vec<Player> {
+        (0..count)
+            .map(|i| Player {
+                id: Some(ObjectId::new()),
+                name: format!("player{i}"),
+            })
+            .collect()
+    }
+
+    fn tournament(players: Vec<Player>, format: TournamentFormat) -> Tournament {
+        Tournament {
+            id: Some(ObjectId::new()),
+            name: "Test".into(),
+            organizer: players[0].clone(),
+            game_type: GameTypes::Square,
+            time_control: TimeControl::Unlimited,
+            format,
+            rated: false,
+            status: TournamentStatus::Running,
+            players,
+            rounds: Vec::new(),
+            current_round: 0,
+        }
+    }
+
+    /// Every pair of players who met, in the order given, and who sat out
+    fn meetings(rounds: &[Round]) -> (Vec<(ObjectId, ObjectId)>, Vec<ObjectId>) {
+        let (mut met, mut byes) = (Vec::new(), Vec::new());
+        for pairing in rounds.iter().flat_map(|round| &round.pairings) {
+            let white = pairing.white.id.unwrap();
+            match &pairing.black {
+                Some(black) => {
+                    let black = black.id.unwrap();
+                    met.push((white.min(black), white.max(black)));
+                }
+                None => byes.push(white),
+            }
+        }
+        (met, byes)
+    }
+
+    #[test]
+    fn round_robins_pair_everyone_once() {
+        for count in 2..=9 {
+            let players = players(count);
+            let rounds = round_robin(&players);
+            assert_eq!(rounds.len(), count + count % 2 - 1);
+            for round in &rounds {
+                // Everyone plays or sits out exactly once a round
+                let mut seen: Vec<ObjectId> = round
+                    .pairings
+                    .iter()
+                    .flat_map(|pairing| [Some(&pairing.white), pairing.black.as_ref()])
+                    .flatten()
+                    .map(|player| player.id.unwrap())
+                    .collect();
+                seen.sort();
+                seen.dedup();
+                assert_eq!(seen.len(), count);
+            }
+            let (mut met, byes) = meetings(&rounds);
+            met.sort();
+            let pairs = met.len();
+            met.dedup();
+            assert_eq!(met.len(), pairs, "a rematch with {count} players");
+            assert_eq!(pairs, count * (count - 1) / 2);
+            // With an odd number everyone sits out once
+            assert_eq!(byes.len(), count % 2 * count);
+            let mut unique = byes.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), byes.len());
+        }
+    }
+
+    /// Plays a Swiss tournament where white always wins, checking nobody meets twice
+    fn swiss_without_rematches(count: usize, rounds: u32) {
+        let mut tournament = tournament(players(count), TournamentFormat::Swiss { rounds });
+        for _ in 0..rounds {
+            let mut round = swiss_round(&tournament);
+            for pairing in &mut round.pairings {
+                if pairing.black.is_some() {
+                    pairing.finished = true;
+                    pairing.winner = Some(Color::White);
+                }
+            }
+            tournament.rounds.push(round);
+        }
+        let (mut met, byes) = meetings(&tournament.rounds);
+        met.sort();
+        let pairs = met.len();
+        met.dedup();
+        assert_eq!(met.len(), pairs, "a rematch with {count} players");
+        let mut unique = byes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(
+            unique.len(),
+            byes.len(),
+            "a second bye with {count} players"
+        );
+    }
+
+    #[test]
+    fn swiss_avoids_rematches() {
+        for count in 4..=10 {
+            swiss_without_rematches(count, 3);
+        }
+        swiss_without_rematches(6, 5);
+        swiss_without_rematches(8, 5);
+    }
+}
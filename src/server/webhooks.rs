@@ -0,0 +1,429 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use isahc::{HttpClient, http::Uri};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+
+use super::{
+    auth::new_secret,
+    notifications::{http_client, pinned_client, post_with_retries},
+    prelude::*,
+    storage::PlayerStore,
+};
+use crate::common::account::{MAX_WEBHOOKS, Webhook, WebhookDelivery, WebhookInfo};
+
+/// Deliveries kept for each webhook to show on the account page
+const LOG_LENGTH: usize = 20;
+const MAX_URL: usize = 2048;
+
+/// What a webhook request is about, also sent in the `X-Duck-Chess-Event` header
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    GameStarted,
+    Turn,
+    Chat,
+    GameEnded,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::GameStarted => "game_started",
+            HookEvent::Turn => "turn",
+            HookEvent::Chat => "chat",
+            HookEvent::GameEnded => "game_ended",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChatLine {
+    pub author: String,
+    pub text: String,
+}
+
+/// The body of every webhook request
+#[derive(Clone, Debug, Serialize)]
+pub struct GameEvent {
+    pub event: HookEvent,
+    /// Hex id of the game
+    pub game: String,
+    pub white: String,
+    pub black: String,
+    /// The turn that was just played, in coordinate notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_move: Option<String>,
+    /// `1-0` or `0-1` once the game is over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<ChatLine>,
+    pub time: u64,
+}
+
+impl GameEvent {
+    pub fn new(event: HookEvent, game_id: ObjectId, game: &Game) -> Self {
+        let (white, black) = if game.maker_color == Color::White {
+            (&game.maker, &game.joiner)
+        } else {
+            (&game.joiner, &game.maker)
+        };
+        Self {
+            event,
+            game: game_id.to_hex(),
+            white: white.name.clone(),
+            black: black.name.clone(),
+            last_move: None,
+            result: game.winner.map(|winner| match winner {
+                Color::White => "1-0".to_string(),
+                Color::Black => "0-1".to_string(),
+            }),
+            message: None,
+            time: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        }
+    }
+}
+
+struct Job {
+    /// Whose webhooks get the event
+    players: Vec<ObjectId>,
+    event: GameEvent,
+}
+
+type Log = Arc<Mutex<HashMap<String, VecDeque<WebhookDelivery>>>>;
+
+/// POSTs game events to the webhooks players set up, from a background task like notifications
+#[derive(Clone)]
+pub struct Webhooks {
+    queue: mpsc::UnboundedSender<Job>,
+    log: Log,
+    allow_private: bool,
+}
+
+impl Webhooks {
+    pub fn start(players: Arc<dyn PlayerStore>, allow_private: bool) -> Result<Self> {
+        let client = http_client()?;
+        let log = Log::default();
+        let (queue, mut received) = mpsc::unbounded_channel::<Job>();
+        let worker_log = log.clone();
+        tokio::spawn(async move {
+            while let Some(job) = received.recv().await {
+                tokio::spawn(deliver(
+                    job,
+                    client.clone(),
+                    players.clone(),
+                    worker_log.clone(),
+                    allow_private,
+                ));
+            }
+        });
+        Ok(Self {
+            queue,
+            log,
+            allow_private,
+        })
+    }
+}
+
+/// Queues the event for both players' webhooks
+pub fn send_webhooks(game: &Game, event: GameEvent, webhooks: &Webhooks) {
+    let mut players: Vec<ObjectId> = [game.maker.id, game.joiner.id]
+        .into_iter()
+        .flatten()
+        .collect();
+    players.dedup();
+    if webhooks.queue.send(Job { players, event }).is_err() {
+        log::error!("webhook queue is closed");
+    }
+}
+
+async fn deliver(
+    job: Job,
+    client: HttpClient,
+    players: Arc<dyn PlayerStore>,
+    log: Log,
+    allow_private: bool,
+) {
+    let body = match serde_json::to_vec(&job.event) {
+        Ok(body) => body,
+        Err(error) => {
+            log::error!("serializing webhook event failed: {error:?}");
+            return;
+        }
+    };
+    let mut hooks = Vec::new();
+    for player in job.players {
+        match players.get(player).await {
+            Ok(stored) => hooks.extend(stored.into_iter().flat_map(|stored| stored.webhooks)),
+            Err(error) => log::error!("looking up webhooks failed: {error:?}"),
+        }
+    }
+    // Each webhook retries on its own schedule, so a slow one doesn't hold up the rest
+    join_all(hooks.iter().map(|hook| async {
+        let time = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        let checked = check_url(&hook.url, allow_private).await.and_then(|pin| {
+            // The name could point somewhere else by the time the request goes out, so it goes
+            // to the address that was checked
+            pin.map_or_else(
+                || Ok(client.clone()),
+                |pin| pinned_client(&pin.host, pin.port, pin.ip),
+            )
+        });
+        let (attempts, result) = match checked {
+            Ok(client) => {
+                let headers = [
+                    ("x-duck-chess-event", job.event.event.name().to_string()),
+                    ("x-duck-chess-timestamp", time.to_string()),
+                    (
+                        "x-duck-chess-signature",
+                        format!("sha256={}", sign(&hook.secret, time, &body)),
+                    ),
+                ];
+                post_with_retries(&client, &hook.url, &body, &headers).await
+            }
+            Err(error) => (0, Err(error)),
+        };
+        let mut log = log.lock().unwrap();
+        let deliveries = log.entry(hook.id.clone()).or_default();
+        deliveries.push_front(WebhookDelivery {
+            time,
+            event: job.event.event.name().to_string(),
+            game: job.event.game.clone(),
+            attempts,
+            error: result.err().map(|error| error.to_string()),
+        });
+        deliveries.truncate(LOG_LENGTH);
+    }))
+    .await;
+}
+
+/// Hex HMAC-SHA256 of the timestamp and body, so a receiver can tell the request came from us
+/// and isn't an old one sent again
+fn sign(secret: &str, time: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key");
+    mac.update(format!("{time}.").as_bytes());
+    mac.update(body);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // This network, 0.0.0.0/8, which some systems route to themselves
+                || ip.octets()[0] == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_multicast()
+                    // NAT64, 64:ff9b::/96, which can reach any IPv4 address
+                    || ip.segments()[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+            }
+        },
+    }
+}
+
+/// Where a webhook's host resolved to when it was checked
+struct Pin {
+    host: String,
+    port: u16,
+    ip: IpAddr,
+}
+
+/// Only http and https, and unless the server allows it nothing that resolves to its own
+/// network, so webhooks can't be used to reach services that trust the server. Returns the
+/// address that passed, which requests have to stick to.
+async fn check_url(url: &str, allow_private: bool) -> Result<Option<Pin>> {
+    let uri: Uri = url.parse().map_err(|_| anyhow!("That isn't a URL"))?;
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        Some("http") => 80,
+        _ => bail!("Webhooks have to be http or https URLs"),
+    };
+    let host = uri
+        .host()
+        .ok_or_else(|| anyhow!("Webhooks need a host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if allow_private {
+        return Ok(None);
+    }
+    let port = uri.port_u16().unwrap_or(default_port);
+    let addresses: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| anyhow!("Couldn't find {host}"))?
+        .collect();
+    if addresses.iter().any(|address| is_private(address.ip())) {
+        bail!("Webhooks can't point at private network addresses")
+    }
+    let address = addresses
+        .first()
+        .ok_or_else(|| anyhow!("Couldn't find {host}"))?;
+    Ok(Some(Pin {
+        host: host.to_string(),
+        port,
+        ip: address.ip(),
+    }))
+}
+
+async fn stored_player(player: &Player, players: &dyn PlayerStore) -> Result<PasswordPlayer> {
+    let id = player.id.ok_or_else(|| anyhow!("Player has no id"))?;
+    players
+        .get(id)
+        .await?
+        .ok_or_else(|| anyhow!("No account for player"))
+}
+
+pub async fn list_webhooks(
+    player: &Player,
+    players: &dyn PlayerStore,
+    webhooks: &Webhooks,
+) -> Result<Vec<WebhookInfo>> {
+    let log = webhooks.log.lock().unwrap().clone();
+    Ok(stored_player(player, players)
+        .await?
+        .webhooks
+        .into_iter()
+        .map(|hook| WebhookInfo {
+            deliveries: log
+                .get(&hook.id)
+                .map(|deliveries| deliveries.iter().cloned().collect())
+                .unwrap_or_default(),
+            id: hook.id,
+            url: hook.url,
+            secret: hook.secret,
+            created: hook.created,
+        })
+        .collect())
+}
+
+pub async fn add_webhook(
+    player: &Player,
+    url: String,
+    players: &dyn PlayerStore,
+    webhooks: &Webhooks,
+) -> Result<()> {
+    let url = url.trim().to_string();
+    if url.len() > MAX_URL {
+        bail!("That URL is too long")
+    }
+    check_url(&url, webhooks.allow_private).await?;
+    let mut stored = stored_player(player, players).await?;
+    if stored.webhooks.len() >= MAX_WEBHOOKS {
+        bail!("You can have at most {MAX_WEBHOOKS} webhooks")
+    }
+    stored.webhooks.push(Webhook {
+        id: ObjectId::new().to_hex(),
+        url,
+        secret: new_secret(),
+        created: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+    });
+    players.replace(stored).await
+}
+
+pub async fn remove_webhook(
+    player: &Player,
+    webhook: &str,
+    players: &dyn PlayerStore,
+    webhooks: &Webhooks,
+) -> Result<()> {
+    let mut stored = stored_player(player, players).await?;
+    let before = stored.webhooks.len();
+    stored.webhooks.retain(|hook| hook.id != webhook);
+    if stored.webhooks.len() == before {
+        bail!("No webhook with that id")
+    }
+    players.replace(stored).await?;
+    webhooks.log.lock().unwrap().remove(webhook);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "0.1.2.3",
+            "100.64.0.1",
+            "169.254.169.254",
+            "224.0.0.1",
+            "::1",
+            "fd00::1",
+            "ff02::1",
+            "::ffff:192.168.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(is_private(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.215.14", "100.128.0.1", "2606:4700::1111"] {
+            assert!(!is_private(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn pinned_requests_skip_dns() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .await;
+        });
+        // The name doesn't exist, so this only works if it goes where it was pinned
+        let client = pinned_client("hooks.invalid", port, [127, 0, 0, 1].into()).unwrap();
+        let url = format!("http://hooks.invalid:{port}/hook");
+        let (attempts, result) = post_with_retries(&client, &url, b"{}", &[]).await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn checked_urls_pin_the_address() {
+        assert!(
+            check_url("http://localhost:8080/hook", false)
+                .await
+                .is_err()
+        );
+        assert!(check_url("ftp://duck.example/hook", false).await.is_err());
+        let pin = check_url("https://93.184.215.14/hook", false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pin.port, 443);
+        assert_eq!(pin.ip, "93.184.215.14".parse::<IpAddr>().unwrap());
+        assert!(
+            check_url("http://localhost:8080/hook", true)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::prelude::*;
+
+#[component]
+pub fn SpectatorCount(id: String) -> Element {
+    let count = use_signal(|| None);
+    use_sse(count, move || crate::rpc::spectator_count(id));
+
+    match count() {
+        None | Some(0) => rsx! {},
+        Some(1) => rsx! { span { class: "spectators", "1 person watching" } },
+        Some(count) => rsx! { span { class: "spectators", "{count} people watching" } },
+    }
+}
+
+#[component]
+pub fn VisibilitySelect(value: Visibility, onchange: EventHandler<Visibility>) -> Element {
+    rsx! {
+        label {
+            "Who can watch: "
+            select {
+                onchange: move |evt| {
+                    if let Ok(i) = evt.value().parse::<usize>() {
+                        onchange(Visibility::all()[i]);
+                    }
+                },
+                for (i, option) in Visibility::all().into_iter().enumerate() {
+                    option {
+                        value: "{i}",
+                        selected: option == value,
+                        "{option.label()}"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lets a player change who can watch a game that already exists.
+#[component]
+pub fn GameVisibility(id: String, value: Visibility) -> Element {
+    rsx! {
+        VisibilitySelect {
+            value,
+            onchange: move |visibility| {
+                let id = id.clone();
+                spawn(async move {
+                    if let Err(error) = crate::rpc::set_visibility_rpc(id, visibility).await {
+                        log::error!("Unable to change visibility: {error:?}");
+                    }
+                });
+            },
+        }
+    }
+}
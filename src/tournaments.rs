@@ -0,0 +1,294 @@
+use game::GameTypes;
+use tournament::{Pairing, Tournament, TournamentFormat, TournamentStatus};
+
+use crate::{newgame::TimeControlSelect, prelude::*, route::Route};
+
+#[component]
+pub fn Tournaments() -> Element {
+    let tournaments =
+        use_resource(|| async { crate::rpc::fetch_tournaments().await.unwrap_or_default() });
+
+    rsx! {
+        div {
+            class: "profile",
+            div {
+                class: "header",
+                h1 { "Tournaments" }
+                div {
+                    class: "buttonMenu",
+                    Link { to: Route::MainMenu {}, "Back" }
+                }
+            }
+            NewTournament {}
+            h2 { "All tournaments" }
+            table {
+                for tournament in tournaments.value()().unwrap_or_default() {
+                    tr {
+                        td {
+                            Link {
+                                to: Route::TournamentPage { id: tournament.id.unwrap().to_string() },
+                                "{tournament.name}"
+                            }
+                        }
+                        td { "{tournament.game_type:?}" }
+                        td { "{tournament.format.label()}" }
+                        td { "{tournament.players.len()} players" }
+                        td { {status_text(&tournament)} }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn NewTournament() -> Element {
+    let mut name = use_signal(String::new);
+    let mut game_type = use_signal(|| GameTypes::Square);
+    let mut time_control = use_signal(TimeControl::default);
+    let mut swiss = use_signal(|| false);
+    let mut rounds = use_signal(|| 5u32);
+    let mut rated = use_signal(|| true);
+    let mut error = use_signal(|| None::<String>);
+
+    rsx! {
+        div {
+            class: "newGame",
+            h2 { "Run a tournament" }
+            label {
+                "Name: "
+                input {
+                    value: "{name}",
+                    oninput: move |evt| name.set(evt.value()),
+                }
+            }
+            label {
+                "Variant: "
+                select {
+                    onchange: move |evt| {
+                        if let Ok(i) = evt.value().parse::<usize>() {
+                            game_type.set(GameTypes::all()[i]);
+                        }
+                    },
+                    for (i, option) in GameTypes::all().into_iter().enumerate() {
+                        option {
+                            value: "{i}",
+                            selected: option == game_type(),
+                            "{option:?}"
+                        }
+                    }
+                }
+            }
+            TimeControlSelect {
+                value: time_control(),
+                onchange: move |value| time_control.set(value),
+            }
+            label {
+                "Format: "
+                select {
+                    onchange: move |evt| swiss.set(evt.value() == "swiss"),
+                    option { value: "roundRobin", selected: !swiss(), "Round robin" }
+                    option { value: "swiss", selected: swiss(), "Swiss" }
+                }
+            }
+            if swiss() {
+                label {
+                    "Rounds: "
+                    input {
+                        "type": "number",
+                        min: "1",
+                        value: "{rounds}",
+                        onchange: move |evt| {
+                            if let Ok(value) = evt.value().parse() {
+                                rounds.set(value);
+                            }
+                        },
+                    }
+                }
+            }
+            label {
+                input {
+                    "type": "checkbox",
+                    checked: rated(),
+                    onchange: move |evt| rated.set(evt.checked()),
+                }
+                "Rated"
+            }
+            button {
+                onclick: move |_| async move {
+                    let format = if swiss() {
+                        TournamentFormat::Swiss { rounds: rounds() }
+                    } else {
+                        TournamentFormat::RoundRobin
+                    };
+                    match crate::rpc::create_tournament(name(), game_type(), time_control(), format, rated()).await {
+                        Ok(id) => {
+                            navigator().push(Route::TournamentPage { id: id.to_string() });
+                        }
+                        Err(err) => error.set(Some(err.to_string())),
+                    }
+                },
+                "Create tournament"
+            }
+            if let Some(error) = error() {
+                div { class: "conflict", "{error}" }
+            }
+        }
+    }
+}
+
+#[component]
+pub fn TournamentPage(id: String) -> Element {
+    let player: Player = use_context();
+    let mut error = use_signal(|| None::<String>);
+    let mut resource = use_resource({
+        let id = id.clone();
+        move || crate::rpc::fetch_tournament(id.clone())
+    });
+
+    let tournament = match resource.value()() {
+        Some(Ok(tournament)) => tournament,
+        Some(Err(_)) => return rsx! { div { class: "profile", "No such tournament" } },
+        None => return rsx! {},
+    };
+    let registered = tournament.players.contains(&player);
+    let registering = tournament.status == TournamentStatus::Registering;
+    let organizing = tournament.organizer == player;
+    let swiss = matches!(tournament.format, TournamentFormat::Swiss { .. });
+
+    let register = {
+        let id = id.clone();
+        move |_| {
+            let id = id.clone();
+            async move {
+                match crate::rpc::set_registered(id, !registered).await {
+                    Ok(()) => resource.restart(),
+                    Err(err) => error.set(Some(err.to_string())),
+                }
+            }
+        }
+    };
+    let start = {
+        let id = id.clone();
+        move |_| {
+            let id = id.clone();
+            async move {
+                match crate::rpc::start_tournament(id).await {
+                    Ok(()) => resource.restart(),
+                    Err(err) => error.set(Some(err.to_string())),
+                }
+            }
+        }
+    };
+
+    rsx! {
+        div {
+            class: "profile",
+            div {
+                class: "header",
+                div {
+                    h1 { "{tournament.name}" }
+                    div {
+                        "{tournament.game_type:?}, {tournament.format.label()}, {tournament.time_control.label()}"
+                        if tournament.rated { ", rated" }
+                    }
+                    div { {status_text(&tournament)} }
+                }
+                div {
+                    class: "buttonMenu",
+                    Link { to: Route::Tournaments {}, "All tournaments" }
+                    if registering {
+                        button {
+                            onclick: register,
+                            if registered { "Withdraw" } else { "Register" }
+                        }
+                    }
+                    if registering && organizing {
+                        button { onclick: start, "Start" }
+                    }
+                }
+            }
+            if let Some(error) = error() {
+                div { class: "conflict", "{error}" }
+            }
+            h2 { "Standings" }
+            table {
+                tr {
+                    th { "#" }
+                    th { "Player" }
+                    th { "Points" }
+                    if swiss {
+                        th { "Buchholz" }
+                    }
+                    th { "Sonneborn-Berger" }
+                }
+                for (i, standing) in tournament.standings().into_iter().enumerate() {
+                    tr {
+                        td { "{i + 1}" }
+                        td {
+                            Link {
+                                to: Route::Profile { name: standing.player.name.clone() },
+                                "{standing.player.name}"
+                            }
+                        }
+                        td { "{standing.score}" }
+                        if swiss {
+                            td { "{standing.buchholz}" }
+                        }
+                        td { "{standing.sonneborn_berger}" }
+                    }
+                }
+            }
+            for (i, round) in tournament.rounds.iter().enumerate() {
+                h2 { "Round {i + 1}" }
+                table {
+                    for pairing in round.pairings.iter() {
+                        {pairing_row(pairing)}
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pairing_row(pairing: &Pairing) -> Element {
+    let Some(black) = &pairing.black else {
+        return rsx! {
+            tr {
+                td { "{pairing.white.name}" }
+                td { "bye" }
+            }
+        };
+    };
+    let result = match (pairing.finished, pairing.winner) {
+        (false, _) => "vs",
+        (true, Some(Color::White)) => "1 - 0",
+        (true, Some(Color::Black)) => "0 - 1",
+        (true, None) => "½ - ½",
+    };
+    rsx! {
+        tr {
+            td { "{pairing.white.name}" }
+            td {
+                if let Some(game) = pairing.game {
+                    Link { to: Route::InGame { id: game.to_string() }, "{result}" }
+                } else {
+                    "{result}"
+                }
+            }
+            td { "{black.name}" }
+        }
+    }
+}
+
+fn status_text(tournament: &Tournament) -> String {
+    match tournament.status {
+        TournamentStatus::Registering => "Open for registration".into(),
+        TournamentStatus::Running => format!(
+            "Round {} of {}",
+            tournament.current_round + 1,
+            tournament.total_rounds()
+        ),
+        TournamentStatus::Finished => "Finished".into(),
+    }
+}
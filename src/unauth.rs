@@ -1,4 +1,10 @@
-use crate::{loginbuttons::login_buttons, prelude::*};
+use web_sys::window;
+
+use crate::{
+    email::{ForgotPassword, ResetPassword},
+    loginbuttons::login_buttons,
+    prelude::*,
+};
 
 #[component]
 pub fn unauth<T: 'static + Eq + Clone>(session: Resource<T>) -> Element {
@@ -8,9 +14,36 @@ pub fn unauth<T: 'static + Eq + Clone>(session: Resource<T>) -> Element {
             id: None,
             name: "me".into(),
         },
+        two_factor: None,
+        passkeys: Vec::new(),
+        email: None,
+        notifications: None,
+        webhooks: Vec::new(),
+        api_tokens: Vec::new(),
+        version: 0,
     });
 
-    let errors = use_signal(|| "");
+    let errors = use_signal(String::new);
+    // Only asked for once the password turns out to need a second factor
+    let mut code = use_signal(|| None::<String>);
+    // Links from password reset emails land here when logged out. Read after the first render so
+    // it matches what the server rendered.
+    let mut reset_token = use_signal(|| None::<String>);
+    use_effect(move || {
+        let path = window().and_then(|window| window.location().pathname().ok());
+        if let Some(token) = path
+            .as_deref()
+            .and_then(|path| path.strip_prefix("/ui/reset/"))
+        {
+            reset_token.set(Some(token.to_string()));
+        }
+    });
+
+    if let Some(token) = reset_token() {
+        return rsx! {
+            ResetPassword { token }
+        };
+    }
 
     rsx! {
         div {
@@ -47,9 +80,27 @@ pub fn unauth<T: 'static + Eq + Clone>(session: Resource<T>) -> Element {
                     oninput: move |evt| player.write().password = evt.value().clone(),
                 }
             }
+            if code().is_some() {
+                div {
+                    label {
+                        "for": "code",
+                        b {
+                            "Code"
+                        }
+                    }
+                    input {
+                        "type": "text",
+                        placeholder: "From your authenticator app, or a recovery code",
+                        name: "code",
+                        autocomplete: "one-time-code",
+                        oninput: move |evt| code.set(Some(evt.value())),
+                    }
+                }
+            }
             div {
-                login_buttons { session, errors, player }
+                login_buttons { session, errors, player, code }
             }
+            ForgotPassword { name: player().name.clone() }
         }
     }
 }